@@ -0,0 +1,87 @@
+//! Parse time for generated configs of growing size
+//!
+//! Run with `cargo bench`. Parsing must take linear time, so the time per
+//! byte of the largest config may not be far above that of the smallest;
+//! the benchmark fails if it is.
+
+use std::time::{Duration, Instant};
+
+/// Sizes of the generated configs, in bytes
+const SIZES: [usize; 3] = [1 << 20, 2 << 20, 5 << 20];
+
+/// How much slower per byte the largest config may parse than the smallest
+const MAX_SLOWDOWN: f64 = 3.0;
+
+/// Parses its input, returning whether it succeeded
+type ParseFn = fn(&str) -> bool;
+
+/// A config of about `size` bytes, using every kind of value
+fn generate(size: usize) -> String {
+    // Strings end at an inline comment, so every service has a few: a parser
+    // that searches ahead for one from each string is quadratic here
+    let mut out = String::from("{ $region eu-west-1  // default region\n, $replicas 3\n}\n{ :services [\n");
+    let mut i = 0;
+    while out.len() < size {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        out.push_str(&format!(
+            "{{ :name service-{i}\n\
+             , :image registry.example.com/team/service-{i}:v1.{i}.0\n\
+             , :description A long description with plenty of words in it for service {i}  // shown in the UI\n\
+             , :region $region\n\
+             , :endpoint $region.example.com/service-{i} // public\n\
+             , :replicas $replicas\n\
+             , :port 0x1F90\n\
+             , :ratio 0.75\n\
+             , :enabled true\n\
+             , :owner null\n\
+             , :kind :deployment\n\
+             , :tags [web\n, api\n, v{i}\n]\n\
+             , :notes\n    first line of notes  // kept short\n    second line of notes\n\
+             }}\n"
+        ));
+        i += 1;
+    }
+    out.push_str("]\n}\n");
+    out
+}
+
+/// The fastest of a few parses of `input`
+fn time(input: &str, parse: ParseFn) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            assert!(parse(input));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let parsers: [(&str, ParseFn); 3] = [
+        ("parse", |input| sym_parser::parse(input).is_ok()),
+        ("borrowed", |input| sym_parser::parse_borrowed(input).is_ok()),
+        ("parse_cst", |input| sym_parser::parse_cst(input).is_ok()),
+    ];
+    let inputs: Vec<String> = SIZES.iter().map(|&size| generate(size)).collect();
+    let mut failed = false;
+    for (name, parse) in parsers {
+        let mut per_byte = Vec::new();
+        for input in &inputs {
+            let elapsed = time(input, parse);
+            let mb = input.len() as f64 / (1 << 20) as f64;
+            println!("{:<10} {:>5.1} MB  {:>9.2?}  {:>6.1} MB/s", name, mb, elapsed, mb / elapsed.as_secs_f64());
+            per_byte.push(elapsed.as_secs_f64() / input.len() as f64);
+        }
+        let slowdown = per_byte[per_byte.len() - 1] / per_byte[0];
+        if slowdown > MAX_SLOWDOWN {
+            println!("{}: {:.1}x slower per byte on the largest config", name, slowdown);
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
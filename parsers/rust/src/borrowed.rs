@@ -0,0 +1,128 @@
+//! Values that borrow their strings from the source
+//!
+//! [`parse_borrowed`](crate::parse_borrowed) returns a [`BorrowedValue`] whose
+//! strings, symbols and keys point into the input wherever they were written
+//! verbatim. Only strings built by the parser, from escapes, lines joined
+//! into one value or substituted variables, are allocated.
+//!
+//! ```
+//! use std::borrow::Cow;
+//! use sym_parser::BorrowedValue;
+//!
+//! let input = "{ $env prod }\n{ :name web-server\n, :host $env.example.com\n}";
+//! let value = sym_parser::parse_borrowed(input).unwrap();
+//! assert!(matches!(value.get("name"), Some(BorrowedValue::String(Cow::Borrowed("web-server")))));
+//! assert!(matches!(value.get("host"), Some(BorrowedValue::String(Cow::Owned(_)))));
+//!
+//! // Keep the value after the input is gone
+//! let value: sym_parser::Value = value.into_owned();
+//! ```
+
+use crate::Value;
+use indexmap::IndexMap;
+use std::borrow::Cow;
+
+/// A SYM value whose strings may borrow from the source
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedValue<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(Cow<'a, str>),
+    Symbol(Cow<'a, str>),
+    Array(Vec<BorrowedValue<'a>>),
+    Object(IndexMap<Cow<'a, str>, BorrowedValue<'a>>),
+}
+
+impl<'a> BorrowedValue<'a> {
+    /// Copy the borrowed strings into a [`Value`]
+    pub fn into_owned(self) -> Value {
+        match self {
+            BorrowedValue::Null => Value::Null,
+            BorrowedValue::Bool(b) => Value::Bool(b),
+            BorrowedValue::Int(i) => Value::Int(i),
+            BorrowedValue::Float(f) => Value::Float(f),
+            BorrowedValue::String(s) => Value::String(s.into_owned()),
+            BorrowedValue::Symbol(s) => Value::Symbol(s.into_owned()),
+            BorrowedValue::Array(items) => {
+                Value::Array(items.into_iter().map(Self::into_owned).collect())
+            }
+            BorrowedValue::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Deep merge another value into this one, like [`Value::deep_merge`]
+    pub(crate) fn deep_merge(&mut self, other: BorrowedValue<'a>) {
+        match (self, other) {
+            (BorrowedValue::Object(base), BorrowedValue::Object(overlay)) => {
+                for (key, value) in overlay {
+                    if let Some(base_value) = base.get_mut(&*key) {
+                        base_value.deep_merge(value);
+                    } else {
+                        base.insert(key, value);
+                    }
+                }
+            }
+            (BorrowedValue::Array(base), BorrowedValue::Array(overlay)) => {
+                base.extend(overlay);
+            }
+            (this, other) => *this = other,
+        }
+    }
+
+    /// Get a field of an object
+    pub fn get(&self, key: &str) -> Option<&BorrowedValue<'a>> {
+        match self {
+            BorrowedValue::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Try to get as string slice
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            BorrowedValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Try to get as symbol name
+    pub fn as_symbol(&self) -> Option<&str> {
+        match self {
+            BorrowedValue::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Try to get as array slice
+    pub fn as_array(&self) -> Option<&[BorrowedValue<'a>]> {
+        match self {
+            BorrowedValue::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl From<Value> for BorrowedValue<'_> {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => BorrowedValue::Null,
+            Value::Bool(b) => BorrowedValue::Bool(b),
+            Value::Int(i) => BorrowedValue::Int(i),
+            Value::Float(f) => BorrowedValue::Float(f),
+            Value::String(s) => BorrowedValue::String(Cow::Owned(s)),
+            Value::Symbol(s) => BorrowedValue::Symbol(Cow::Owned(s)),
+            Value::Array(items) => BorrowedValue::Array(items.into_iter().map(Self::from).collect()),
+            Value::Object(map) => BorrowedValue::Object(
+                map.into_iter()
+                    .map(|(key, value)| (Cow::Owned(key), Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
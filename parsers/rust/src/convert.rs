@@ -161,13 +161,13 @@ pub fn to_sym_string(value: &Value, indent: usize) -> String {
 }
 
 /// Escape a string for SYM output
-fn escape_sym_string(s: &str) -> String {
+pub(crate) fn escape_sym_string(s: &str) -> String {
     // Check if the string needs escaping at the start
     let needs_escape = s.starts_with(':')
-        || s.starts_with('$')
         || s.starts_with('{')
         || s.starts_with('[')
-        || s.starts_with('\\')
+        || s.starts_with(',')
+        || s.starts_with('/')
         || s == "true"
         || s == "false"
         || s == "null"
@@ -177,18 +177,41 @@ fn escape_sym_string(s: &str) -> String {
         || looks_like_number(s);
     
     if needs_escape {
-        format!("\\{}", s)
+        format!("\\{}", escape_inline(s))
     } else if s.is_empty() {
         // Empty string - just leave it empty (will be empty value)
         String::new()
-    } else if s.contains('\n') {
-        // Multiline string - indent each line
-        s.to_string()
     } else {
-        s.to_string()
+        escape_inline(s)
     }
 }
 
+/// Escape backslashes, closing brackets, `//` after whitespace, and `$`
+/// where it would start a variable or environment reference
+pub(crate) fn escape_inline(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut prev = None;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '}' | ']' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '/' if chars.peek() == Some(&'/') && matches!(prev, Some(' ' | '\t')) => {
+                result.push_str("\\/")
+            }
+            '$' if chars.peek().is_some_and(|n| n.is_alphabetic() || *n == '_' || *n == '{') => {
+                result.push_str("\\$")
+            }
+            c => result.push(c),
+        }
+        prev = Some(c);
+    }
+    result
+}
+
 /// Check if a string looks like a number
 fn looks_like_number(s: &str) -> bool {
     if s.is_empty() {
@@ -285,10 +308,31 @@ mod tests {
         assert!(value.is_object());
     }
 
+    #[test]
+    fn test_key_order() {
+        let keys = |value: &Value| value.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        let expected = ["name", "version", "dependencies", "author"];
+        let json = r#"{"name": "app", "version": "1.0.0", "dependencies": {"zod": "3", "axios": "1"}, "author": "A"}"#;
+        let value = parse_json(json).unwrap();
+        assert_eq!(keys(&value), expected);
+        assert_eq!(keys(&value.as_object().unwrap()["dependencies"]), ["zod", "axios"]);
+        assert_eq!(crate::parse(&to_sym_string(&value, 0)).unwrap(), value);
+
+        let yaml = "name: app\nversion: 1.0.0\ndependencies: {}\nauthor: A";
+        assert_eq!(keys(&parse_yaml(yaml).unwrap()), expected);
+        let toml = "name = \"app\"\nversion = \"1.0.0\"\nauthor = \"A\"\n\n[dependencies]\n";
+        assert_eq!(keys(&parse_toml(toml).unwrap()), ["name", "version", "author", "dependencies"]);
+    }
+
     #[test]
     fn test_escape_number_string() {
         assert_eq!(escape_sym_string("42"), "\\42");
         assert_eq!(escape_sym_string("hello"), "hello");
         assert_eq!(escape_sym_string(":foo"), "\\:foo");
+        assert_eq!(escape_sym_string("$price"), "\\$price");
+        assert_eq!(escape_sym_string("costs $5"), "costs $5");
+        assert_eq!(escape_sym_string("C:\\dir"), "C:\\\\dir");
+        assert_eq!(escape_sym_string("a } b // c"), "a \\} b \\// c");
+        assert_eq!(escape_sym_string(",x"), "\\,x");
     }
 }
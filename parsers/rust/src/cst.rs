@@ -0,0 +1,723 @@
+//! Lossless concrete syntax tree for SYM source
+//!
+//! Unlike [`parse`](crate::parse), which evaluates a document into a
+//! [`Value`](crate::Value), the CST keeps every byte of the input: whitespace,
+//! comments, separators, the original spelling of numbers like `0xff` or
+//! `1_000`, escapes and variable references. Printing a tree with
+//! `to_string()` reproduces the source exactly.
+//!
+//! ```
+//! let source = "// settings\n{ :port 0x1F90  // hex\n, :name \\$HOME\n}\n";
+//! let tree = sym_parser::parse_cst(source).unwrap();
+//! assert_eq!(tree.to_string(), source);
+//! ```
+
+use crate::diagnostic::ErrorCode;
+use crate::lexer::{
+    block_comment_len, env_ref, horizontal_whitespace_len, line_comment_len, line_len, name_len, parse_literal,
+    plain_text_len, starts_var_ref, value_follows, var_ref_len,
+};
+use crate::options::{Limit, ParseOptions};
+use crate::span::Location;
+use crate::{Result, SymError, Value};
+use std::fmt;
+
+/// The kind of a [`SyntaxNode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    /// A whole file: imports followed by defs and data blocks
+    Document,
+    /// An `@import path` directive
+    Import,
+    /// `{ ... }`
+    Object,
+    /// `[ ... ]`
+    Array,
+    /// A `:key value` or `$name value` entry of an object
+    Field,
+    /// A single-token value: number, symbol, boolean or null
+    Scalar,
+    /// A string value, possibly spanning several lines
+    String,
+}
+
+/// The kind of a [`SyntaxToken`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// Spaces, tabs and other whitespace on a single line
+    Whitespace,
+    /// A line feed
+    Newline,
+    /// `// ...` up to the end of the line
+    LineComment,
+    /// `/* ... */`
+    BlockComment,
+    /// `{`
+    LBrace,
+    /// `}`
+    RBrace,
+    /// `[`
+    LBracket,
+    /// `]`
+    RBracket,
+    /// `,`
+    Comma,
+    /// `@import`
+    Import,
+    /// The path of an `@import`
+    ImportPath,
+    /// `:name` in key position
+    Key,
+    /// `$name` in a definition
+    VarName,
+    /// The `!` or `+` after a key
+    Modifier,
+    /// `:name` in value position
+    Symbol,
+    /// A number as written, including `inf` and `nan`
+    Number,
+    /// `true` or `false`
+    Bool,
+    /// `null`
+    Null,
+    /// Literal text of a string
+    Text,
+    /// A backslash and the character it escapes
+    Escape,
+    /// `$name` inside a value
+    VarRef,
+    /// `${NAME}`, `${NAME:-default}` or `${NAME:?message}`
+    EnvRef,
+}
+
+impl TokenKind {
+    /// Whitespace and comments, which carry no data
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            TokenKind::Whitespace
+                | TokenKind::Newline
+                | TokenKind::LineComment
+                | TokenKind::BlockComment
+        )
+    }
+}
+
+/// A leaf of the tree: a piece of the source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxToken {
+    kind: TokenKind,
+    text: String,
+}
+
+impl SyntaxToken {
+    pub(crate) fn new(kind: TokenKind, text: impl Into<String>) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> TokenKind {
+        self.kind
+    }
+
+    /// The source text of the token
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// An inner node of the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxNode {
+    kind: NodeKind,
+    children: Vec<SyntaxElement>,
+}
+
+/// A child of a [`SyntaxNode`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub(crate) fn new(kind: NodeKind, children: Vec<SyntaxElement>) -> Self {
+        Self { kind, children }
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Child nodes and tokens in source order
+    pub fn children(&self) -> &[SyntaxElement] {
+        &self.children
+    }
+
+    pub(crate) fn children_mut(&mut self) -> &mut Vec<SyntaxElement> {
+        &mut self.children
+    }
+
+    /// Child nodes, skipping tokens
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Every token below this node, in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'n>(&'n self, tokens: &mut Vec<&'n SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    /// The comments below this node, in source order
+    pub fn comments(&self) -> impl Iterator<Item = &SyntaxToken> {
+        self.tokens().into_iter().filter(|token| {
+            matches!(token.kind, TokenKind::LineComment | TokenKind::BlockComment)
+        })
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyntaxElement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntaxElement::Node(node) => write!(f, "{}", node),
+            SyntaxElement::Token(token) => f.write_str(&token.text),
+        }
+    }
+}
+
+/// Parse SYM source into a lossless syntax tree
+///
+/// The tree accepts the same documents as [`parse`](crate::parse) but does not
+/// resolve imports or variables.
+pub fn parse(input: &str) -> Result<SyntaxNode> {
+    parse_with_options(input, &ParseOptions::default())
+}
+
+/// Parse SYM source into a lossless syntax tree with custom options
+///
+/// The options decide which literals are numbers, e.g. whether `0xff` is one,
+/// and bound the input size and nesting depth.
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<SyntaxNode> {
+    CstParser::new(input, options).document()
+}
+
+/// Builds a syntax tree, following the grammar of [`Parser`](crate::parser::Parser)
+struct CstParser<'a> {
+    input: &'a str,
+    options: &'a ParseOptions,
+    pos: usize,
+    line: usize,
+    col: usize,
+    /// Arrays and objects open at the current position
+    depth: usize,
+}
+
+impl<'a> CstParser<'a> {
+    fn new(input: &'a str, options: &'a ParseOptions) -> Self {
+        Self {
+            input,
+            options,
+            pos: 0,
+            line: 1,
+            col: 1,
+            depth: 0,
+        }
+    }
+
+    fn document(&mut self) -> Result<SyntaxNode> {
+        let max = self.options.limits.max_input_size;
+        if self.input.len() > max {
+            return Err(self.limit_error(Limit::InputSize, max));
+        }
+
+        let mut children = Vec::new();
+        self.trivia(&mut children);
+
+        let mut has_content = false;
+        while self.rest().starts_with("@import") {
+            children.push(SyntaxElement::Node(self.import()));
+            has_content = true;
+            self.trivia(&mut children);
+        }
+
+        while !self.at_end() {
+            self.value(&mut children)?;
+            has_content = true;
+            self.trivia(&mut children);
+        }
+
+        if !has_content {
+            return Err(self.error(ErrorCode::EmptyDocument, "Empty document"));
+        }
+        Ok(SyntaxNode::new(NodeKind::Document, children))
+    }
+
+    /// `@import path`, up to the end of the line
+    fn import(&mut self) -> SyntaxNode {
+        let mut children = vec![self.token(TokenKind::Import, "@import".len())];
+        self.horizontal_whitespace(&mut children);
+
+        let rest = self.rest();
+        let path = rest[..line_len(rest)].trim_end();
+        if !path.is_empty() {
+            children.push(self.token(TokenKind::ImportPath, path.len()));
+        }
+        self.horizontal_whitespace(&mut children);
+        SyntaxNode::new(NodeKind::Import, children)
+    }
+
+    /// A value, preceded by any trivia and followed by the trivia a string
+    /// consumed after its last character
+    fn value(&mut self, out: &mut Vec<SyntaxElement>) -> Result<()> {
+        self.trivia(out);
+
+        let node = match self.peek() {
+            None => return Err(self.error(ErrorCode::UnexpectedEnd, "Unexpected end of input")),
+            Some('{') => self.nested(Self::object)?,
+            Some('[') => self.nested(Self::array)?,
+            Some(c @ ('}' | ']')) => return Err(self.error(ErrorCode::UnexpectedClose, &format!("Unexpected '{}'", c))),
+            Some(':') => {
+                let len = 1 + self.identifier_len(1)?;
+                let token = self.token(TokenKind::Symbol, len);
+                SyntaxNode::new(NodeKind::Scalar, vec![token])
+            }
+            Some(_) => match self.literal() {
+                Some(token) => SyntaxNode::new(NodeKind::Scalar, vec![token]),
+                None => return self.string(out),
+            },
+        };
+        out.push(SyntaxElement::Node(node));
+        Ok(())
+    }
+
+    /// Parse an array or object within the nesting limit
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<SyntaxNode>) -> Result<SyntaxNode> {
+        let max = self.options.limits.max_depth;
+        if self.depth >= max {
+            return Err(self.limit_error(Limit::Depth, max));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn object(&mut self) -> Result<SyntaxNode> {
+        let mut children = vec![self.token(TokenKind::LBrace, 1)];
+        self.trivia(&mut children);
+
+        if self.peek() == Some('}') {
+            children.push(self.token(TokenKind::RBrace, 1));
+            return Ok(SyntaxNode::new(NodeKind::Object, children));
+        }
+
+        self.field(&mut children)?;
+        loop {
+            self.trivia(&mut children);
+            if self.peek() == Some('}') {
+                children.push(self.token(TokenKind::RBrace, 1));
+                break;
+            }
+            if self.peek() != Some(',') {
+                if self.at_end() {
+                    return Err(self.error(ErrorCode::UnclosedObject, "Unclosed object"));
+                }
+                return Err(self.error(ErrorCode::MissingSeparator, "Expected ',' separator"));
+            }
+            children.push(self.token(TokenKind::Comma, 1));
+            self.trivia(&mut children);
+            if self.peek() == Some('}') {
+                children.push(self.token(TokenKind::RBrace, 1));
+                break;
+            }
+            self.field(&mut children)?;
+        }
+        Ok(SyntaxNode::new(NodeKind::Object, children))
+    }
+
+    /// A field; trivia after its value goes to the enclosing object
+    fn field(&mut self, out: &mut Vec<SyntaxElement>) -> Result<()> {
+        let (kind, modifiers): (_, &[char]) = match self.peek() {
+            Some(':') => (TokenKind::Key, &['!', '+']),
+            Some('$') => (TokenKind::VarName, &['!']),
+            _ => return Err(self.error(ErrorCode::ExpectedField, "Expected ':' or '$' at start of field")),
+        };
+        let len = 1 + self.identifier_len(1)?;
+        let mut children = vec![self.token(kind, len)];
+        if self.peek().is_some_and(|c| modifiers.contains(&c)) {
+            children.push(self.token(TokenKind::Modifier, 1));
+        }
+        self.horizontal_whitespace(&mut children);
+
+        let mut trailing = Vec::new();
+        if value_follows(self.rest()) {
+            self.value(&mut trailing)?;
+            // Leading trivia and the value belong to the field
+            let end = trailing
+                .iter()
+                .rposition(|child| matches!(child, SyntaxElement::Node(_)))
+                .map_or(0, |i| i + 1);
+            let rest = trailing.split_off(end);
+            children.extend(trailing);
+            trailing = rest;
+        }
+
+        out.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::Field, children)));
+        out.extend(trailing);
+        Ok(())
+    }
+
+    fn array(&mut self) -> Result<SyntaxNode> {
+        let mut children = vec![self.token(TokenKind::LBracket, 1)];
+        self.trivia(&mut children);
+
+        if self.peek() == Some(']') {
+            children.push(self.token(TokenKind::RBracket, 1));
+            return Ok(SyntaxNode::new(NodeKind::Array, children));
+        }
+
+        self.value(&mut children)?;
+        loop {
+            self.trivia(&mut children);
+            if self.peek() == Some(']') {
+                children.push(self.token(TokenKind::RBracket, 1));
+                break;
+            }
+            if self.peek() != Some(',') {
+                if self.at_end() {
+                    return Err(self.error(ErrorCode::UnclosedArray, "Unclosed array"));
+                }
+                return Err(self.error(ErrorCode::MissingSeparator, "Expected ',' separator"));
+            }
+            children.push(self.token(TokenKind::Comma, 1));
+            self.trivia(&mut children);
+            if self.peek() == Some(']') {
+                children.push(self.token(TokenKind::RBracket, 1));
+                break;
+            }
+            self.value(&mut children)?;
+        }
+        Ok(SyntaxNode::new(NodeKind::Array, children))
+    }
+
+    /// A number, boolean or null, if one starts here
+    fn literal(&mut self) -> Option<SyntaxElement> {
+        let (value, len) = parse_literal(self.rest(), self.options)?;
+        let kind = match value {
+            Value::Bool(_) => TokenKind::Bool,
+            Value::Null => TokenKind::Null,
+            _ => TokenKind::Number,
+        };
+        Some(self.token(kind, len))
+    }
+
+    /// A string value, pushing the trivia scanned after its last character too
+    fn string(&mut self, out: &mut Vec<SyntaxElement>) -> Result<()> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let rest = self.rest();
+            match self.peek() {
+                None | Some('}') | Some(']') => break,
+                Some('\n') => {
+                    tokens.push(self.token(TokenKind::Newline, 1));
+                    self.horizontal_whitespace(&mut tokens);
+                    // A comma on the next line separates the value from the next one
+                    if self.peek() == Some(',') {
+                        break;
+                    }
+                    if self.rest().starts_with("//") {
+                        tokens.push(self.line_comment());
+                    } else if self.rest().starts_with("/*") {
+                        tokens.push(self.block_comment());
+                    }
+                }
+                Some('\r') => tokens.push(self.token(TokenKind::Whitespace, 1)),
+                Some('\\') => {
+                    let len = 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+                    tokens.push(self.token(TokenKind::Escape, len));
+                }
+                Some(' ') | Some('\t') => {
+                    self.horizontal_whitespace(&mut tokens);
+                    // Whitespace followed by `//` starts an inline comment
+                    if self.rest().starts_with("//") {
+                        tokens.push(self.line_comment());
+                    }
+                }
+                Some('$') if starts_var_ref(rest) => {
+                    let len = var_ref_len(rest);
+                    tokens.push(self.token(TokenKind::VarRef, len));
+                }
+                Some('$') if rest[1..].starts_with('{') => {
+                    let token = env_ref(rest).map_err(|(offset, code, message)| self.error_at(offset, code, message))?;
+                    let len = token.len;
+                    tokens.push(self.token(TokenKind::EnvRef, len));
+                }
+                Some(_) => {
+                    // Plain text up to the next character with a meaning
+                    let len = plain_text_len(rest);
+                    let SyntaxElement::Token(text) = self.token(TokenKind::Text, len) else {
+                        unreachable!()
+                    };
+                    match tokens.last_mut() {
+                        Some(SyntaxElement::Token(last)) if last.kind == TokenKind::Text => {
+                            last.text.push_str(&text.text);
+                        }
+                        _ => tokens.push(SyntaxElement::Token(text)),
+                    }
+                }
+            }
+        }
+
+        // Whitespace and comments after the last character are not part of the value
+        let end = tokens
+            .iter()
+            .rposition(|token| match token {
+                SyntaxElement::Token(token) => !token.kind.is_trivia(),
+                SyntaxElement::Node(_) => true,
+            })
+            .map_or(0, |i| i + 1);
+        let trailing = tokens.split_off(end);
+        out.push(SyntaxElement::Node(SyntaxNode::new(NodeKind::String, tokens)));
+        out.extend(trailing);
+        Ok(())
+    }
+
+    /// Length of the identifier starting `offset` bytes ahead
+    fn identifier_len(&self, offset: usize) -> Result<usize> {
+        name_len(&self.rest()[offset..])
+            .ok_or_else(|| self.error_at(offset, ErrorCode::ExpectedIdentifier, "Expected identifier"))
+    }
+
+    /// Whitespace, newlines and comments
+    fn trivia(&mut self, out: &mut Vec<SyntaxElement>) {
+        loop {
+            let rest = self.rest();
+            if rest.starts_with('\n') {
+                out.push(self.token(TokenKind::Newline, 1));
+            } else if rest.starts_with(|c: char| c.is_whitespace()) {
+                let len = rest
+                    .find(|c: char| c == '\n' || !c.is_whitespace())
+                    .unwrap_or(rest.len());
+                out.push(self.token(TokenKind::Whitespace, len));
+            } else if rest.starts_with("//") {
+                out.push(self.line_comment());
+            } else if rest.starts_with("/*") {
+                out.push(self.block_comment());
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn horizontal_whitespace(&mut self, out: &mut Vec<SyntaxElement>) {
+        let len = horizontal_whitespace_len(self.rest());
+        if len > 0 {
+            out.push(self.token(TokenKind::Whitespace, len));
+        }
+    }
+
+    fn line_comment(&mut self) -> SyntaxElement {
+        let len = line_comment_len(self.rest());
+        self.token(TokenKind::LineComment, len)
+    }
+
+    /// `/* ... */`, or everything up to the end of an unclosed comment
+    fn block_comment(&mut self) -> SyntaxElement {
+        let len = block_comment_len(self.rest());
+        self.token(TokenKind::BlockComment, len)
+    }
+
+    // Helper methods
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    /// Consume `len` bytes as a token
+    fn token(&mut self, kind: TokenKind, len: usize) -> SyntaxElement {
+        let text = &self.input[self.pos..self.pos + len];
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+        self.pos += len;
+        SyntaxElement::Token(SyntaxToken::new(kind, text))
+    }
+
+    fn error(&self, code: ErrorCode, message: &str) -> SymError {
+        self.error_at(0, code, message)
+    }
+
+    fn limit_error(&self, limit: Limit, max: usize) -> SymError {
+        SymError::LimitExceeded {
+            limit,
+            max,
+            location: Some(Location {
+                line: self.line,
+                col: self.col,
+                ..Location::default()
+            }),
+        }
+    }
+
+    /// An error `offset` bytes ahead on the current line
+    fn error_at(&self, offset: usize, code: ErrorCode, message: &str) -> SymError {
+        SymError::ParseError {
+            message: message.to_string(),
+            code,
+            location: Location {
+                line: self.line,
+                col: self.col + self.rest()[..offset].chars().count(),
+                ..Location::default()
+            },
+            suggestions: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_round_trip() {
+        let input = "// header\n@import ./base.sym  \n\n{ $host  localhost }\n\n{ :port 0x1F90  // hex\n, :count 1_000\n, :ratio -1.5e3\n, :flags [ true, false, null, inf ]\n, :path C:\\\\Users\\$HOME\n/* block */\n, :url http://$host:${PORT:-80}/x\n, :text\n    Line one\n    // skipped\n    \\  indented\n, :empty\n, :nested!\n  { :list+ [ :a\n    , :b\n    , ]\n  }\n}\n";
+        let tree = parse(input).unwrap();
+        assert_eq!(tree.to_string(), input);
+
+        let spellings: Vec<_> = tree
+            .tokens()
+            .into_iter()
+            .filter(|t| t.kind() == TokenKind::Number)
+            .map(SyntaxToken::text)
+            .collect();
+        assert_eq!(spellings, ["0x1F90", "1_000", "-1.5e3", "inf"]);
+
+        let comments: Vec<_> = tree.comments().map(SyntaxToken::text).collect();
+        assert_eq!(comments, ["// header", "// hex", "/* block */", "// skipped"]);
+    }
+
+    #[test]
+    fn test_structure() {
+        let tree = parse("{ :name Alice   // owner\n, :age 30\n}").unwrap();
+        let object = tree.child_nodes().next().unwrap();
+        assert_eq!(object.kind(), NodeKind::Object);
+
+        let fields: Vec<_> = object.child_nodes().collect();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].to_string(), ":name Alice");
+        let value = fields[0].child_nodes().next().unwrap();
+        assert_eq!(value.kind(), NodeKind::String);
+        assert_eq!(fields[1].to_string(), ":age 30");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            parse("{ :a 1\n, :b 2"),
+            Err(SymError::ParseError { location: Location { line: 2, col: 7, .. }, .. })
+        ));
+        assert!(parse("").is_err());
+        assert!(parse("{ :a ${} }").is_err());
+        assert!(parse("{ :a 1 }\n}").is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        // Literals are classified as the evaluating parser reads them
+        let kinds = |tree: &SyntaxNode| -> Vec<TokenKind> {
+            tree.tokens().into_iter().map(SyntaxToken::kind).filter(|k| !k.is_trivia()).collect()
+        };
+        let input = "[ 0xff\n, nan\n, true ]";
+        let tree = parse_with_options(input, &ParseOptions::new().hex(false).nan(false)).unwrap();
+        assert_eq!(tree.to_string(), input);
+        assert_eq!(kinds(&tree)[1..6], [TokenKind::Text, TokenKind::Comma, TokenKind::Text, TokenKind::Comma, TokenKind::Bool]);
+        assert_eq!(kinds(&parse(input).unwrap())[1..4], [TokenKind::Number, TokenKind::Comma, TokenKind::Number]);
+
+        // Limits come from the options
+        let limits = crate::options::Limits { max_depth: 2, max_input_size: 20, ..Default::default() };
+        let options = ParseOptions::new().limits(limits);
+        assert!(parse_with_options("{ :a [ 1 ] }", &options).is_ok());
+        assert!(matches!(
+            parse_with_options("{ :a [ [ 1 ] ] }", &options),
+            Err(SymError::LimitExceeded { limit: Limit::Depth, max: 2, .. })
+        ));
+        assert!(matches!(
+            parse_with_options(&format!("{{ :a {} }}", "x".repeat(20)), &options),
+            Err(SymError::LimitExceeded { limit: Limit::InputSize, .. })
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_test_cases() {
+        // Every test case and example that parses must print back unchanged
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+        let mut files = Vec::new();
+        for dir in ["tests/cases", "examples"] {
+            collect_sym_files(&root.join(dir), &mut files);
+        }
+        assert!(!files.is_empty());
+
+        for file in files {
+            let input = std::fs::read_to_string(&file).unwrap();
+            if crate::parse(&input).is_err() {
+                continue;
+            }
+            let tree = parse(&input).unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+            assert_eq!(tree.to_string(), input, "{}", file.display());
+        }
+    }
+
+    fn collect_sym_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                collect_sym_files(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "sym") {
+                files.push(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! Deserializing Rust types from SYM with serde
+//!
+//! [`from_str`](crate::from_str) and [`from_file`](crate::from_file) parse SYM
+//! and deserialize its data into any type that implements
+//! [`Deserialize`](serde::Deserialize); [`from_value`] does the same for a
+//! value that is already parsed. Objects become structs and maps, arrays
+//! become sequences and tuples, and symbols name unit enum variants:
+//!
+//! ```
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! #[serde(rename_all = "lowercase")]
+//! enum Mode {
+//!     Active,
+//!     Paused,
+//! }
+//!
+//! #[derive(Deserialize)]
+//! struct Service {
+//!     name: String,
+//!     port: u16,
+//!     mode: Mode,
+//!     tags: Vec<String>,
+//! }
+//!
+//! let service: Service =
+//!     sym_parser::from_str("{ :name web\n, :port 8080\n, :mode :active\n, :tags [a\n  , b]\n}").unwrap();
+//! assert!(matches!(service.mode, Mode::Active));
+//!
+//! let err = sym_parser::from_str::<Service>("{ :name web\n, :port 80800\n, :mode :active\n, :tags []\n}")
+//!     .err()
+//!     .unwrap();
+//! assert_eq!(
+//!     err.to_string(),
+//!     "Cannot deserialize port at line 2, column 9: invalid value: integer `80800`, expected u16"
+//! );
+//! ```
+//!
+//! A variant that carries data is written as an object with one key, the
+//! variant name: `{ :Tcp 8080 }`. Errors name the key path of the value that
+//! could not be deserialized and, when it was parsed from source, its line.
+
+use crate::path::{format_path, Segment};
+use crate::span::{SpannedKind, SpannedValue};
+use crate::{Location, Result, SymError, Value};
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{
+    self, Deserialize, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, Unexpected,
+    VariantAccess, Visitor,
+};
+use std::fmt;
+
+/// Deserialize a `T` from a parsed value
+///
+/// Errors name the key path of the value at fault, but not its line; see
+/// [`from_str`](crate::from_str) for that.
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de Value) -> Result<T> {
+    T::deserialize(value)
+}
+
+/// Deserialize a `T` from a value parsed with spans, locating any error at
+/// the value it is about
+pub fn from_spanned<T: DeserializeOwned>(value: &SpannedValue<'_>) -> Result<T> {
+    from_value(&value.to_value()).map_err(|mut err| {
+        if let SymError::DeserializeError { path, location, .. } = &mut err {
+            *location = locate(value, path);
+        }
+        err
+    })
+}
+
+/// Where the value at `path` was written, or the innermost value on the way
+/// to it that exists
+fn locate(mut value: &SpannedValue<'_>, path: &[Segment]) -> Option<Location> {
+    for segment in path {
+        let child = match (&value.kind, segment) {
+            (SpannedKind::Object(map), Segment::Key(key)) => map.get(key.as_str()).map(|field| &field.value),
+            (SpannedKind::Array(items), Segment::Index(index)) => items.get(*index),
+            _ => None,
+        };
+        match child {
+            Some(child) => value = child,
+            None => break,
+        }
+    }
+    // Values supplied from outside the source have an empty span
+    (value.span.line > 0).then(|| Location::from(&value.span))
+}
+
+/// The path of an error, as shown in its message
+pub(crate) fn describe_path(path: &[Segment]) -> String {
+    if path.is_empty() {
+        "the value".to_string()
+    } else {
+        format_path(path)
+    }
+}
+
+impl de::Error for SymError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        SymError::DeserializeError {
+            message: message.to_string(),
+            path: Vec::new(),
+            location: None,
+        }
+    }
+}
+
+/// Put `segment` in front of the path of an error found inside it
+fn within(mut err: SymError, segment: Segment) -> SymError {
+    if let SymError::DeserializeError { path, .. } = &mut err {
+        path.insert(0, segment);
+    }
+    err
+}
+
+/// How a value is described in errors about its type
+fn unexpected(value: &Value) -> Unexpected<'_> {
+    match value {
+        Value::Null => Unexpected::Unit,
+        Value::Bool(b) => Unexpected::Bool(*b),
+        Value::Int(i) => Unexpected::Signed(*i),
+        Value::Float(f) => Unexpected::Float(*f),
+        Value::String(s) => Unexpected::Str(s),
+        Value::Symbol(_) => Unexpected::Other("symbol"),
+        Value::Array(_) => Unexpected::Seq,
+        Value::Object(_) => Unexpected::Map,
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value {
+    type Error = SymError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::Float(f) => visitor.visit_f64(*f),
+            // A symbol reads as its name, without the `:`
+            Value::String(s) | Value::Symbol(s) => visitor.visit_borrowed_str(s),
+            Value::Array(items) => {
+                let mut seq = Seq {
+                    items: items.iter().enumerate(),
+                };
+                let value = visitor.visit_seq(&mut seq)?;
+                match seq.items.len() {
+                    0 => Ok(value),
+                    left => Err(de::Error::invalid_length(
+                        items.len(),
+                        &format!("{} elements", items.len() - left).as_str(),
+                    )),
+                }
+            }
+            Value::Object(map) => visitor.visit_map(Map {
+                entries: map.iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// A symbol or string names a unit variant; an object with one key is a
+    /// variant with the key's value as its data
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self {
+            Value::Symbol(variant) | Value::String(variant) => visitor.visit_enum(Enum {
+                variant,
+                value: None,
+            }),
+            Value::Object(map) if map.len() == 1 => {
+                let (variant, value) = map.first().expect("the object has one key");
+                visitor.visit_enum(Enum {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            _ => Err(de::Error::invalid_type(
+                unexpected(self),
+                &"a symbol, or an object with one key",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// The elements of an array
+struct Seq<'de> {
+    items: std::iter::Enumerate<std::slice::Iter<'de, Value>>,
+}
+
+impl<'de> SeqAccess<'de> for Seq<'de> {
+    type Error = SymError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.items.next() {
+            Some((index, item)) => seed
+                .deserialize(item)
+                .map(Some)
+                .map_err(|err| within(err, Segment::Index(index))),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// The fields of an object, with the value of the last key read
+struct Map<'de> {
+    entries: indexmap::map::Iter<'de, String, Value>,
+    value: Option<(&'de String, &'de Value)>,
+}
+
+impl<'de> MapAccess<'de> for Map<'de> {
+    type Error = SymError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some((key, value));
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+            .map_err(|err| within(err, Segment::Key(key.clone())))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| <SymError as de::Error>::custom("value requested before its key"))?;
+        seed.deserialize(value).map_err(|err| within(err, Segment::Key(key.clone())))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// An enum variant and the value it carries, if any
+struct Enum<'de> {
+    variant: &'de str,
+    value: Option<&'de Value>,
+}
+
+impl<'de> Enum<'de> {
+    /// Deserialize the variant's data, which sits under the variant name
+    fn data<T>(self, expected: &str, deserialize: impl FnOnce(&'de Value) -> Result<T>) -> Result<T> {
+        match self.value {
+            Some(value) => deserialize(value).map_err(|err| within(err, Segment::Key(self.variant.to_string()))),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &expected)),
+        }
+    }
+}
+
+impl<'de> EnumAccess<'de> for Enum<'de> {
+    type Error = SymError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::<SymError>::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Enum<'de> {
+    type Error = SymError;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            None | Some(Value::Null) => Ok(()),
+            Some(value) => Err(within(
+                de::Error::invalid_type(unexpected(value), &"a unit variant"),
+                Segment::Key(self.variant.to_string()),
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        self.data("a newtype variant", |value| seed.deserialize(value))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.data("a tuple variant", |value| de::Deserializer::deserialize_any(value, visitor))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        self.data("a struct variant", |value| de::Deserializer::deserialize_any(value, visitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_file, from_str, ErrorCode};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Status {
+        Active,
+        Paused,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Listen {
+        Port(u16),
+        Socket { path: String, mode: Option<u32> },
+        Pair(String, u16),
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Server {
+        host: String,
+        listen: Listen,
+        #[serde(default)]
+        weight: f32,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct AppConfig {
+        name: String,
+        status: Status,
+        debug: Option<bool>,
+        servers: Vec<Server>,
+        limits: HashMap<String, u64>,
+        ratio: (i8, i8),
+    }
+
+    #[test]
+    fn test_from_str() {
+        let input = "{ $port 8080 }\n{ :name api\n, :status :active\n, :servers\n  [ { :host a\n    , :listen { :Port $port }\n    }\n  , { :host b\n    , :listen { :Socket { :path /run/api.sock } }\n    , :weight 2\n    }\n  , { :host c\n    , :listen { :Pair [ localhost\n        , 9000\n        ] }\n    }\n  ]\n, :limits { :requests 100 }\n, :ratio [1\n  , -2]\n}";
+        let config: AppConfig = from_str(input).unwrap();
+        assert_eq!(config.name, "api");
+        assert_eq!(config.status, Status::Active);
+        assert_eq!(config.debug, None);
+        assert_eq!(config.servers[0].listen, Listen::Port(8080));
+        assert_eq!(
+            config.servers[1],
+            Server {
+                host: "b".into(),
+                listen: Listen::Socket {
+                    path: "/run/api.sock".into(),
+                    mode: None
+                },
+                weight: 2.0
+            }
+        );
+        assert_eq!(config.servers[2].listen, Listen::Pair("localhost".into(), 9000));
+        assert_eq!(config.limits["requests"], 100);
+        assert_eq!(config.ratio, (1, -2));
+
+        assert_eq!(from_str::<Vec<Status>>("[ :paused\n, active ]").unwrap(), [Status::Paused, Status::Active]);
+        assert_eq!(from_str::<Option<String>>("{ $x null }\n$x").unwrap(), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let input = "{ :name api\n, :status :active\n, :servers\n  [ { :host a\n    , :listen { :Port 70000 }\n    }\n  ]\n, :limits {}\n, :ratio [1\n  , 2]\n}";
+        let err = from_str::<AppConfig>(input).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Deserialize);
+        assert_eq!(err.location().unwrap().line, 5);
+        match &err {
+            SymError::DeserializeError { path, message, .. } => {
+                assert_eq!(format_path(path), "servers[0].listen.Port");
+                assert_eq!(message, "invalid value: integer `70000`, expected u16");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let message = |input: &str| from_str::<AppConfig>(input).unwrap_err().to_string();
+        assert_eq!(
+            message("{ :name api\n, :status :stopped\n}"),
+            "Cannot deserialize status at line 2, column 11: unknown variant `stopped`, expected `active` or `paused`"
+        );
+        assert_eq!(
+            message("{ :name api\n, :status :active\n}"),
+            "Cannot deserialize the value at line 1, column 1: missing field `servers`"
+        );
+        assert_eq!(
+            message("{ :name [x]\n}"),
+            "Cannot deserialize name at line 1, column 9: invalid type: sequence, expected a string"
+        );
+        assert!(message("{ :name api\n, :status { :active 1 }\n}").contains("invalid type: integer `1`, expected a unit variant"));
+        assert!(message("{ :name a\n, :status :active\n, :servers []\n, :limits {}\n, :ratio [1\n  , 2\n  , 3]\n}")
+            .ends_with("ratio at line 5, column 10: invalid length 3, expected 2 elements"));
+
+        // Without spans the path is still reported
+        let value = crate::parse("{ :host a\n, :listen :Port\n}").unwrap();
+        let err = from_value::<Server>(&value).unwrap_err();
+        assert!(err.location().is_none());
+        assert_eq!(err.to_string(), "Cannot deserialize listen: invalid type: unit variant, expected a newtype variant");
+    }
+
+    #[test]
+    fn test_from_file() {
+        let dir = std::env::temp_dir().join(format!("sym-parser-de-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("base.sym"), "{ :host base\n, :weight x\n}").unwrap();
+        std::fs::write(dir.join("server.sym"), "@import ./base.sym\n{ :listen { :Port 80 } }").unwrap();
+        let err = from_file::<Server>(dir.join("server.sym")).unwrap_err();
+        let location = err.location().unwrap();
+        assert!(location.file.as_deref().unwrap().ends_with("base.sym"));
+        assert_eq!(location.line, 2);
+
+        std::fs::write(dir.join("base.sym"), "{ :host base }").unwrap();
+        let server: Server = from_file(dir.join("server.sym")).unwrap();
+        assert_eq!(server.listen, Listen::Port(80));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
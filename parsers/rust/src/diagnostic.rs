@@ -0,0 +1,605 @@
+//! Human-readable error reports
+//!
+//! A [`Diagnostic`] presents a [`SymError`] the way a compiler would: with a
+//! stable error code, the offending source line with the error underlined,
+//! and a hint for common mistakes.
+//!
+//! ```text
+//! error[SYM0006]: Expected ',' separator
+//!  --> config.sym:1:15
+//!   |
+//! 1 | { :name Alice :age 28 }
+//!   |               ^
+//!   = hint: separate values with a line break followed by ','
+//! ```
+
+use crate::{Location, SymError};
+use std::fmt;
+
+/// Stable identifier of a kind of error, printed as `SYM0001`
+///
+/// Codes are never reused or renumbered, so they can be searched for and
+/// matched on by tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The input ended in the middle of a value
+    UnexpectedEnd,
+    /// The document holds no imports, definitions or data
+    EmptyDocument,
+    /// A `}` or `]` that closes nothing
+    UnexpectedClose,
+    /// An object without its closing `}`
+    UnclosedObject,
+    /// An array without its closing `]`
+    UnclosedArray,
+    /// Two values not separated by a line break and `,`
+    MissingSeparator,
+    /// An object entry that does not start with `:` or `$`
+    ExpectedField,
+    /// A key, variable or symbol name that is not an identifier
+    ExpectedIdentifier,
+    /// A specific character was expected
+    ExpectedCharacter,
+    /// `${` without a variable name
+    ExpectedEnvName,
+    /// `${NAME` without its closing `}`
+    UnclosedEnvRef,
+    /// A `$name` reference to a variable that is not defined
+    UndefinedVariable,
+    /// An array or object variable used inside a string
+    NonScalarInterpolation,
+    /// A required environment variable is not set
+    MissingEnvVar,
+    /// Variables defined in terms of each other
+    VariableCycle,
+    /// A variable defined twice without `!`
+    DuplicateVariable,
+    /// A key defined twice without `!` or `+`
+    DuplicateKey,
+    /// `:key+` on a value that is not an array
+    AppendToNonArray,
+    /// An edit of a [`Document`](crate::edit::Document) that cannot be made
+    Edit,
+    /// An imported file that cannot be found or read
+    Import,
+    /// Files that import each other
+    ImportCycle,
+    /// Reading the input failed
+    Io,
+    /// A key or variable followed by something other than `!` or `+`
+    InvalidModifier,
+    /// The input is larger or deeper than the parser allows
+    LimitExceeded,
+    /// A document whose data cannot be read as a stream of elements
+    Stream,
+    /// A [query](crate::query) that cannot be parsed
+    Query,
+    /// A value that does not fit the type it is converted to
+    Conversion,
+    /// Data that does not match the type it is [deserialized](crate::de) into
+    Deserialize,
+}
+
+impl ErrorCode {
+    /// The code as printed, e.g. `SYM0006`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedEnd => "SYM0001",
+            ErrorCode::EmptyDocument => "SYM0002",
+            ErrorCode::UnexpectedClose => "SYM0003",
+            ErrorCode::UnclosedObject => "SYM0004",
+            ErrorCode::UnclosedArray => "SYM0005",
+            ErrorCode::MissingSeparator => "SYM0006",
+            ErrorCode::ExpectedField => "SYM0007",
+            ErrorCode::ExpectedIdentifier => "SYM0008",
+            ErrorCode::ExpectedCharacter => "SYM0009",
+            ErrorCode::ExpectedEnvName => "SYM0010",
+            ErrorCode::UnclosedEnvRef => "SYM0011",
+            ErrorCode::UndefinedVariable => "SYM0012",
+            ErrorCode::NonScalarInterpolation => "SYM0013",
+            ErrorCode::MissingEnvVar => "SYM0014",
+            ErrorCode::VariableCycle => "SYM0015",
+            ErrorCode::DuplicateVariable => "SYM0016",
+            ErrorCode::DuplicateKey => "SYM0017",
+            ErrorCode::AppendToNonArray => "SYM0018",
+            ErrorCode::Edit => "SYM0019",
+            ErrorCode::Import => "SYM0020",
+            ErrorCode::ImportCycle => "SYM0021",
+            ErrorCode::Io => "SYM0022",
+            ErrorCode::InvalidModifier => "SYM0023",
+            ErrorCode::LimitExceeded => "SYM0024",
+            ErrorCode::Stream => "SYM0025",
+            ErrorCode::Query => "SYM0026",
+            ErrorCode::Conversion => "SYM0027",
+            ErrorCode::Deserialize => "SYM0028",
+        }
+    }
+}
+
+impl ErrorCode {
+    /// The category the code belongs to
+    pub fn kind(self) -> ErrorKind {
+        match self {
+            ErrorCode::UnexpectedEnd
+            | ErrorCode::EmptyDocument
+            | ErrorCode::UnexpectedClose
+            | ErrorCode::UnclosedObject
+            | ErrorCode::UnclosedArray
+            | ErrorCode::MissingSeparator
+            | ErrorCode::ExpectedField
+            | ErrorCode::ExpectedIdentifier
+            | ErrorCode::ExpectedCharacter
+            | ErrorCode::ExpectedEnvName
+            | ErrorCode::UnclosedEnvRef
+            | ErrorCode::InvalidModifier
+            | ErrorCode::Stream => ErrorKind::Syntax,
+            ErrorCode::UndefinedVariable
+            | ErrorCode::NonScalarInterpolation
+            | ErrorCode::MissingEnvVar
+            | ErrorCode::VariableCycle
+            | ErrorCode::DuplicateVariable => ErrorKind::Variable,
+            ErrorCode::DuplicateKey | ErrorCode::AppendToNonArray => ErrorKind::Merge,
+            ErrorCode::Edit => ErrorKind::Edit,
+            ErrorCode::Import | ErrorCode::ImportCycle => ErrorKind::Import,
+            ErrorCode::Io => ErrorKind::Io,
+            ErrorCode::LimitExceeded => ErrorKind::Limit,
+            ErrorCode::Query => ErrorKind::Query,
+            ErrorCode::Conversion | ErrorCode::Deserialize => ErrorKind::Conversion,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Stable identifier of a kind of warning, printed as `SYMW0001`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningCode {
+    /// A key repeated in one object without `!` or `+`, deep merged
+    DuplicateKey,
+    /// A variable that is defined but never used
+    UnusedVariable,
+    /// An `@import` that adds no variables or data
+    EmptyImport,
+    /// A `:key` without a value, read as the empty string
+    EmptyValue,
+    /// An `@import` of a file that the same file already imports
+    RepeatedImport,
+}
+
+impl WarningCode {
+    /// The code as printed, e.g. `SYMW0001`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarningCode::DuplicateKey => "SYMW0001",
+            WarningCode::UnusedVariable => "SYMW0002",
+            WarningCode::EmptyImport => "SYMW0003",
+            WarningCode::EmptyValue => "SYMW0004",
+            WarningCode::RepeatedImport => "SYMW0005",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Something legal that is almost always a mistake
+///
+/// Warnings never stop a parse; callers decide whether to show them or to
+/// treat them as errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+    pub location: Location,
+}
+
+impl Warning {
+    /// Format the warning like a [`Diagnostic`], quoting the line of `source`
+    pub fn render(&self, source: &str, file: Option<&str>) -> String {
+        let mut out = format!("warning[{}]: {}\n", self.code, self.message);
+        render_location(&mut out, &self.location, 1, None, source, file);
+        out
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.location)
+    }
+}
+
+/// Broad category of an error, for tools that only need to know what failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The source is not well-formed SYM
+    Syntax,
+    /// A variable or environment variable cannot be resolved
+    Variable,
+    /// Values cannot be merged as the key modifiers ask
+    Merge,
+    /// An imported file cannot be loaded
+    Import,
+    /// Reading the input failed
+    Io,
+    /// An edit of a [`Document`](crate::edit::Document) cannot be made
+    Edit,
+    /// The input exceeds a configured [`Limits`](crate::Limits)
+    Limit,
+    /// A [query](crate::query) is not well-formed
+    Query,
+    /// A value cannot be converted to a Rust type
+    Conversion,
+}
+
+impl ErrorKind {
+    /// The name used for the `type` of the test suite's `error.json` files
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "SyntaxError",
+            ErrorKind::Variable => "VariableError",
+            ErrorKind::Merge => "MergeError",
+            ErrorKind::Import => "ImportError",
+            ErrorKind::Io => "IoError",
+            ErrorKind::Edit => "EditError",
+            ErrorKind::Limit => "LimitError",
+            ErrorKind::Query => "QueryError",
+            ErrorKind::Conversion => "ConversionError",
+        }
+    }
+
+    /// The kind with the given name; `ParseError` is accepted for `SyntaxError`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let kind = match name {
+            "SyntaxError" | "ParseError" => ErrorKind::Syntax,
+            "VariableError" => ErrorKind::Variable,
+            "MergeError" => ErrorKind::Merge,
+            "ImportError" => ErrorKind::Import,
+            "IoError" => ErrorKind::Io,
+            "EditError" => ErrorKind::Edit,
+            "LimitError" => ErrorKind::Limit,
+            "QueryError" => ErrorKind::Query,
+            "ConversionError" => ErrorKind::Conversion,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An error prepared for display against the source it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    /// What went wrong, without the location
+    pub message: String,
+    /// Where the error was found, if it has a location
+    pub location: Option<Location>,
+    /// Number of characters to underline
+    pub width: usize,
+    /// A suggestion for fixing a common mistake
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// Describe `error`, which was found in `source`
+    ///
+    /// For an error in an imported file, `source` is the text of that file.
+    pub fn new(error: &SymError, source: &str) -> Self {
+        let name_width = |name: &str, sigils: usize| name.chars().count() + sigils;
+        let width = match error {
+            SymError::UndefinedVariable { name, .. }
+            | SymError::NonScalarInterpolation { name, .. }
+            | SymError::DuplicateVariable { name, .. } => name_width(name, 1),
+            SymError::MissingEnvVar { name, .. } => name_width(name, 3),
+            SymError::VariableCycle { names, .. } => names.last().map_or(1, |name| name_width(name, 1)),
+            SymError::DuplicateKey { key, .. } | SymError::AppendToNonArray { key, .. } => name_width(key, 1),
+            SymError::ImportError { .. } | SymError::ImportCycle { .. } => "@import".len(),
+            _ => 1,
+        };
+        let mut diagnostic = Diagnostic {
+            code: error.code(),
+            message: error.message(),
+            location: error.location().cloned(),
+            width,
+            hint: None,
+        };
+        diagnostic.hint = match error {
+            SymError::UndefinedVariable { suggestions, .. } if !suggestions.is_empty() => {
+                Some(format!("did you mean {}?", format_suggestions("$", suggestions)))
+            }
+            _ if !error.suggestions().is_empty() => {
+                Some(format!("did you mean {}?", format_suggestions("", error.suggestions())))
+            }
+            _ => hint(&diagnostic, source),
+        };
+        diagnostic
+    }
+
+    /// Format the diagnostic, quoting the offending line of `source`
+    ///
+    /// `file` names the source in the location line, unless the error has a
+    /// file of its own; it defaults to `<input>`.
+    pub fn render(&self, source: &str, file: Option<&str>) -> String {
+        let mut out = format!("error[{}]: {}\n", self.code, self.message);
+        match &self.location {
+            Some(location) => {
+                render_location(&mut out, location, self.width, self.hint.as_deref(), source, file)
+            }
+            None => {
+                if let Some(hint) = &self.hint {
+                    out.push_str(&format!("  = hint: {}\n", hint));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Append the location, the quoted source line, the imports and the hint of a report
+fn render_location(
+    out: &mut String,
+    location: &Location,
+    width: usize,
+    hint: Option<&str>,
+    source: &str,
+    file: Option<&str>,
+) {
+    let number = location.line.to_string();
+    let gutter = " ".repeat(number.len());
+    let file_name = |location: &Location| match &location.file {
+        Some(path) => path.display().to_string(),
+        None => file.unwrap_or("<input>").to_string(),
+    };
+    out.push_str(&format!(
+        "{}--> {}:{}:{}\n",
+        gutter,
+        file_name(location),
+        location.line,
+        location.col
+    ));
+
+    if let Some(text) = source.lines().nth(location.line.saturating_sub(1)) {
+        // Keep tabs so the marker lines up with the quoted text
+        let padding: String = text
+            .chars()
+            .take(location.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let marker = if width > 1 { "~" } else { "^" };
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", number, text));
+        out.push_str(&format!("{} | {}{}\n", gutter, padding, marker.repeat(width)));
+    }
+    // Innermost import first, like a backtrace
+    for site in location.import_stack.iter().rev() {
+        out.push_str(&format!(
+            "{} = note: imported from {}:{}:{}\n",
+            gutter,
+            file_name(site),
+            site.line,
+            site.col
+        ));
+    }
+    if let Some(hint) = hint {
+        out.push_str(&format!("{} = hint: {}\n", gutter, hint));
+    }
+}
+
+/// Render `error` against its source in one step
+pub fn render(error: &SymError, source: &str, file: Option<&str>) -> String {
+    Diagnostic::new(error, source).render(source, file)
+}
+
+/// The candidates closest to a misspelled `name`, best first
+///
+/// Only candidates within a few edits of `name` are returned, so an empty
+/// list means nothing looked like a typo.
+pub(crate) fn suggest<'c>(name: &str, candidates: impl IntoIterator<Item = &'c str>) -> Vec<String> {
+    let limit = name.chars().count().div_ceil(3).max(1);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .collect();
+    scored.sort_by_key(|(distance, _)| *distance);
+    let best = scored.first().map_or(0, |(distance, _)| *distance);
+    let mut suggestions = Vec::new();
+    for (distance, candidate) in scored {
+        if distance == best && !suggestions.iter().any(|s| s == candidate) {
+            suggestions.push(candidate.to_string());
+        }
+    }
+    suggestions
+}
+
+/// Number of insertions, deletions, substitutions and swaps of adjacent
+/// characters that turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows for i - 2, i - 1 and i
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut prev, row);
+    }
+    prev[b.len()]
+}
+
+/// Format suggestions as `` `a` ``, `` `a` or `b` ``, `` `a`, `b` or `c` ``
+pub(crate) fn format_suggestions(prefix: &str, suggestions: &[String]) -> String {
+    let quoted: Vec<String> = suggestions.iter().map(|s| format!("`{}{}`", prefix, s)).collect();
+    match quoted.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+/// Suggest a fix for a common mistake that leads to this error
+fn hint(diagnostic: &Diagnostic, source: &str) -> Option<String> {
+    let location = diagnostic.location.as_ref()?;
+    let line = location.line;
+    if !matches!(
+        diagnostic.code,
+        ErrorCode::MissingSeparator
+            | ErrorCode::ExpectedField
+            | ErrorCode::ExpectedIdentifier
+            | ErrorCode::UnexpectedClose
+            | ErrorCode::UnclosedObject
+            | ErrorCode::UnclosedArray
+    ) {
+        return None;
+    }
+    // Values supplied from outside the source are located on line 0
+    let index = line.checked_sub(1)?;
+    let lines: Vec<&str> = source.lines().collect();
+
+    // `:port:8080` - the value is glued to the key
+    if let Some(key) = lines.get(index).and_then(|text| glued_key(text, location.col)) {
+        return Some(format!("put a space between `:{}` and its value", key));
+    }
+
+    // `, :a 1,` then `  :b 2` - a comma at the end of the line before the
+    // error instead of the start of the next
+    let previous = index.checked_sub(1).and_then(|i| lines.get(i));
+    if let (Some(previous), Some(text)) = (previous, lines.get(index)) {
+        if strip_line_comment(previous).trim_end().ends_with(',') && !text.trim_start().starts_with(',') {
+            return Some(format!(
+                "line {} ends with ','; in SYM the comma starts the next line instead: `, :key value`",
+                index
+            ));
+        }
+    }
+
+    if diagnostic.code == ErrorCode::MissingSeparator {
+        return Some("separate values with a line break followed by ','".to_string());
+    }
+    None
+}
+
+/// A key in field position followed directly by its value, at or before `col`
+fn glued_key(text: &str, col: usize) -> Option<&str> {
+    let mut prev = None;
+    for (i, c) in text.char_indices() {
+        let field_start = matches!(prev, None | Some(' ' | '\t' | '{' | '[' | ','));
+        prev = Some(c);
+        if c != ':' || !field_start || text[..i].chars().count() >= col {
+            continue;
+        }
+        let rest = &text[i + 1..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        if len == 0 || !rest.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            continue;
+        }
+        let after = rest[len..].trim_start_matches(['!', '+']);
+        if after.starts_with(|c: char| !matches!(c, ' ' | '\t' | ',' | '}' | ']')) {
+            return Some(&rest[..len]);
+        }
+    }
+    None
+}
+
+/// The line without a trailing `// comment`
+fn strip_line_comment(text: &str) -> &str {
+    match text.find(" //").or_else(|| text.find("\t//")) {
+        Some(i) => &text[..i],
+        None if text.trim_start().starts_with("//") => "",
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, parse_with_env};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render() {
+        let source = "{ :name Alice\n, :port 80 80\n}";
+        let error = parse(source).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::MissingSeparator);
+        assert_eq!(
+            render(&error, source, Some("app.sym")),
+            "error[SYM0006]: Expected ',' separator\n --> app.sym:2:12\n  |\n2 | , :port 80 80\n  |            ^\n  = hint: separate values with a line break followed by ','\n"
+        );
+
+        let source = "{ :user $usr }";
+        let error = parse(source).unwrap_err();
+        let diagnostic = Diagnostic::new(&error, source);
+        assert_eq!(diagnostic.code.as_str(), "SYM0012");
+        assert!(diagnostic.render(source, None).contains("\n1 | { :user $usr }\n  |         ~~~~\n"));
+
+        // The message leaves the location and suggestions to the report
+        let source = "{ :a 1\n, :b ${HOME:?set it}\n}";
+        let error = parse_with_env(source, HashMap::new(), HashMap::new()).unwrap_err();
+        assert_eq!(error.message(), "Environment variable HOME is not set: set it");
+        assert_eq!(error.to_string(), "Environment variable HOME is not set at line 2, column 6: set it");
+        let source = "{ $user bob }\n{ :b $usr\n}";
+        let error = parse(source).unwrap_err();
+        assert_eq!(error.message(), "Undefined variable $usr");
+        assert_eq!(Diagnostic::new(&error, source).message, error.message());
+        assert_eq!(error.to_string(), "Undefined variable $usr at line 2, column 6; did you mean `$user`?");
+    }
+
+    #[test]
+    fn test_hints() {
+        let hint = |source: &str| Diagnostic::new(&parse(source).unwrap_err(), source).hint;
+
+        let trailing = hint("{ :name app,\n  :db { :host x } }").unwrap();
+        assert!(trailing.starts_with("line 1 ends with ','"), "{}", trailing);
+        // Only the line just before the error is looked at
+        let separator = hint("{ :list [ 1,\n  2 ]\n, :b 2 2\n}").unwrap();
+        assert_eq!(separator, "separate values with a line break followed by ','");
+
+        // Errors without a source line get no hint
+        let error = SymError::ParseError {
+            message: "Expected ',' separator".to_string(),
+            code: ErrorCode::MissingSeparator,
+            location: Location::default(),
+            suggestions: Vec::new(),
+        };
+        assert_eq!(Diagnostic::new(&error, "{ :a 1,\n}").hint, None);
+
+        let glued = hint("{ :port:8080\n}").unwrap();
+        assert_eq!(glued, "put a space between `:port` and its value");
+        assert_eq!(glued_key("{ :url http://x:8080", 20), None);
+
+        assert_eq!(hint("{ :a $missing }"), None);
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(edit_distance("port", "prot"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest("db_hots", ["db_host", "db_port", "name"]), ["db_host"]);
+        assert_eq!(suggest("x", ["a", "b", "xyz"]), ["a", "b"]);
+        assert!(suggest("region", ["name", "port"]).is_empty());
+        assert_eq!(format_suggestions("$", &["a".into(), "b".into(), "c".into()]), "`$a`, `$b` or `$c`");
+    }
+}
@@ -0,0 +1,836 @@
+//! Format-preserving editing of SYM documents
+//!
+//! A [`Document`] wraps the lossless [syntax tree](crate::cst) of a file and
+//! changes values by key path, rewriting only the lines an edit touches.
+//! Comments, key order, variables and the spelling of untouched values are
+//! kept exactly as written.
+//!
+//! ```
+//! use sym_parser::edit::Document;
+//!
+//! let mut doc: Document = "// release\n{ :version v1.2.0  // bump me\n, :debug true\n}\n"
+//!     .parse()
+//!     .unwrap();
+//! doc.set("version", "v1.3.0").unwrap();
+//! doc.remove("debug").unwrap();
+//! assert_eq!(doc.to_string(), "// release\n{ :version v1.3.0  // bump me\n}\n");
+//! ```
+//!
+//! Paths name object keys separated by dots, with `[n]` for array elements:
+//! `server.port`, `services[0].image`, as described in [`path`](crate::path).
+//! A path starting with `$name` edits a variable definition instead of the
+//! data block.
+
+use crate::convert::{escape_inline, escape_sym_string};
+use crate::cst::{self, NodeKind, SyntaxElement, SyntaxNode, SyntaxToken, TokenKind};
+use crate::options::{Limits, ParseOptions};
+use crate::path::{format_path, Segment};
+use crate::{Result, SymError, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// An editable SYM document that keeps comments and layout
+#[derive(Debug, Clone)]
+pub struct Document {
+    tree: SyntaxNode,
+    /// Options the document was parsed with, to check edits against
+    options: ParseOptions,
+}
+
+impl Document {
+    /// Parse SYM source into an editable document
+    pub fn parse(input: &str) -> Result<Self> {
+        Self::parse_with_options(input, &ParseOptions::default())
+    }
+
+    /// Parse SYM source into an editable document with custom options
+    ///
+    /// Edits are checked against the same options, so an edit that would
+    /// nest too deep fails.
+    pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<Self> {
+        Ok(Self {
+            tree: cst::parse_with_options(input, options)?,
+            options: options.clone(),
+        })
+    }
+
+    /// The syntax tree of the document
+    pub fn syntax(&self) -> &SyntaxNode {
+        &self.tree
+    }
+
+    /// The syntax node of the value at `path`, if there is one
+    ///
+    /// Print the node to get the value as written in the source.
+    pub fn get(&self, path: &str) -> Option<&SyntaxNode> {
+        let segments = parse_path(path).ok()?;
+        let (last, parents) = segments.split_last()?;
+        let container = self.container(parents, last).ok()?;
+        let item = self.child(&container, last)?;
+        let node = self.node(&container).children().get(item)?;
+        match node {
+            SyntaxElement::Node(node) if node.kind() == NodeKind::Field => field_value(node)
+                .and_then(|i| match &node.children()[i] {
+                    SyntaxElement::Node(value) => Some(value),
+                    SyntaxElement::Token(_) => None,
+                }),
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        }
+    }
+
+    /// Replace the value at `path`, adding the key to its object if it is missing
+    pub fn set(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let value = value.into();
+        self.edit(path, |tree, container, last| {
+            match (tree.child(&container, last), last) {
+                (Some(item), _) => tree.replace(&container, item, &value, path),
+                (None, Segment::Key(key)) => tree.insert_field(&container, key, &value, path),
+                (None, Segment::Index(_)) => Err(edit_error(path, "array index out of range")),
+            }
+        })
+    }
+
+    /// Add a key that does not exist yet, or insert an element before index `n`
+    /// of an array when the path ends in `[n]`
+    pub fn insert(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let value = value.into();
+        self.edit(path, |tree, container, last| match last {
+            Segment::Key(key) => {
+                if tree.child(&container, last).is_some() {
+                    return Err(edit_error(path, "key already exists"));
+                }
+                tree.insert_field(&container, key, &value, path)
+            }
+            Segment::Index(index) => {
+                if *index > items(tree.node(&container)).len() {
+                    return Err(edit_error(path, "array index out of range"));
+                }
+                let node = render_node(&value, &tree.indent_for_item(&container, *index), path)?;
+                tree.insert_item(&container, *index, node);
+                Ok(())
+            }
+        })
+    }
+
+    /// Remove a key or array element, returning the syntax of its value
+    ///
+    /// Returns `None` if there is nothing at `path`.
+    pub fn remove(&mut self, path: &str) -> Result<Option<SyntaxNode>> {
+        let mut removed = None;
+        self.edit(path, |tree, container, last| {
+            if let Some(item) = tree.child(&container, last) {
+                removed = Some(tree.remove_item(&container, item));
+            }
+            Ok(())
+        })?;
+        Ok(removed.and_then(|node| match node.kind() {
+            NodeKind::Field => field_value(&node).and_then(|i| match &node.children()[i] {
+                SyntaxElement::Node(value) => Some(value.clone()),
+                SyntaxElement::Token(_) => None,
+            }),
+            _ => Some(node),
+        }))
+    }
+
+    /// Add an element to the end of the array at `path`
+    pub fn append(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let value = value.into();
+        let array = self.find(&parse_path(path)?, path)?;
+        if self.node(&array).kind() != NodeKind::Array {
+            return Err(edit_error(path, "not an array"));
+        }
+        let len = items(self.node(&array)).len();
+        let mut tree = self.clone();
+        let node = render_node(&value, &tree.indent_for_item(&array, len), path)?;
+        tree.insert_item(&array, len, node);
+        self.commit(tree, path)
+    }
+
+    /// Run an edit on a copy of the tree, keeping it only if the result parses
+    fn edit(
+        &mut self,
+        path: &str,
+        f: impl FnOnce(&mut Self, Vec<usize>, &Segment) -> Result<()>,
+    ) -> Result<()> {
+        let segments = parse_path(path)?;
+        let Some((last, parents)) = segments.split_last() else {
+            return Err(edit_error(path, "empty path"));
+        };
+        let mut tree = self.clone();
+        let container = tree.container(parents, last)?;
+        f(&mut tree, container, last)?;
+        self.commit(tree, path)
+    }
+
+    fn commit(&mut self, tree: Document, path: &str) -> Result<()> {
+        let text = tree.to_string();
+        match cst::parse_with_options(&text, &self.options) {
+            Ok(tree) => {
+                self.tree = tree;
+                Ok(())
+            }
+            Err(e) => Err(edit_error(path, &format!("the edit would not parse: {}", e))),
+        }
+    }
+
+    // Navigation. Nodes are addressed by the child indices leading to them.
+
+    fn node(&self, indices: &[usize]) -> &SyntaxNode {
+        let mut node = &self.tree;
+        for &i in indices {
+            node = match &node.children()[i] {
+                SyntaxElement::Node(child) => child,
+                SyntaxElement::Token(_) => unreachable!("indices lead to a token"),
+            };
+        }
+        node
+    }
+
+    fn node_mut(&mut self, indices: &[usize]) -> &mut SyntaxNode {
+        let mut node = &mut self.tree;
+        for &i in indices {
+            node = match &mut node.children_mut()[i] {
+                SyntaxElement::Node(child) => child,
+                SyntaxElement::Token(_) => unreachable!("indices lead to a token"),
+            };
+        }
+        node
+    }
+
+    /// The object or array holding the last segment of a path
+    fn container(&self, parents: &[Segment], last: &Segment) -> Result<Vec<usize>> {
+        if !parents.is_empty() {
+            return self.find_container(parents);
+        }
+        match last {
+            Segment::Key(name) if name.starts_with('$') => {
+                // The block defining the variable, or the last defs block for a new one
+                let blocks = self.defs_blocks();
+                blocks
+                    .iter()
+                    .rev()
+                    .find(|&&block| self.child(&[block], last).is_some())
+                    .or(blocks.last())
+                    .map(|&block| vec![block])
+                    .ok_or_else(|| edit_error(name, "the document has no defs block"))
+            }
+            _ => self.data_block().map(|block| vec![block]),
+        }
+    }
+
+    fn find_container(&self, segments: &[Segment]) -> Result<Vec<usize>> {
+        let path = format_path(segments);
+        let indices = self.find(segments, &path)?;
+        match self.node(&indices).kind() {
+            NodeKind::Object | NodeKind::Array => Ok(indices),
+            _ => Err(edit_error(&path, "not an object or array")),
+        }
+    }
+
+    /// The value node at a path
+    fn find(&self, segments: &[Segment], path: &str) -> Result<Vec<usize>> {
+        let Some((last, parents)) = segments.split_last() else {
+            return Err(edit_error(path, "empty path"));
+        };
+        let mut indices = self.container(parents, last)?;
+        let item = self
+            .child(&indices, last)
+            .ok_or_else(|| edit_error(path, "no such key or index"))?;
+        indices.push(item);
+        let node = self.node(&indices);
+        if node.kind() == NodeKind::Field {
+            let value = field_value(node).ok_or_else(|| edit_error(path, "the key has no value"))?;
+            indices.push(value);
+        }
+        Ok(indices)
+    }
+
+    /// The index of the field or element named by `segment` in a container
+    ///
+    /// A key defined twice is found at its last definition, which wins.
+    fn child(&self, container: &[usize], segment: &Segment) -> Option<usize> {
+        let node = self.node(container);
+        match (node.kind(), segment) {
+            (NodeKind::Object, Segment::Key(key)) => items(node).into_iter().rev().find(|&i| {
+                matches!(&node.children()[i], SyntaxElement::Node(field) if field_name(field) == Some(key))
+            }),
+            (NodeKind::Array, Segment::Index(index)) => items(node).get(*index).copied(),
+            _ => None,
+        }
+    }
+
+    /// Top-level blocks whose fields all define variables
+    fn defs_blocks(&self) -> Vec<usize> {
+        items(&self.tree)
+            .into_iter()
+            .filter(|&i| is_defs_block(self.node(&[i])))
+            .collect()
+    }
+
+    /// The last top-level block that is not a defs block
+    fn data_block(&self) -> Result<usize> {
+        items(&self.tree)
+            .into_iter()
+            .rev()
+            .find(|&i| {
+                let node = self.node(&[i]);
+                node.kind() != NodeKind::Import && !is_defs_block(node)
+            })
+            .ok_or_else(|| edit_error("", "the document has no data block"))
+    }
+
+    /// Byte offset of the node at `indices`
+    fn offset(&self, indices: &[usize]) -> usize {
+        let mut offset = 0;
+        let mut node = &self.tree;
+        for &i in indices {
+            offset += node.children()[..i]
+                .iter()
+                .map(|child| child.to_string().len())
+                .sum::<usize>();
+            node = match &node.children()[i] {
+                SyntaxElement::Node(child) => child,
+                SyntaxElement::Token(_) => break,
+            };
+        }
+        offset
+    }
+
+    /// Whitespace reaching the column of `offset`, to align continuation lines
+    fn indent_at(&self, offset: usize) -> String {
+        let text = self.to_string();
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        text[line_start..offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect()
+    }
+
+    /// Indentation of the `,` separators in a container
+    ///
+    /// Taken from an existing separator line, or aligned with the opening bracket.
+    fn separator_indent(&self, container: &[usize]) -> String {
+        let children = self.node(container).children();
+        for (i, child) in children.iter().enumerate() {
+            if !is_token(child, TokenKind::Comma) {
+                continue;
+            }
+            match i.checked_sub(1).map(|j| &children[j]) {
+                Some(prev) if is_token(prev, TokenKind::Newline) => return String::new(),
+                Some(SyntaxElement::Token(ws))
+                    if ws.kind() == TokenKind::Whitespace
+                        && i >= 2
+                        && is_token(&children[i - 2], TokenKind::Newline) =>
+                {
+                    return ws.text().to_string()
+                }
+                _ => {}
+            }
+        }
+        self.indent_at(self.offset(container))
+    }
+
+    /// Indentation for a new item placed at `index` of a container
+    fn indent_for_item(&self, container: &[usize], index: usize) -> String {
+        let items = items(self.node(container));
+        match items.get(index) {
+            Some(&item) => {
+                let mut indices = container.to_vec();
+                indices.push(item);
+                self.indent_at(self.offset(&indices))
+            }
+            None if items.is_empty() => self.indent_at(self.offset(container)) + "  ",
+            None => self.separator_indent(container) + "  ",
+        }
+    }
+
+    // Edits
+
+    /// Replace the value of a field or an array element
+    fn replace(&mut self, container: &[usize], item: usize, value: &Value, path: &str) -> Result<()> {
+        let mut indices = container.to_vec();
+        indices.push(item);
+        if self.node(&indices).kind() != NodeKind::Field {
+            let node = render_node(value, &self.indent_at(self.offset(&indices)), path)?;
+            self.node_mut(container).children_mut()[item] = SyntaxElement::Node(node);
+            return Ok(());
+        }
+
+        match field_value(self.node(&indices)) {
+            Some(index) => {
+                indices.push(index);
+                let indent = self.indent_at(self.offset(&indices));
+                indices.pop();
+                let field = self.node_mut(&indices).children_mut();
+                match render(value, &indent, path)? {
+                    // A key without a value is the empty string
+                    text if text.is_empty() => {
+                        field.truncate(index);
+                        while field.last().is_some_and(is_trivia) {
+                            field.pop();
+                        }
+                    }
+                    text => field[index] = SyntaxElement::Node(parse_value(&text)),
+                }
+            }
+            None => {
+                let indent = self.indent_at(self.offset(&indices) + self.node(&indices).to_string().len());
+                let text = render(value, &format!("{} ", indent), path)?;
+                if !text.is_empty() {
+                    let field = self.node_mut(&indices).children_mut();
+                    field.push(token(TokenKind::Whitespace, " "));
+                    field.push(SyntaxElement::Node(parse_value(&text)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `:key value`, or `$name value` in a defs block, to the end of an object
+    fn insert_field(&mut self, container: &[usize], key: &str, value: &Value, path: &str) -> Result<()> {
+        if self.node(container).kind() != NodeKind::Object {
+            return Err(edit_error(path, "not an object"));
+        }
+        let (sigil, name) = match key.strip_prefix('$') {
+            Some(name) => ("$", name),
+            None => (":", key),
+        };
+        if !is_identifier(name) {
+            return Err(edit_error(path, "keys must be identifiers"));
+        }
+
+        let len = items(self.node(container)).len();
+        let indent = self.indent_for_item(container, len);
+        let text = format!("{}{}", sigil, name);
+        let value = render(value, &format!("{}{} ", indent, " ".repeat(text.chars().count())), path)?;
+        let text = if value.is_empty() { text } else { format!("{} {}", text, value) };
+        self.insert_item(container, len, parse_field(&text));
+        Ok(())
+    }
+
+    /// Insert a field or element before item `index` of a container, or at the end
+    fn insert_item(&mut self, container: &[usize], index: usize, node: SyntaxNode) {
+        let indent = self.separator_indent(container);
+        let separator = |out: &mut Vec<SyntaxElement>| {
+            out.push(token(TokenKind::Newline, "\n"));
+            if !indent.is_empty() {
+                out.push(token(TokenKind::Whitespace, &indent));
+            }
+            out.push(token(TokenKind::Comma, ","));
+            out.push(token(TokenKind::Whitespace, " "));
+        };
+
+        let items = items(self.node(container));
+        let children = self.node_mut(container).children_mut();
+        let mut new = Vec::new();
+
+        if items.is_empty() {
+            // `{}` becomes `{ :key value }`, keeping comments inside the brackets
+            let close = children.pop().unwrap();
+            let inner: Vec<_> = children.drain(1..).collect();
+            let comments: Vec<_> = inner
+                .into_iter()
+                .skip_while(|child| is_token(child, TokenKind::Whitespace))
+                .collect();
+            children.push(token(TokenKind::Whitespace, " "));
+            children.push(SyntaxElement::Node(node));
+            children.push(token(TokenKind::Whitespace, " "));
+            children.extend(comments);
+            children.push(close);
+        } else if let Some(&item) = items.get(index) {
+            // Take the place of the item, which moves to the next line
+            new.push(SyntaxElement::Node(node));
+            separator(&mut new);
+            children.splice(item..item, new);
+        } else {
+            // After the last item and any comment on its line
+            let last = *items.last().unwrap();
+            let mut at = last + 1;
+            let mut end = at;
+            while end < children.len() && is_inline_trivia(&children[end]) {
+                end += 1;
+            }
+            if end < children.len() && is_token(&children[end], TokenKind::Newline) {
+                at = end;
+            }
+            separator(&mut new);
+            new.push(SyntaxElement::Node(node));
+            children.splice(at..at, new);
+        }
+    }
+
+    /// Remove a field or element together with its separator
+    fn remove_item(&mut self, container: &[usize], item: usize) -> SyntaxNode {
+        let items = items(self.node(container));
+        let children = self.node_mut(container).children_mut();
+        let position = items.iter().position(|&i| i == item).unwrap();
+
+        let range = if items.len() == 1 {
+            // Leave `{}` or `[]`
+            1..children.len() - 1
+        } else if position == 0 {
+            // The next item takes over the first line
+            item..items[1]
+        } else {
+            // From the end of the previous line through any comment on this one
+            let mut end = item + 1;
+            while end < children.len() && is_inline_trivia(&children[end]) {
+                end += 1;
+            }
+            if end == children.len() || !is_token(&children[end], TokenKind::Newline) {
+                end = item + 1;
+            }
+            let mut start = item;
+            while start > 0 && is_token(&children[start - 1], TokenKind::Whitespace) {
+                start -= 1;
+            }
+            if start > 0 && is_token(&children[start - 1], TokenKind::Comma) {
+                start -= 1;
+                while start > 0 && is_token(&children[start - 1], TokenKind::Whitespace) {
+                    start -= 1;
+                }
+                if start > 0 && is_token(&children[start - 1], TokenKind::Newline) {
+                    start -= 1;
+                }
+            }
+            start..end
+        };
+
+        let removed = children.splice(range, []).find_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        });
+        removed.expect("the removed range holds the item")
+    }
+}
+
+impl PartialEq for Document {
+    fn eq(&self, other: &Self) -> bool {
+        self.tree == other.tree
+    }
+}
+
+impl Eq for Document {}
+
+impl FromStr for Document {
+    type Err = SymError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.tree)
+    }
+}
+
+/// Split a path like `server.ports[0]` or `$version` into segments
+///
+/// Keys must be names that SYM can write, and only the first may be a
+/// variable name including its `$`.
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let segments = crate::path::parse_path(path)?;
+    for (i, segment) in segments.iter().enumerate() {
+        if let Segment::Key(key) = segment {
+            let name = key.strip_prefix('$').filter(|_| i == 0).unwrap_or(key);
+            if !is_identifier(name) {
+                return Err(edit_error(path, "invalid path"));
+            }
+        }
+    }
+    Ok(segments)
+}
+
+fn edit_error(path: &str, message: &str) -> SymError {
+    SymError::EditError {
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Format a value as SYM source, aligning continuation lines with `indent`
+///
+/// The empty string renders as nothing, like a key written without a value.
+fn render(value: &Value, indent: &str, path: &str) -> Result<String> {
+    let text = match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Float(f) if f.is_nan() => "nan".to_string(),
+        Value::Float(f) if f.is_infinite() => if *f > 0.0 { "inf" } else { "-inf" }.to_string(),
+        Value::Float(f) => {
+            // Keep a decimal point, so the value reads back as a float
+            let text = f.to_string();
+            if text.contains(['.', 'e']) { text } else { format!("{}.0", text) }
+        }
+        Value::String(s) => {
+            let mut lines = s.split('\n');
+            let mut text = escape_sym_string(lines.next().unwrap_or(""));
+            for line in lines {
+                text.push('\n');
+                if line.is_empty() {
+                    continue;
+                }
+                text.push_str(indent);
+                // Keep leading whitespace, and stop the line reading as a separator or comment
+                if line.starts_with([' ', '\t', ',', '/']) {
+                    text.push('\\');
+                }
+                text.push_str(&escape_inline(line));
+            }
+            text
+        }
+        Value::Symbol(s) => format!(":{}", s),
+        Value::Array(items) if items.is_empty() => "[]".to_string(),
+        Value::Array(items) => {
+            let inner = format!("{}  ", indent);
+            let mut text = String::from("[ ");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    text.push_str(&format!("\n{}, ", indent));
+                }
+                let item = render(item, &inner, path)?;
+                if item.is_empty() {
+                    return Err(edit_error(path, "an array element cannot be an empty string"));
+                }
+                text.push_str(&item);
+            }
+            text.push_str(&format!("\n{}]", indent));
+            text
+        }
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        Value::Object(map) => {
+            let mut text = String::from("{ ");
+            for (i, (key, value)) in map.iter().enumerate() {
+                if !is_identifier(key) {
+                    return Err(edit_error(path, &format!("key {:?} is not an identifier", key)));
+                }
+                if i > 0 {
+                    text.push_str(&format!("\n{}, ", indent));
+                }
+                text.push(':');
+                text.push_str(key);
+                let inner = format!("{}  {} ", indent, " ".repeat(key.chars().count() + 1));
+                let value = render(value, &inner, path)?;
+                if !value.is_empty() {
+                    text.push(' ');
+                    text.push_str(&value);
+                }
+            }
+            text.push_str(&format!("\n{}}}", indent));
+            text
+        }
+    };
+    Ok(text)
+}
+
+/// Render a value and parse it into a syntax node
+fn render_node(value: &Value, indent: &str, path: &str) -> Result<SyntaxNode> {
+    match render(value, indent, path)? {
+        text if text.is_empty() => Err(edit_error(path, "an array element cannot be an empty string")),
+        text => Ok(parse_value(&text)),
+    }
+}
+
+/// Parse the source of a single value
+///
+/// The value is parsed as an array element, so that text like `@import`
+/// cannot be mistaken for a directive.
+fn parse_value(text: &str) -> SyntaxNode {
+    first_node(&parse_wrapped(&format!("[ {}\n]", text)))
+}
+
+/// Parse the source of a single field
+fn parse_field(text: &str) -> SyntaxNode {
+    first_node(&parse_wrapped(&format!("{{ {}\n}}", text)))
+}
+
+/// Parse rendered source, without a depth limit: the document's own limits
+/// are checked when the edit is committed
+fn parse_wrapped(text: &str) -> SyntaxNode {
+    let options = ParseOptions::new().limits(Limits {
+        max_depth: usize::MAX,
+        ..Limits::default()
+    });
+    first_node(&cst::parse_with_options(text, &options).expect("rendered values are valid SYM"))
+}
+
+fn first_node(node: &SyntaxNode) -> SyntaxNode {
+    node.child_nodes().next().cloned().expect("a rendered value parses")
+}
+
+fn token(kind: TokenKind, text: &str) -> SyntaxElement {
+    SyntaxElement::Token(SyntaxToken::new(kind, text))
+}
+
+fn is_token(element: &SyntaxElement, kind: TokenKind) -> bool {
+    matches!(element, SyntaxElement::Token(token) if token.kind() == kind)
+}
+
+fn is_trivia(element: &SyntaxElement) -> bool {
+    matches!(element, SyntaxElement::Token(token) if token.kind().is_trivia())
+}
+
+/// Whitespace and comments that do not end the line
+fn is_inline_trivia(element: &SyntaxElement) -> bool {
+    is_trivia(element) && !is_token(element, TokenKind::Newline)
+}
+
+/// Indices of the child nodes: fields of an object, elements of an array,
+/// or the imports and blocks of a document
+fn items(node: &SyntaxNode) -> Vec<usize> {
+    node.children()
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| matches!(child, SyntaxElement::Node(_)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The key of a field without its `:`, or the variable name with its `$`
+fn field_name(field: &SyntaxNode) -> Option<&str> {
+    match field.children().first()? {
+        SyntaxElement::Token(token) if token.kind() == TokenKind::Key => Some(&token.text()[1..]),
+        SyntaxElement::Token(token) if token.kind() == TokenKind::VarName => Some(token.text()),
+        _ => None,
+    }
+}
+
+/// Index of the value node of a field
+fn field_value(field: &SyntaxNode) -> Option<usize> {
+    field
+        .children()
+        .iter()
+        .position(|child| matches!(child, SyntaxElement::Node(_)))
+}
+
+fn is_defs_block(node: &SyntaxNode) -> bool {
+    let fields: Vec<_> = node.child_nodes().collect();
+    node.kind() == NodeKind::Object
+        && !fields.is_empty()
+        && fields
+            .iter()
+            .all(|field| field_name(field).is_some_and(|name| name.starts_with('$')))
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+
+    fn edit(input: &str, f: impl FnOnce(&mut Document)) -> String {
+        let mut doc = Document::parse(input).unwrap();
+        f(&mut doc);
+        doc.to_string()
+    }
+
+    #[test]
+    fn test_set_keeps_layout() {
+        let input = "// app\n{ $version v1.0.0 }\n\n{ :name   app  // the name\n, :version $version\n, :port 0x1F90\n}\n";
+        let output = edit(input, |doc| {
+            doc.set("port", Value::Int(9000)).unwrap();
+            doc.set("$version", Value::String("v1.1.0".into())).unwrap();
+        });
+        assert_eq!(
+            output,
+            "// app\n{ $version v1.1.0 }\n\n{ :name   app  // the name\n, :version $version\n, :port 9000\n}\n"
+        );
+        assert_eq!(crate::parse(&output).unwrap().as_object().unwrap()["version"], Value::String("v1.1.0".into()));
+    }
+
+    #[test]
+    fn test_get() {
+        let doc = Document::parse("{ :server\n  { :ports [ 80, 0x1bb ]\n  }\n}").unwrap();
+        assert_eq!(doc.get("server.ports[1]").unwrap().to_string(), "0x1bb");
+        assert_eq!(doc.get("server").unwrap().kind(), NodeKind::Object);
+        assert!(doc.get("server.host").is_none());
+        assert!(doc.get("server.ports[2]").is_none());
+        assert!(doc.get("server..ports").is_none());
+    }
+
+    #[test]
+    fn test_insert_and_append() {
+        let input = "{ :name app\n, :tags [ :web ]  // roles\n, :env {}\n}";
+        let output = edit(input, |doc| {
+            doc.insert("debug", Value::Bool(false)).unwrap();
+            doc.append("tags", Value::Symbol("api".into())).unwrap();
+            doc.insert("tags[0]", Value::Symbol("edge".into())).unwrap();
+            doc.set("env.region", Value::String("eu-west".into())).unwrap();
+            assert!(doc.insert("name", Value::Null).is_err());
+        });
+        assert_eq!(
+            output,
+            "{ :name app\n, :tags [ :edge\n        , :web\n        , :api ]  // roles\n, :env { :region eu-west }\n, :debug false\n}"
+        );
+    }
+
+    #[test]
+    fn test_set_nested_value() {
+        let output = edit("{ :name app\n}\n", |doc| {
+            let db = IndexMap::from([("host".to_string(), Value::String("localhost".into()))]);
+            doc.set("db", Value::Object(db)).unwrap();
+            doc.set("motd", Value::String("Hello\n  indented\n, not a separator".into())).unwrap();
+        });
+        assert_eq!(
+            output,
+            "{ :name app\n, :db { :host localhost\n      }\n, :motd Hello\n        \\  indented\n        \\, not a separator\n}\n"
+        );
+        let value = crate::parse(&output).unwrap();
+        assert_eq!(
+            value.as_object().unwrap()["motd"].as_str(),
+            Some("Hello\n  indented\n, not a separator")
+        );
+    }
+
+    #[test]
+    fn test_remove() {
+        let input = "{ :a 1  // first\n, :b 2  // second\n, :c [ x\n     , y\n     ]\n}";
+        let output = edit(input, |doc| {
+            assert_eq!(doc.remove("b").unwrap().unwrap().to_string(), "2");
+            assert_eq!(doc.remove("c[0]").unwrap().unwrap().to_string(), "x");
+            assert!(doc.remove("missing").unwrap().is_none());
+        });
+        assert_eq!(output, "{ :a 1  // first\n, :c [ y\n     ]\n}");
+
+        let output = edit(input, |doc| {
+            doc.remove("a").unwrap();
+        });
+        assert_eq!(output, "{ :b 2  // second\n, :c [ x\n     , y\n     ]\n}");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut doc = Document::parse("{ :a 1\n, :list [ x ]\n}").unwrap();
+        assert!(matches!(doc.set("a.b", Value::Null), Err(SymError::EditError { .. })));
+        assert!(doc.append("a", Value::Null).is_err());
+        assert!(doc.set("list[3]", Value::Null).is_err());
+        assert!(doc.set("$missing", Value::Null).is_err());
+        assert!(doc.set("bad key", Value::Null).is_err());
+        assert_eq!(doc.to_string(), "{ :a 1\n, :list [ x ]\n}");
+    }
+
+    #[test]
+    fn test_parse_with_options() {
+        let options = ParseOptions::new().limits(Limits { max_depth: 2, ..Limits::default() });
+        assert!(Document::parse_with_options("{ :a [ [ 1 ] ] }", &options).is_err());
+
+        // Edits are checked against the options the document was parsed with
+        let mut doc = Document::parse_with_options("{ :a [ 1 ] }", &options).unwrap();
+        let nested = Value::Array(vec![Value::Array(vec![Value::Int(1)])]);
+        assert!(matches!(doc.set("a", nested.clone()), Err(SymError::EditError { .. })));
+        assert_eq!(doc.to_string(), "{ :a [ 1 ] }");
+        doc.set("a", Value::Array(vec![Value::Int(2)])).unwrap();
+        assert_eq!(doc.to_string(), "{ :a [ 2\n     ] }");
+
+        let mut doc = Document::parse("{ :a [ 1 ] }").unwrap();
+        doc.set("a", nested).unwrap();
+    }
+}
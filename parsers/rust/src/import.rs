@@ -0,0 +1,296 @@
+//! Import resolution for `@import` directives
+
+use crate::span::{Location, Span};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Locates and loads the files named by `@import` directives
+///
+/// The parser consults its resolver for every import, so embedders can serve
+/// imports from memory, from assets compiled into the binary, from an archive
+/// or from a restricted directory.
+pub trait Resolver {
+    /// Turn an import path into a stable file id
+    ///
+    /// `importer` is the id of the importing file, or `None` for the root
+    /// document. Ids identify files for cycle detection and de-duplication.
+    fn resolve(&self, importer: Option<&Path>, path: &str) -> io::Result<PathBuf>;
+
+    /// The id of a root document the caller has already read from `path`
+    ///
+    /// Imports in the document are resolved relative to it. The default
+    /// normalises `path` without touching the file.
+    fn root(&self, path: &Path) -> PathBuf {
+        normalize(path)
+    }
+
+    /// Load the source of a file previously returned by `resolve`
+    fn load(&self, id: &Path) -> io::Result<String>;
+
+    /// Import paths of the files in the directory `path` points into,
+    /// written the way `path` is
+    ///
+    /// Used to suggest a fix when `path` cannot be resolved. The default
+    /// lists nothing.
+    fn siblings(&self, importer: Option<&Path>, path: &str) -> Vec<String> {
+        let _ = (importer, path);
+        Vec::new()
+    }
+}
+
+/// Import paths similar to `path`, which could not be resolved
+pub(crate) fn suggest_paths(resolver: &dyn Resolver, importer: Option<&Path>, path: &str) -> Vec<String> {
+    let siblings = resolver.siblings(importer, path);
+    crate::diagnostic::suggest(path, siblings.iter().map(String::as_str))
+}
+
+/// The directory part of an import path, with its trailing `/`
+fn dir_prefix(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..=i])
+}
+
+/// Resolves imports on the local filesystem (the default)
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver {
+    root: Option<PathBuf>,
+}
+
+impl FsResolver {
+    /// Resolve imports relative to the importing file, anywhere on disk
+    pub const fn new() -> Self {
+        Self { root: None }
+    }
+
+    /// Only allow imports of files inside `root`
+    ///
+    /// Relative paths of the root document are taken from `root` as well.
+    pub fn sandboxed<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: Some(root.into()),
+        }
+    }
+}
+
+impl FsResolver {
+    /// The directory paths are relative to
+    fn base<'p>(&'p self, importer: Option<&'p Path>) -> &'p Path {
+        match (importer.and_then(Path::parent), &self.root) {
+            (Some(dir), _) => dir,
+            (None, Some(root)) => root.as_path(),
+            (None, None) => Path::new(""),
+        }
+    }
+
+    /// Fail unless `path` is inside the sandbox
+    fn check_sandbox(&self, path: &Path) -> io::Result<()> {
+        if let Some(root) = &self.root {
+            if !path.starts_with(root.canonicalize()?) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is outside of {}", path.display(), root.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Resolver for FsResolver {
+    /// The canonical path of the root file, which need not be in the sandbox
+    fn root(&self, path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| normalize(path))
+    }
+
+    fn resolve(&self, importer: Option<&Path>, path: &str) -> io::Result<PathBuf> {
+        let id = self.base(importer).join(path).canonicalize()?;
+        self.check_sandbox(&id)?;
+        Ok(id)
+    }
+
+    fn load(&self, id: &Path) -> io::Result<String> {
+        std::fs::read_to_string(id)
+    }
+
+    fn siblings(&self, importer: Option<&Path>, path: &str) -> Vec<String> {
+        let prefix = dir_prefix(path);
+        let dir = self.base(importer).join(prefix);
+        let Ok(dir) = dir.canonicalize() else {
+            return Vec::new();
+        };
+        if self.check_sandbox(&dir).is_err() {
+            return Vec::new();
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .map(|entry| format!("{}{}", prefix, entry.file_name().to_string_lossy()))
+            .collect()
+    }
+}
+
+/// Serves imports from an in-memory set of files
+///
+/// Paths are normalised, so `./base.sym` and `shared/../base.sym` name the
+/// same file. Useful for tests and for configs embedded with `include_str!`.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryResolver {
+    /// Create an empty resolver
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file, builder style
+    pub fn with_file<P: AsRef<Path>, S: Into<String>>(mut self, path: P, source: S) -> Self {
+        self.insert(path, source);
+        self
+    }
+
+    /// Add or replace a file
+    pub fn insert<P: AsRef<Path>, S: Into<String>>(&mut self, path: P, source: S) {
+        self.files.insert(normalize(path.as_ref()), source.into());
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn resolve(&self, importer: Option<&Path>, path: &str) -> io::Result<PathBuf> {
+        let base = importer.and_then(Path::parent).unwrap_or(Path::new(""));
+        let id = normalize(&base.join(path));
+        if self.files.contains_key(&id) {
+            Ok(id)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such file: {}", id.display()),
+            ))
+        }
+    }
+
+    fn load(&self, id: &Path) -> io::Result<String> {
+        self.files.get(id).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such file: {}", id.display()),
+            )
+        })
+    }
+
+    fn siblings(&self, importer: Option<&Path>, path: &str) -> Vec<String> {
+        let prefix = dir_prefix(path);
+        let base = importer.and_then(Path::parent).unwrap_or(Path::new(""));
+        let dir = normalize(&base.join(prefix));
+        self.files
+            .keys()
+            .filter(|id| id.parent() == Some(dir.as_path()))
+            .filter_map(|id| id.file_name())
+            .map(|name| format!("{}{}", prefix, name.to_string_lossy()))
+            .collect()
+    }
+}
+
+/// Lexically resolve `.` and `..` components
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// The files a document depends on through `@import`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportGraph {
+    /// Every file reachable from the root, root first, in discovery order
+    pub files: Vec<PathBuf>,
+    /// `(importer, imported)` pairs in declaration order
+    pub edges: Vec<(PathBuf, PathBuf)>,
+    /// Files in the order they are merged: imports before their importer, root last
+    pub load_order: Vec<PathBuf>,
+}
+
+impl ImportGraph {
+    /// The root file the graph was built from
+    pub fn root(&self) -> Option<&Path> {
+        self.files.first().map(PathBuf::as_path)
+    }
+
+    /// Files imported directly by `file`, in declaration order
+    pub fn imports_of<'g>(&'g self, file: &'g Path) -> impl Iterator<Item = &'g Path> + 'g {
+        self.edges
+            .iter()
+            .filter(move |(from, _)| from == file)
+            .map(|(_, to)| to.as_path())
+    }
+
+    /// Every file the root depends on, excluding the root itself
+    pub fn dependencies(&self) -> &[PathBuf] {
+        self.files.get(1..).unwrap_or(&[])
+    }
+}
+
+/// Import state shared by a root parser and the parsers of its imports
+#[derive(Default)]
+pub(crate) struct ImportContext {
+    /// Files currently being loaded, outermost first
+    pub(crate) stack: Vec<PathBuf>,
+    /// Graph of everything loaded so far
+    pub(crate) graph: ImportGraph,
+    /// The `@import` directives through which each file was first reached
+    pub(crate) sites: HashMap<PathBuf, Vec<Location>>,
+}
+
+impl ImportContext {
+    /// Start a context for a root file
+    pub(crate) fn for_root(path: PathBuf) -> Self {
+        Self {
+            stack: vec![path.clone()],
+            graph: ImportGraph {
+                files: vec![path],
+                ..ImportGraph::default()
+            },
+            sites: HashMap::new(),
+        }
+    }
+
+    /// Record that `path` is imported by the directive at `span` of the current file
+    pub(crate) fn record_site(&mut self, path: &Path, span: &Span) {
+        let mut stack = self.import_stack(self.stack.last().map(PathBuf::as_path));
+        stack.push(Location::from(span));
+        self.sites.insert(path.to_path_buf(), stack);
+    }
+
+    /// The `@import` directives leading to `file`, outermost first
+    pub(crate) fn import_stack(&self, file: Option<&Path>) -> Vec<Location> {
+        file.and_then(|f| self.sites.get(f)).cloned().unwrap_or_default()
+    }
+
+    /// The chain of imports leading back to `path`, if loading it would form a cycle
+    pub(crate) fn cycle_to(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        let start = self.stack.iter().position(|p| p == path)?;
+        let mut chain = self.stack[start..].to_vec();
+        chain.push(path.to_path_buf());
+        Some(chain)
+    }
+}
+
+/// Format an import chain as `a.sym -> b.sym -> a.sym`
+pub(crate) fn format_chain(chain: &[PathBuf]) -> String {
+    chain
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
@@ -0,0 +1,355 @@
+//! Byte-level scanning shared by the parser and the syntax tree
+//!
+//! Each function looks at the start of the remaining input and returns how
+//! many bytes a token takes, reading no further than the token itself and
+//! one character after it; only [`value_follows`] looks ahead past blank
+//! lines and comments. Nothing is allocated, except to drop the `_`
+//! separators of a number that has them.
+
+use crate::diagnostic::ErrorCode;
+use crate::options::ParseOptions;
+use crate::Value;
+use std::borrow::Cow;
+
+/// The first character of `s`, without decoding when it is ASCII
+pub(crate) fn first_char(s: &str) -> Option<char> {
+    match s.as_bytes().first() {
+        Some(&byte) if byte.is_ascii() => Some(byte as char),
+        Some(_) => s.chars().next(),
+        None => None,
+    }
+}
+
+/// Whether `c` may start a key, symbol or variable name
+pub(crate) fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Whether `c` may continue a key, symbol or variable name
+pub(crate) fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Length of the key, symbol or variable name at the start of `s`, if one
+/// starts there
+pub(crate) fn name_len(s: &str) -> Option<usize> {
+    if !s.starts_with(is_name_start) {
+        return None;
+    }
+    Some(s.find(|c| !is_name_char(c)).unwrap_or(s.len()))
+}
+
+/// Length of the spaces and tabs at the start of `s`
+pub(crate) fn horizontal_whitespace_len(s: &str) -> usize {
+    s.bytes().take_while(|&b| b == b' ' || b == b'\t').count()
+}
+
+/// Length of the rest of the line, up to a `\n` or `\r`
+pub(crate) fn line_len(s: &str) -> usize {
+    s.find(['\n', '\r']).unwrap_or(s.len())
+}
+
+/// Length of the `//` comment at the start of `s`, up to the end of the line
+pub(crate) fn line_comment_len(s: &str) -> usize {
+    s.find('\n').unwrap_or(s.len())
+}
+
+/// Length of the `/* ... */` comment at the start of `s`, or of the rest of
+/// the input if it is not closed
+pub(crate) fn block_comment_len(s: &str) -> usize {
+    s[2..].find("*/").map_or(s.len(), |i| i + 4)
+}
+
+/// Length of `keyword` at the start of `s`, unless a name continues after it
+pub(crate) fn keyword_len(s: &str, keyword: &str) -> Option<usize> {
+    let after = s.strip_prefix(keyword)?;
+    match first_char(after) {
+        Some(c) if is_name_char(c) => None,
+        _ => Some(keyword.len()),
+    }
+}
+
+/// Whether a value follows a key in `s`: on the same line, or on a later
+/// line after blank lines and comments, unless a separator or closing
+/// bracket comes first
+pub(crate) fn value_follows(s: &str) -> bool {
+    let mut rest = s;
+    if s.starts_with(['\n', '\r']) {
+        loop {
+            rest = rest.trim_start();
+            if rest.starts_with("//") {
+                rest = &rest[line_comment_len(rest)..];
+            } else if rest.starts_with("/*") {
+                rest = &rest[block_comment_len(rest)..];
+            } else {
+                break;
+            }
+        }
+    }
+    !matches!(first_char(rest), None | Some(',' | '}' | ']'))
+}
+
+/// Length of the plain text at the start of a string, up to the next
+/// character with a meaning: a bracket, an escape, a reference or whitespace
+///
+/// The first character is always taken, whatever it is.
+pub(crate) fn plain_text_len(s: &str) -> usize {
+    let first = first_char(s).map_or(0, char::len_utf8);
+    s[first..]
+        .find(|c: char| matches!(c, '}' | ']' | '\\' | '$') || c.is_whitespace())
+        .map_or(s.len(), |i| first + i)
+}
+
+/// Whether `s` starts with a `$name` reference
+pub(crate) fn starts_var_ref(s: &str) -> bool {
+    s.strip_prefix('$').is_some_and(|rest| rest.starts_with(is_name_start))
+}
+
+/// Length of the `$name` reference at the start of `rest`
+///
+/// Hyphens belong to the name only when another name character follows.
+pub(crate) fn var_ref_len(rest: &str) -> usize {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut len = 1;
+    let mut chars = rest[1..].chars().peekable();
+    while let Some(c) = chars.next() {
+        let continues = is_name_char(c) || (c == '-' && chars.peek().is_some_and(|&n| is_name_char(n)));
+        if !continues {
+            break;
+        }
+        len += c.len_utf8();
+    }
+    len
+}
+
+/// The parts of an environment variable reference: `${NAME}`,
+/// `${NAME:-default}` or `${NAME:?message}`
+pub(crate) struct EnvRefToken<'s> {
+    pub(crate) name: &'s str,
+    /// `-` or `?`, with the text after it
+    pub(crate) fallback: Option<(char, &'s str)>,
+    /// Length of the whole reference, including the closing `}`
+    pub(crate) len: usize,
+}
+
+/// Scan the environment variable reference at the start of `s`
+///
+/// On error, returns how many bytes into `s` the problem is, with its code
+/// and message.
+pub(crate) fn env_ref(s: &str) -> std::result::Result<EnvRefToken<'_>, (usize, ErrorCode, &'static str)> {
+    let name_len = s[2..]
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len() - 2);
+    if name_len == 0 {
+        return Err((2, ErrorCode::ExpectedEnvName, "Expected environment variable name after '${'"));
+    }
+
+    let mut len = 2 + name_len;
+    let mut fallback = None;
+    let after = &s[len..];
+    if let Some(kind @ ('-' | '?')) = after.strip_prefix(':').and_then(first_char) {
+        let text = &after[2..];
+        let text = &text[..text.find(['}', '\n']).unwrap_or(text.len())];
+        fallback = Some((kind, text));
+        len += 2 + text.len();
+    }
+    if !s[len..].starts_with('}') {
+        return Err((len, ErrorCode::UnclosedEnvRef, "Unclosed environment variable reference, expected '}'"));
+    }
+    Ok(EnvRefToken {
+        name: &s[2..2 + name_len],
+        fallback,
+        len: len + 1,
+    })
+}
+
+/// The number, boolean or null at the start of a value, with its length
+///
+/// `inf`, `nan` and hex numbers are literals only if `options` enable them.
+pub(crate) fn parse_literal(s: &str, options: &ParseOptions) -> Option<(Value, usize)> {
+    let hex = s.starts_with("0x") || s.starts_with("0X");
+    if let Some(value) = try_parse_number(s).filter(|_| options.hex || !hex) {
+        return Some((value, number_len(s)));
+    }
+
+    // Keywords, only when no name continues after them
+    let keywords = [
+        ("true", true, Value::Bool(true)),
+        ("false", true, Value::Bool(false)),
+        ("null", true, Value::Null),
+        ("inf", options.inf, Value::Float(f64::INFINITY)),
+        ("-inf", options.inf, Value::Float(f64::NEG_INFINITY)),
+        ("nan", options.nan, Value::Float(f64::NAN)),
+    ];
+    keywords.into_iter().find_map(|(keyword, enabled, value)| {
+        let len = keyword_len(s, keyword).filter(|_| enabled)?;
+        Some((value, len))
+    })
+}
+
+/// Length of the number at the start of `s`, as far as the parser consumes it
+///
+/// This can be longer than the part [`try_parse_number`] reads: `1.0.0` is
+/// consumed whole but read as `1.0`.
+pub(crate) fn number_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    if let Some(radix) = radix_prefix(bytes) {
+        return 2 + digits_len(&bytes[2..], radix);
+    }
+
+    let mut len = usize::from(bytes.first() == Some(&b'-'));
+    let mut in_exponent = false;
+    while let Some(&b) = bytes.get(len) {
+        if b.is_ascii_digit() || b == b'_' || (b == b'.' && !in_exponent) {
+            len += 1;
+        } else if (b == b'e' || b == b'E') && !in_exponent {
+            in_exponent = true;
+            len += 1;
+            if matches!(bytes.get(len), Some(b'+' | b'-')) {
+                len += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    len
+}
+
+/// Try to parse a number from the start of a string
+///
+/// A number followed by identifier characters is not one, so `3px` and
+/// `0xffgg` are left to be read as strings.
+pub(crate) fn try_parse_number(s: &str) -> Option<Value> {
+    let bytes = s.as_bytes();
+
+    // Hex, binary and octal
+    if let Some(radix) = radix_prefix(bytes) {
+        let len = digits_len(&bytes[2..], radix);
+        if len == 0 || continues_name(&s[2 + len..]) {
+            return None;
+        }
+        let digits = without_underscores(&s[2..2 + len]);
+        return i64::from_str_radix(&digits, radix).ok().map(Value::Int);
+    }
+
+    // Decimal (int or float), with an optional negative sign
+    let mut len = usize::from(bytes.first() == Some(&b'-'));
+    let mut is_float = false;
+
+    // Integer part
+    let mut has_digits = false;
+    while let Some(&b) = bytes.get(len) {
+        if b.is_ascii_digit() {
+            has_digits = true;
+        } else if b != b'_' || !has_digits {
+            break;
+        }
+        len += 1;
+    }
+    if !has_digits {
+        return None;
+    }
+
+    // Decimal part, only if a digit follows the dot
+    if bytes.get(len) == Some(&b'.') && bytes.get(len + 1).is_some_and(u8::is_ascii_digit) {
+        is_float = true;
+        len += 1;
+        len += bytes[len..]
+            .iter()
+            .take_while(|&&b| b.is_ascii_digit() || b == b'_')
+            .count();
+    }
+
+    // Exponent part
+    if matches!(bytes.get(len), Some(b'e' | b'E')) {
+        is_float = true;
+        len += 1;
+        if matches!(bytes.get(len), Some(b'+' | b'-')) {
+            len += 1;
+        }
+        len += bytes[len..].iter().take_while(|b| b.is_ascii_digit()).count();
+    }
+
+    // Make sure the next char isn't an identifier char
+    if continues_name(&s[len..]) {
+        return None;
+    }
+
+    let text = without_underscores(&s[..len]);
+    if is_float {
+        text.parse::<f64>().ok().map(Value::Float)
+    } else {
+        text.parse::<i64>().ok().map(Value::Int)
+    }
+}
+
+/// The radix of a `0x`, `0b` or `0o` prefix at the start of `bytes`
+fn radix_prefix(bytes: &[u8]) -> Option<u32> {
+    match bytes {
+        [b'0', b'x' | b'X', ..] => Some(16),
+        [b'0', b'b' | b'B', ..] => Some(2),
+        [b'0', b'o' | b'O', ..] => Some(8),
+        _ => None,
+    }
+}
+
+/// Length of the digits of `radix` and `_` separators at the start of `bytes`
+fn digits_len(bytes: &[u8], radix: u32) -> usize {
+    bytes
+        .iter()
+        .take_while(|&&b| (b as char).is_digit(radix) || b == b'_')
+        .count()
+}
+
+/// Whether `s` starts with a character that would continue an identifier
+fn continues_name(s: &str) -> bool {
+    first_char(s).is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn without_underscores(s: &str) -> Cow<'_, str> {
+    if s.contains('_') {
+        Cow::Owned(s.replace('_', ""))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(try_parse_number("42\n,"), Some(Value::Int(42)));
+        assert_eq!(try_parse_number("-1_000"), Some(Value::Int(-1000)));
+        assert_eq!(try_parse_number("0x1F90 // port"), Some(Value::Int(8080)));
+        assert_eq!(try_parse_number("0b1010"), Some(Value::Int(10)));
+        assert_eq!(try_parse_number("0o17"), Some(Value::Int(15)));
+        assert_eq!(try_parse_number("1.5e3"), Some(Value::Float(1500.0)));
+        assert_eq!(try_parse_number("1.0.0"), Some(Value::Float(1.0)));
+        assert_eq!(try_parse_number("3px"), None);
+        assert_eq!(try_parse_number("0x"), None);
+        assert_eq!(try_parse_number("0b1012"), None);
+        assert_eq!(try_parse_number("0o79"), None);
+        assert_eq!(try_parse_number("0xffgg"), None);
+        assert_eq!(try_parse_number("-"), None);
+
+        assert_eq!(number_len("1.0.0 release"), 5);
+        assert_eq!(number_len("0xffé,"), 4);
+        assert_eq!(number_len("0b1012,"), 5);
+        assert_eq!(number_len("0x1F_90 // port"), 7);
+        assert_eq!(number_len("-2e-3}"), 5);
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(keyword_len("true\n", "true"), Some(4));
+        assert_eq!(keyword_len("trueish", "true"), None);
+        assert_eq!(keyword_len("null-value", "null"), None);
+        assert_eq!(horizontal_whitespace_len(" \t // x"), 3);
+        assert_eq!(line_comment_len("// x\n, y"), 4);
+        assert_eq!(block_comment_len("/* x */ y"), 7);
+        assert_eq!(block_comment_len("/* x"), 4);
+        assert_eq!(first_char("é"), Some('é'));
+    }
+}
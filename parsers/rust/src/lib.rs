@@ -2,41 +2,311 @@
 //!
 //! A parser for the SYM data format - a less verbose, easier to read alternative to JSON.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::ops::Index;
+use std::sync::Arc;
 use thiserror::Error;
 
 pub mod parser;
+pub mod borrowed;
 pub mod convert;
+pub mod cst;
+pub mod de;
+pub mod diagnostic;
+pub mod edit;
+pub mod import;
+mod lexer;
+pub mod options;
+pub mod path;
+pub mod query;
+pub mod span;
+pub mod stream;
+
+pub use borrowed::BorrowedValue;
+pub use de::from_value;
+pub use diagnostic::{Diagnostic, ErrorCode, ErrorKind, Warning, WarningCode};
+pub use import::{FsResolver, ImportGraph, MemoryResolver, Resolver};
+pub use indexmap::IndexMap;
+pub use options::{DuplicateKeys, Limit, Limits, ParseOptions, UndefinedVariables};
+pub use parser::{Parsed, Recovered};
+pub use query::{Match, Query};
+pub use span::{Location, Span, SpannedField, SpannedKind, SpannedName, SpannedValue};
+pub use stream::{ArrayElements, Event, Events};
 
 /// Errors that can occur during parsing
+///
+/// Every error found in the source carries a [`Location`]; errors raised in
+/// an imported file name that file and the `@import` directives leading to it.
 #[derive(Error, Debug)]
 pub enum SymError {
-    #[error("Parse error at line {line}, column {col}: {message}")]
     ParseError {
-        line: usize,
-        col: usize,
         message: String,
+        code: ErrorCode,
+        location: Location,
+        /// Corrected spellings of what was written at the location
+        suggestions: Vec<String>,
+    },
+
+    UndefinedVariable {
+        name: String,
+        location: Location,
+        /// Defined variables with similar names, closest first
+        suggestions: Vec<String>,
+    },
+
+    NonScalarInterpolation { name: String, location: Location },
+
+    MissingEnvVar {
+        name: String,
+        message: String,
+        location: Location,
     },
 
-    #[error("Undefined variable: ${0}")]
-    UndefinedVariable(String),
+    VariableCycle {
+        /// The variables in the cycle, starting and ending with the same name
+        names: Vec<String>,
+        /// The reference that closes the cycle
+        location: Location,
+    },
+
+    DuplicateVariable { name: String, location: Location },
+
+    DuplicateKey { key: String, location: Location },
+
+    AppendToNonArray { key: String, location: Location },
 
-    #[error("Duplicate variable without override: ${0} (use ${{0}}! to override)")]
-    DuplicateVariable(String),
+    EditError { path: String, message: String },
 
-    #[error("Duplicate key without override: :{0} (use :{0}! to override)")]
-    DuplicateKey(String),
+    QueryError { query: String, message: String },
 
-    #[error("Cannot append to non-array: :{0}")]
-    AppendToNonArray(String),
+    ConversionError { found: String, expected: &'static str },
+
+    DeserializeError {
+        message: String,
+        /// The key path of the value that could not be deserialized
+        path: Vec<path::Segment>,
+        /// Where the value was written, if it was parsed from source
+        location: Option<Location>,
+    },
+
+    ImportError {
+        /// The path as written in the `@import` directive
+        written: Arc<str>,
+        /// The path of the file, resolved as far as it could be
+        path: Arc<std::path::Path>,
+        source: std::io::Error,
+        /// The `@import` directive, unless the root document could not be loaded
+        location: Option<Location>,
+        /// Existing files with similar paths, written as an import path
+        suggestions: Vec<String>,
+    },
+
+    ImportCycle {
+        chain: Vec<std::path::PathBuf>,
+        /// The `@import` directive that closes the cycle
+        location: Location,
+    },
+
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+        /// Where the limit was crossed, unless it applies to the whole input
+        location: Option<Location>,
+    },
 
-    #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, SymError>;
 
+impl SymError {
+    /// The stable code identifying the kind of error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SymError::ParseError { code, .. } => *code,
+            SymError::UndefinedVariable { .. } => ErrorCode::UndefinedVariable,
+            SymError::NonScalarInterpolation { .. } => ErrorCode::NonScalarInterpolation,
+            SymError::MissingEnvVar { .. } => ErrorCode::MissingEnvVar,
+            SymError::VariableCycle { .. } => ErrorCode::VariableCycle,
+            SymError::DuplicateVariable { .. } => ErrorCode::DuplicateVariable,
+            SymError::DuplicateKey { .. } => ErrorCode::DuplicateKey,
+            SymError::AppendToNonArray { .. } => ErrorCode::AppendToNonArray,
+            SymError::EditError { .. } => ErrorCode::Edit,
+            SymError::QueryError { .. } => ErrorCode::Query,
+            SymError::ConversionError { .. } => ErrorCode::Conversion,
+            SymError::DeserializeError { .. } => ErrorCode::Deserialize,
+            SymError::ImportError { .. } => ErrorCode::Import,
+            SymError::ImportCycle { .. } => ErrorCode::ImportCycle,
+            SymError::LimitExceeded { .. } => ErrorCode::LimitExceeded,
+            SymError::IoError(_) => ErrorCode::Io,
+        }
+    }
+
+    /// The category of the error
+    pub fn kind(&self) -> ErrorKind {
+        self.code().kind()
+    }
+
+    /// Where in the source the error was found
+    ///
+    /// `None` for edits and for failures to read the input itself.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            SymError::ParseError { location, .. }
+            | SymError::UndefinedVariable { location, .. }
+            | SymError::NonScalarInterpolation { location, .. }
+            | SymError::MissingEnvVar { location, .. }
+            | SymError::VariableCycle { location, .. }
+            | SymError::DuplicateVariable { location, .. }
+            | SymError::DuplicateKey { location, .. }
+            | SymError::AppendToNonArray { location, .. }
+            | SymError::ImportCycle { location, .. } => Some(location),
+            SymError::ImportError { location, .. }
+            | SymError::LimitExceeded { location, .. }
+            | SymError::DeserializeError { location, .. } => location.as_ref(),
+            SymError::EditError { .. }
+            | SymError::QueryError { .. }
+            | SymError::ConversionError { .. }
+            | SymError::IoError(_) => None,
+        }
+    }
+
+    /// What was probably meant, for errors that look like a typo
+    ///
+    /// Each suggestion replaces the name the error is about: a variable name
+    /// without `$`, an import path, or a key with its modifier (`:key!`).
+    pub fn suggestions(&self) -> &[String] {
+        match self {
+            SymError::ParseError { suggestions, .. }
+            | SymError::UndefinedVariable { suggestions, .. }
+            | SymError::ImportError { suggestions, .. } => suggestions,
+            _ => &[],
+        }
+    }
+
+    /// Mutable access to the location, to add context as the error propagates
+    pub(crate) fn location_mut(&mut self) -> Option<&mut Location> {
+        match self {
+            SymError::ParseError { location, .. }
+            | SymError::UndefinedVariable { location, .. }
+            | SymError::NonScalarInterpolation { location, .. }
+            | SymError::MissingEnvVar { location, .. }
+            | SymError::VariableCycle { location, .. }
+            | SymError::DuplicateVariable { location, .. }
+            | SymError::DuplicateKey { location, .. }
+            | SymError::AppendToNonArray { location, .. }
+            | SymError::ImportCycle { location, .. } => Some(location),
+            SymError::ImportError { location, .. }
+            | SymError::LimitExceeded { location, .. }
+            | SymError::DeserializeError { location, .. } => location.as_mut(),
+            SymError::EditError { .. }
+            | SymError::QueryError { .. }
+            | SymError::ConversionError { .. }
+            | SymError::IoError(_) => None,
+        }
+    }
+
+    /// What went wrong, without the location and suggestions
+    ///
+    /// `Display` adds both; a [`Diagnostic`] shows them on lines of their own.
+    pub fn message(&self) -> String {
+        match self {
+            // The code already says it is a parse error
+            SymError::ParseError { message, .. } => message.clone(),
+            _ => {
+                let (head, tail) = self.message_parts();
+                head + &tail
+            }
+        }
+    }
+
+    /// The message, split where `Display` writes the location
+    fn message_parts(&self) -> (String, String) {
+        match self {
+            SymError::ParseError { message, .. } => ("Parse error".to_string(), format!(": {}", message)),
+            SymError::UndefinedVariable { name, .. } => (format!("Undefined variable ${}", name), String::new()),
+            SymError::NonScalarInterpolation { name, .. } => {
+                (format!("Cannot interpolate ${}", name), ": it is an array or object".to_string())
+            }
+            SymError::MissingEnvVar { name, message, .. } => {
+                let reason = if message.is_empty() { String::new() } else { format!(": {}", message) };
+                (format!("Environment variable {} is not set", name), reason)
+            }
+            SymError::VariableCycle { names, .. } => {
+                ("Variable reference cycle".to_string(), format!(": {}", format_names(names)))
+            }
+            SymError::DuplicateVariable { name, .. } => (
+                format!("Duplicate variable without override: ${}", name),
+                format!(" (use ${}! to override)", name),
+            ),
+            SymError::DuplicateKey { key, .. } => (
+                format!("Duplicate key without override: :{}", key),
+                format!(" (use :{}! to override)", key),
+            ),
+            SymError::AppendToNonArray { key, .. } => (format!("Cannot append to non-array: :{}", key), String::new()),
+            SymError::EditError { path, message } => (format!("Cannot edit {}: {}", path, message), String::new()),
+            SymError::QueryError { query, message } => (format!("Invalid query {}: {}", query, message), String::new()),
+            SymError::ConversionError { found, expected } => {
+                (format!("Cannot convert {} to {}", found, expected), String::new())
+            }
+            SymError::DeserializeError { message, path, .. } => {
+                (format!("Cannot deserialize {}", de::describe_path(path)), format!(": {}", message))
+            }
+            SymError::ImportError { written, path, source, .. } => {
+                (format!("Cannot import {}", format_import(written, path)), format!(": {}", source))
+            }
+            SymError::ImportCycle { chain, .. } => {
+                ("Import cycle".to_string(), format!(": {}", import::format_chain(chain)))
+            }
+            SymError::LimitExceeded { limit, max, .. } => {
+                (format!("The {} limit of {} was exceeded", limit, max), String::new())
+            }
+            SymError::IoError(error) => (format!("IO error: {}", error), String::new()),
+        }
+    }
+}
+
+impl fmt::Display for SymError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (head, tail) = self.message_parts();
+        f.write_str(&head)?;
+        if let Some(location) = self.location() {
+            write!(f, " at {}", location)?;
+        }
+        f.write_str(&tail)?;
+        let prefix = if matches!(self, SymError::UndefinedVariable { .. }) { "$" } else { "" };
+        f.write_str(&did_you_mean(prefix, self.suggestions()))
+    }
+}
+
+/// Format suggestions as `; did you mean `$a` or `$b`?`
+fn did_you_mean(prefix: &str, suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    format!("; did you mean {}?", diagnostic::format_suggestions(prefix, suggestions))
+}
+
+/// Format an import path as written, followed by the file it resolved to
+fn format_import(written: &str, path: &std::path::Path) -> String {
+    if path == std::path::Path::new(written) {
+        written.to_string()
+    } else {
+        format!("{} ({})", written, path.display())
+    }
+}
+
+/// Format variable names as `$a -> $b -> $a`
+pub(crate) fn format_names(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|n| format!("${}", n))
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
 /// A SYM value - the core AST type
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -61,8 +331,8 @@ pub enum Value {
     /// Array of values
     Array(Vec<Value>),
 
-    /// Object with string keys
-    Object(HashMap<String, Value>),
+    /// Object with string keys, in the order they were written
+    Object(IndexMap<String, Value>),
 }
 
 impl Value {
@@ -156,7 +426,64 @@ impl Value {
     }
 
     /// Try to get as object
-    pub fn as_object(&self) -> Option<&HashMap<String, Value>> {
+    pub fn as_object(&self) -> Option<&IndexMap<String, Value>> {
+        match self {
+            Value::Object(o) => Some(o),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable bool
+    pub fn as_bool_mut(&mut self) -> Option<&mut bool> {
+        match self {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable i64
+    pub fn as_int_mut(&mut self) -> Option<&mut i64> {
+        match self {
+            Value::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable f64; unlike [`as_float`](Self::as_float),
+    /// integers are not included
+    pub fn as_float_mut(&mut self) -> Option<&mut f64> {
+        match self {
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable string
+    pub fn as_string_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable symbol name
+    pub fn as_symbol_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Value::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable array
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Try to get as a mutable object
+    pub fn as_object_mut(&mut self) -> Option<&mut IndexMap<String, Value>> {
         match self {
             Value::Object(o) => Some(o),
             _ => None,
@@ -164,7 +491,7 @@ impl Value {
     }
 
     /// Deep merge another value into this one
-    /// Used for import merging
+    /// Used for import merging: objects merge recursively, arrays are appended
     pub fn deep_merge(&mut self, other: Value) {
         match (self, other) {
             (Value::Object(base), Value::Object(overlay)) => {
@@ -176,6 +503,9 @@ impl Value {
                     }
                 }
             }
+            (Value::Array(base), Value::Array(overlay)) => {
+                base.extend(overlay);
+            }
             (this, other) => {
                 *this = other;
             }
@@ -228,15 +558,276 @@ impl fmt::Display for Value {
     }
 }
 
+/// A value shared by the `Index` impls for keys and elements that are missing
+static NULL: Value = Value::Null;
+
+/// `value["key"]`: the value of a key, or `Null` if there is no such key or
+/// the value is not an object
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        self.as_object().and_then(|o| o.get(key)).unwrap_or(&NULL)
+    }
+}
+
+/// `value[0]`: an element, or `Null` if it is out of range or the value is not
+/// an array
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        self.as_array().and_then(|a| a.get(index)).unwrap_or(&NULL)
+    }
+}
+
+macro_rules! from_scalar {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self {
+                    Value::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+from_scalar! {
+    bool => Bool,
+    i8 => Int, i16 => Int, i32 => Int, i64 => Int,
+    u8 => Int, u16 => Int, u32 => Int,
+    f32 => Float, f64 => Float,
+    String => String, &str => String, Cow<'_, str> => String,
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    /// `None` becomes `Null`
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> From<IndexMap<K, V>> for Value {
+    fn from(map: IndexMap<K, V>) -> Self {
+        Value::Object(map.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> From<BTreeMap<K, V>> for Value {
+    /// Keys come out sorted
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Value::Object(map.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>, S> From<HashMap<K, V, S>> for Value {
+    /// Keys come out in the map's iteration order
+    fn from(map: HashMap<K, V, S>) -> Self {
+        Value::Object(map.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl Value {
+    /// What a value that cannot be converted is reported as: numbers by
+    /// value, anything else by its type
+    fn describe(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Bool(_) => "a boolean".to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::String(_) => "a string".to_string(),
+            Value::Symbol(_) => "a symbol".to_string(),
+            Value::Array(_) => "an array".to_string(),
+            Value::Object(_) => "an object".to_string(),
+        }
+    }
+
+    fn conversion_error(&self, expected: &'static str) -> SymError {
+        SymError::ConversionError {
+            found: self.describe(),
+            expected,
+        }
+    }
+}
+
+macro_rules! try_from_int {
+    ($($ty:ty),*) => {
+        $(
+            /// Integers that fit; floats are not converted, even whole ones
+            impl TryFrom<&Value> for $ty {
+                type Error = SymError;
+
+                fn try_from(value: &Value) -> Result<Self> {
+                    value
+                        .as_int()
+                        .and_then(|i| <$ty>::try_from(i).ok())
+                        .ok_or_else(|| value.conversion_error(stringify!($ty)))
+                }
+            }
+        )*
+    };
+}
+
+try_from_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Integers and floats
+impl TryFrom<&Value> for f64 {
+    type Error = SymError;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        value.as_float().ok_or_else(|| value.conversion_error("f64"))
+    }
+}
+
+/// Integers and floats that are within the range of `f32`, rounded to
+/// the nearest `f32`
+impl TryFrom<&Value> for f32 {
+    type Error = SymError;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        match value.as_float() {
+            Some(f) if !f.is_finite() || (f as f32).is_finite() => Ok(f as f32),
+            _ => Err(value.conversion_error("f32")),
+        }
+    }
+}
+
 /// Parse a SYM string into a Value
 pub fn parse(input: &str) -> Result<Value> {
     parser::Parser::new(input).parse()
 }
 
+/// Parse a SYM string, reporting every syntax error together with the
+/// value built from the parts that did parse
+pub fn parse_recovering(input: &str) -> Recovered {
+    parser::Parser::new(input).parse_recovering()
+}
+
+/// Parse a SYM string and deserialize its data into a `T`, see [`de`]
+pub fn from_str<T: serde::de::DeserializeOwned>(input: &str) -> Result<T> {
+    de::from_spanned(&parse_spanned(input)?)
+}
+
+/// Parse a SYM string with non-default [`ParseOptions`]
+pub fn parse_with_options(input: &str, options: &ParseOptions) -> Result<Value> {
+    let mut parser = parser::Parser::new(input);
+    parser.set_options(options.clone());
+    parser.parse()
+}
+
+/// Parse a SYM string, also returning warnings about likely mistakes
+pub fn parse_with_warnings(input: &str) -> Result<Parsed> {
+    parser::Parser::new(input).parse_with_warnings()
+}
+
+/// Parse a SYM string, keeping the source location of every value
+pub fn parse_spanned(input: &str) -> Result<SpannedValue<'_>> {
+    parser::Parser::new(input).parse_spanned()
+}
+
+/// Parse a SYM string into a value whose verbatim strings and keys borrow from `input`
+pub fn parse_borrowed(input: &str) -> Result<BorrowedValue<'_>> {
+    parser::Parser::new(input).parse_borrowed()
+}
+
+/// Read the data of a SYM string as a sequence of [`Event`]s
+pub fn events(input: &str) -> Events<'_> {
+    Events::new(parser::Parser::new(input))
+}
+
+/// Read the elements of a SYM string whose data is an array one at a time
+pub fn array_elements(input: &str) -> ArrayElements<'_> {
+    ArrayElements::new(parser::Parser::new(input))
+}
+
+/// Parse a SYM string into a lossless syntax tree that keeps comments and layout
+pub fn parse_cst(input: &str) -> Result<cst::SyntaxNode> {
+    cst::parse(input)
+}
+
 /// Parse a SYM file into a Value
+///
+/// `@import` directives are resolved relative to the file's directory.
 pub fn parse_file<P: AsRef<std::path::Path>>(path: P) -> Result<Value> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    parser::Parser::for_file(&content, path).parse()
+}
+
+/// Parse a SYM file and deserialize its data into a `T`, see [`de`]
+pub fn from_file<T: serde::de::DeserializeOwned>(path: impl AsRef<std::path::Path>) -> Result<T> {
+    de::from_spanned(&parse_file_spanned(path)?)
+}
+
+/// Parse a SYM file with non-default [`ParseOptions`]
+pub fn parse_file_with_options<P: AsRef<std::path::Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<Value> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    let mut parser = parser::Parser::for_file(&content, path);
+    parser.set_options(options.clone());
+    parser.parse()
+}
+
+/// Parse a SYM file, also returning warnings about likely mistakes
+pub fn parse_file_with_warnings<P: AsRef<std::path::Path>>(path: P) -> Result<Parsed> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    parser::Parser::for_file(&content, path).parse_with_warnings()
+}
+
+/// Parse a SYM file, keeping the source location of every value
+///
+/// Values from imported files are located in the file they were written in.
+pub fn parse_file_spanned<P: AsRef<std::path::Path>>(path: P) -> Result<SpannedValue<'static>> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    parser::Parser::for_file(&content, path)
+        .parse_spanned()
+        .map(SpannedValue::into_owned)
+}
+
+/// Parse a SYM file, loading it and everything it imports through `resolver`
+pub fn parse_file_with_resolver<P: AsRef<std::path::Path>>(
+    path: P,
+    resolver: &dyn Resolver,
+) -> Result<Value> {
+    let path = path.as_ref();
+    let written = path.to_string_lossy();
+    let import_error = |path: &std::path::Path, source, suggestions| SymError::ImportError {
+        written: written.as_ref().into(),
+        path: Arc::from(path),
+        source,
+        location: None,
+        suggestions,
+    };
+    let id = resolver.resolve(None, &written).map_err(|source| {
+        import_error(path, source, import::suggest_paths(resolver, None, &written))
+    })?;
+    let content = resolver
+        .load(&id)
+        .map_err(|source| import_error(&id, source, Vec::new()))?;
+
+    let mut parser = parser::Parser::for_file(&content, &id);
+    parser.set_resolver(resolver);
+    parser.parse()
+}
+
+/// Build the graph of files a SYM file depends on through `@import`
+pub fn import_graph<P: AsRef<std::path::Path>>(path: P) -> Result<ImportGraph> {
+    let path = path.as_ref();
     let content = std::fs::read_to_string(path)?;
-    parse(&content)
+    parser::Parser::for_file(&content, path).import_graph()
 }
 
 /// Parse a SYM string with a custom variable scope
@@ -244,6 +835,18 @@ pub fn parse_with_vars(input: &str, vars: HashMap<String, Value>) -> Result<Valu
     parser::Parser::with_vars(input, vars).parse()
 }
 
+/// Parse a SYM string with a custom variable scope, reading `${NAME}`
+/// references from `env` instead of the process environment
+pub fn parse_with_env(
+    input: &str,
+    vars: HashMap<String, Value>,
+    env: HashMap<String, String>,
+) -> Result<Value> {
+    let mut parser = parser::Parser::with_vars(input, vars);
+    parser.set_env(env);
+    parser.parse()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -296,6 +899,372 @@ mod tests {
     }
 
     #[test]
+    fn test_interpolation() {
+        let input = r#"{ $app web-api
+, $env prod
+, $region us-east
+, $image-tag v2.4.1
+, $replicas 3
+}
+{ :deploy $env-$region
+, :host db.$env.example.com
+, :image gcr.io/myproject/$app:$image-tag
+, :vpc $env-vpc
+, :summary $app runs $replicas replicas
+, :replicas $replicas
+}"#;
+        let result = parse(input).unwrap();
+        let obj = result.as_object().unwrap();
+        assert_eq!(obj.get("deploy").unwrap().as_str(), Some("prod-us-east"));
+        assert_eq!(obj.get("host").unwrap().as_str(), Some("db.prod.example.com"));
+        assert_eq!(
+            obj.get("image").unwrap().as_str(),
+            Some("gcr.io/myproject/web-api:v2.4.1")
+        );
+        assert_eq!(obj.get("vpc").unwrap().as_str(), Some("prod-vpc"));
+        assert_eq!(
+            obj.get("summary").unwrap().as_str(),
+            Some("web-api runs 3 replicas")
+        );
+        assert_eq!(obj.get("replicas").unwrap().as_int(), Some(3));
+    }
+
+    #[test]
+    fn test_variables_reference_variables() {
+        let input = r#"{ $users $base/users
+, $base https://api.$domain
+}
+{ $domain example.com
+, $timeout $default-timeout
+, $default-timeout 30
+}
+{ :users $users
+, :timeout $timeout
+}"#;
+        let result = parse(input).unwrap();
+        let obj = result.as_object().unwrap();
+        assert_eq!(
+            obj.get("users").unwrap().as_str(),
+            Some("https://api.example.com/users")
+        );
+        assert_eq!(obj.get("timeout").unwrap().as_int(), Some(30));
+    }
+
+    #[test]
+    fn test_override_refers_to_previous_definition() {
+        let input = "{ $a 1\n, $path /usr\n}\n{ $a! $a-2\n, $path! $path/local\n}\n{ $path! $path/bin\n, $both $a:$path\n}\n{ :a $a\n, :both $both\n}";
+        let result = parse(input).unwrap();
+        assert_eq!(result.get_path("a").unwrap().as_str(), Some("1-2"));
+        assert_eq!(result.get_path("both").unwrap().as_str(), Some("1-2:/usr/local/bin"));
+
+        // A value given to the parser can be extended the same way
+        let vars = HashMap::from([("env".to_string(), Value::String("prod".into()))]);
+        let result = parse_with_vars("{ $env! $env-eu }\n{ :env $env }", vars).unwrap();
+        assert_eq!(result.get_path("env").unwrap().as_str(), Some("prod-eu"));
+
+        // The first definition of a name still cannot refer to itself
+        let err = parse("{ $a x-$a }\n{ $a! $a-2 }\n{ :a $a }").unwrap_err();
+        assert!(matches!(err, SymError::VariableCycle { ref names, .. } if names.ends_with(&["a".to_string()])));
+    }
+
+    #[test]
+    fn test_variable_cycle() {
+        let input = "{ $a $b-x\n, $b $c\n, $c prefix-$a\n}\n{ :a $a }";
+        let err = parse(input).unwrap_err();
+        match &err {
+            SymError::VariableCycle { names, location } => {
+                assert_eq!(names, &["a", "b", "c", "a"]);
+                assert_eq!((location.line, location.col), (3, 13));
+            }
+            other => panic!("expected cycle, got {:?}", other),
+        }
+        assert!(err.to_string().contains("$a -> $b -> $c -> $a"));
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let input = r#"{ $env prod }
+{ :host ${DB_HOST}
+, :url postgres://${DB_USER:-admin}@${DB_HOST}/$env
+, :password ${DB_PASSWORD}
+}"#;
+        let env = HashMap::from([("DB_HOST".to_string(), "db.internal".to_string())]);
+        let result = parse_with_env(input, HashMap::new(), env).unwrap();
+        let obj = result.as_object().unwrap();
+        assert_eq!(obj.get("host").unwrap().as_str(), Some("db.internal"));
+        assert_eq!(
+            obj.get("url").unwrap().as_str(),
+            Some("postgres://admin@db.internal/prod")
+        );
+        assert_eq!(obj.get("password").unwrap().as_str(), Some(""));
+    }
+
+    #[test]
+    fn test_escaped_env_ref() {
+        let input = r#"{ :x \${HOME}
+, :y \${HOME:-/root} and ${HOME}
+, :z cost \$5
+}"#;
+        let env = HashMap::from([("HOME".to_string(), "/home/app".to_string())]);
+        let result = parse_with_env(input, HashMap::new(), env).unwrap();
+        assert_eq!(result["x"].as_str(), Some("${HOME}"));
+        assert_eq!(result["y"].as_str(), Some("${HOME:-/root} and /home/app"));
+        assert_eq!(result["z"].as_str(), Some("cost $5"));
+    }
+
+    #[test]
+    fn test_env_var_required() {
+        let input = "{ :a 1\n, :secret ${API_KEY:?set it in the deploy config}\n}";
+        let err = parse_with_env(input, HashMap::new(), HashMap::new()).unwrap_err();
+        match &err {
+            SymError::MissingEnvVar { name, location, .. } => {
+                assert_eq!(name, "API_KEY");
+                assert_eq!(location.line, 2);
+            }
+            other => panic!("expected missing env var, got {:?}", other),
+        }
+        assert!(err.to_string().ends_with(": set it in the deploy config"));
+
+        let err = parse("{ :a ${UNCLOSED }").unwrap_err();
+        assert!(matches!(err, SymError::ParseError { .. }));
+    }
+
+    #[test]
+    fn test_interpolation_escapes() {
+        let input = r#"{ $env prod }
+{ :price costs \$env or $5
+, :path \\share\\$env
+}"#;
+        let result = parse(input).unwrap();
+        let obj = result.as_object().unwrap();
+        assert_eq!(obj.get("price").unwrap().as_str(), Some("costs $env or $5"));
+        assert_eq!(obj.get("path").unwrap().as_str(), Some("\\share\\prod"));
+    }
+
+    #[test]
+    fn test_interpolation_undefined() {
+        let input = "{ $env prod }\n{ :a fine\n, :host db.$nope.example.com\n}";
+        match parse(input).unwrap_err() {
+            SymError::UndefinedVariable { name, location, .. } => {
+                assert_eq!(name, "nope");
+                assert_eq!((location.line, location.col), (3, 12));
+            }
+            other => panic!("expected undefined variable, got {:?}", other),
+        }
+
+        let input = "{ $list [ a ] }\n{ :a items: $list }";
+        let err = parse(input).unwrap_err();
+        assert!(matches!(err, SymError::NonScalarInterpolation { .. }));
+    }
+
+    #[test]
+    fn test_inline_array_variables() {
+        // A reference that is a whole element keeps the variable's value
+        let value = parse("{ $x 1 }\n{ :a [$x, $x] }").unwrap();
+        assert_eq!(value["a"], Value::from(vec![1i64, 1]));
+        let value = parse("{ $x [1] }\n{ :a [$x, $x] }").unwrap();
+        assert_eq!(value["a"], Value::Array(vec![Value::from(vec![1i64]), Value::from(vec![1i64])]));
+
+        // Within other text it is interpolated
+        let value = parse("{ $x 1 }\n{ :a [v$x, $x]\n, :b $x, welcome\n}").unwrap();
+        assert_eq!(value["a"], Value::from(vec!["v1, 1"]));
+        assert_eq!(value["b"].as_str(), Some("1, welcome"));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let err = parse("{ $app web\n, $port 80\n}\n{ :name $aap }").unwrap_err();
+        assert!(matches!(&err, SymError::UndefinedVariable { name, .. } if name == "aap"));
+        assert_eq!(err.suggestions(), ["app"]);
+        assert!(err.to_string().ends_with("did you mean `$app`?"));
+
+        let err = parse("{ :user $usr }").unwrap_err();
+        assert!(err.suggestions().is_empty());
+
+        let err = parse("{ :name* Alice\n, :age 28\n}").unwrap_err();
+        match &err {
+            SymError::ParseError { code, message, .. } => {
+                assert_eq!(*code, ErrorCode::InvalidModifier);
+                assert_eq!(message, "Invalid key modifier `*` on :name: only ! and + are allowed");
+            }
+            other => panic!("expected invalid modifier, got {:?}", other),
+        }
+        assert_eq!(err.kind(), ErrorKind::Syntax);
+        assert_eq!(err.suggestions(), [":name", ":name!", ":name+"]);
+        assert_eq!(parse("{ :tags++ [ a ] }").unwrap_err().suggestions(), [":tags+"]);
+        assert_eq!(parse("{ $port!! 80 }\n{ :a 1 }").unwrap_err().suggestions(), ["$port!"]);
+
+        let resolver = MemoryResolver::new()
+            .with_file("config/base.sym", "{ :a 1 }")
+            .with_file("config/prod.sym", "{ :b 2 }")
+            .with_file("main.sym", "@import config/bsae.sym\n{ :c 3 }");
+        let err = parse_file_with_resolver("main.sym", &resolver).unwrap_err();
+        assert_eq!(err.suggestions(), ["config/base.sym"]);
+        let err = parse_file_with_resolver("mian.sym", &resolver).unwrap_err();
+        assert_eq!(err.suggestions(), ["main.sym"]);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let input = "{ $region eu\n, $unused 1\n}\n{ :region $region\n, :db { :host a }\n, :db { :port 5 }\n, :tags [ a ]\n, :tags+ [ b ]\n, :note\n}";
+        let parsed = parse_with_warnings(input).unwrap();
+        assert_eq!(parsed.value.as_object().unwrap()["db"].as_object().unwrap().len(), 2);
+        let warnings: Vec<_> = parsed
+            .warnings
+            .iter()
+            .map(|w| (w.code, w.location.line))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (WarningCode::EmptyValue, 9),
+                (WarningCode::DuplicateKey, 6),
+                (WarningCode::UnusedVariable, 2),
+            ]
+        );
+        assert!(parsed.warnings[2].to_string().starts_with("Variable $unused is never used"));
+
+        // Merging across layers and overrides are intended
+        let source = "@import ./base.sym\n@import ./empty.sym\n@import ./base.sym\n{ :db { :port 5 }\n, :db! { :port 6 }\n}";
+        let resolver = MemoryResolver::new()
+            .with_file("base.sym", "{ :db { :host a } }")
+            .with_file("empty.sym", "{}");
+        let mut parser = parser::Parser::for_file(source, std::path::Path::new("main.sym"));
+        parser.set_resolver(&resolver);
+        let warnings: Vec<_> = parser
+            .parse_with_warnings()
+            .unwrap()
+            .warnings
+            .into_iter()
+            .map(|w| (w.code, w.location.line))
+            .collect();
+        assert_eq!(warnings, [(WarningCode::EmptyImport, 2), (WarningCode::RepeatedImport, 3)]);
+        assert_eq!(WarningCode::RepeatedImport.to_string(), "SYMW0005");
+    }
+
+    #[test]
+    fn test_reject_duplicate_keys() {
+        let options = ParseOptions::new().duplicate_keys(DuplicateKeys::Reject);
+        let strict = |input: &str| {
+            let mut parser = parser::Parser::new(input);
+            parser.set_options(options.clone());
+            parser.parse()
+        };
+
+        let err = strict("{ :name app\n, :db { :host a\n  , :port 1\n  , :host b\n  }\n}").unwrap_err();
+        match &err {
+            SymError::DuplicateKey { key, location } => {
+                assert_eq!(key, "host");
+                assert_eq!((location.line, location.col), (4, 5));
+            }
+            other => panic!("expected duplicate key, got {:?}", other),
+        }
+        assert_eq!(err.kind(), ErrorKind::Merge);
+
+        let value = strict("{ :db { :host a }\n, :db! { :host b }\n, :tags [ a ]\n, :tags+ [ b ]\n}").unwrap();
+        assert_eq!(value.as_object().unwrap()["tags"].as_array().unwrap().len(), 2);
+
+        // Layers may still merge into imported values
+        let resolver = MemoryResolver::new()
+            .with_file("base.sym", "{ :db { :host a } }")
+            .with_file("main.sym", "@import ./base.sym\n{ :db { :port 5 } }");
+        let source = resolver.load(std::path::Path::new("main.sym")).unwrap();
+        let mut parser = parser::Parser::for_file(&source, std::path::Path::new("main.sym"));
+        parser.set_options(options.clone());
+        parser.set_resolver(&resolver);
+        assert!(parser.parse().is_ok());
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "{ $port 8080 }\n{ :name  Alice   // the owner\n, :port $port\n, :url http://$port/x\n, :tags [ :a, :b ]\n}";
+        let result = parse_spanned(input).unwrap();
+        assert_eq!(result.span.line, 2);
+        assert_eq!(result.span.text(input), &input[15..]);
+
+        let name = result.field("name").unwrap();
+        assert_eq!(name.key_span.text(input), ":name");
+        assert_eq!(name.value.span.text(input), "Alice");
+        assert_eq!((name.value.span.line, name.value.span.col), (2, 10));
+        assert_eq!(name.value.span.file, None);
+
+        let port = result.get("port").unwrap();
+        assert_eq!(port.kind, SpannedKind::Int(8080));
+        assert_eq!((port.span.line, port.span.col), (3, 9));
+        assert_eq!(port.refs[0].name, "port");
+        assert_eq!(port.refs[0].span.text(input), "$port");
+
+        let url = result.get("url").unwrap();
+        assert_eq!(url.span.text(input), "http://$port/x");
+        assert_eq!(url.refs[0].span.col, 15);
+
+        let tags = result.get("tags").unwrap();
+        let SpannedKind::Array(items) = &tags.kind else {
+            panic!("expected an array");
+        };
+        assert_eq!(items[1].span.text(input), ":b");
+        assert_eq!(result.into_value(), parse(input).unwrap());
+    }
+
+    #[test]
+    fn test_recovering() {
+        let input = "{ :name app\n, :port 80 80\n, :tags [ a\n  , :\n  , b\n  ]\n, 42\n, :replicas 3\n, :nested { :a 1\n  , :b 2 2\n  }\n}\n}";
+        let recovered = parse_recovering(input);
+        let lines: Vec<_> = recovered
+            .errors
+            .iter()
+            .map(|e| match e {
+                SymError::ParseError { location, .. } => location.line,
+                other => panic!("expected a syntax error, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(lines, [2, 4, 7, 10, 13]);
+
+        let value = recovered.value.unwrap();
+        let obj = value.as_object().unwrap();
+        assert_eq!(obj.get("name").unwrap().as_str(), Some("app"));
+        assert_eq!(obj.get("port").unwrap().as_int(), Some(80));
+        assert_eq!(obj.get("tags").unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(obj.get("replicas").unwrap().as_int(), Some(3));
+        let nested = obj.get("nested").unwrap().as_object().unwrap();
+        assert_eq!(nested.get("a").unwrap().as_int(), Some(1));
+
+        let recovered = parse_recovering("{ :a 1\n, :b 2\n}");
+        assert!(recovered.errors.is_empty());
+        assert_eq!(recovered.value, Some(parse("{ :a 1\n, :b 2\n}").unwrap()));
+
+        let recovered = parse_recovering("{ :a $missing }");
+        assert!(recovered.value.is_none());
+        assert!(matches!(recovered.errors[..], [SymError::UndefinedVariable { .. }]));
+    }
+
+    #[test]
+    fn test_recovering_unclosed_array() {
+        let input = "{ :a 1\n, :list [ 1\n  , 2\n}\n, :b 2\n, :c 3\n, :d 4\n}";
+        let recovered = parse_recovering(input);
+        assert_eq!(recovered.errors.len(), 1);
+        assert_eq!(recovered.errors[0].code(), ErrorCode::UnclosedArray);
+        assert_eq!(recovered.errors[0].location().unwrap().line, 2);
+
+        let value = recovered.value.unwrap();
+        assert_eq!(value["list"], Value::from(vec![1i64, 2]));
+        assert_eq!(value["b"].as_int(), Some(2));
+        assert_eq!(value["c"].as_int(), Some(3));
+        assert_eq!(value["d"].as_int(), Some(4));
+
+        // The `}` belongs to the object when nothing follows it
+        let recovered = parse_recovering("{ :a 1\n, :list [ 1\n  , 2\n}");
+        assert_eq!(recovered.errors.len(), 1);
+        assert_eq!(recovered.value.unwrap()["a"].as_int(), Some(1));
+
+        let recovered = parse_recovering("[ { :a 1\n  , :b 2\n]\n, 3\n]");
+        assert_eq!(recovered.errors.len(), 1);
+        assert_eq!(recovered.errors[0].code(), ErrorCode::UnclosedObject);
+        assert_eq!(recovered.value.unwrap()[1].as_int(), Some(3));
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
     fn test_numbers() {
         let input = r#"{ :int 42
 , :negative -17
@@ -304,6 +1273,9 @@ mod tests {
 , :octal 0o755
 , :float 3.14
 , :scientific 1e10
+, :not-binary 0b1012
+, :not-octal 0o79
+, :not-hex 0xffgg
 }"#;
         let result = parse(input).unwrap();
         let obj = result.as_object().unwrap();
@@ -313,6 +1285,10 @@ mod tests {
         assert_eq!(obj.get("binary").unwrap().as_int(), Some(10));
         assert_eq!(obj.get("octal").unwrap().as_int(), Some(493));
         assert_eq!(obj.get("float").unwrap().as_float(), Some(3.14));
+        // Digits outside the radix make the literal a string, like `3px`
+        assert_eq!(obj.get("not-binary").unwrap().as_str(), Some("0b1012"));
+        assert_eq!(obj.get("not-octal").unwrap().as_str(), Some("0o79"));
+        assert_eq!(obj.get("not-hex").unwrap().as_str(), Some("0xffgg"));
     }
 
     #[test]
@@ -338,6 +1314,77 @@ mod tests {
         assert_eq!(obj.get("greeting").unwrap().as_str(), Some("Hello, world"));
     }
 
+    #[test]
+    fn test_key_order() {
+        let input = "{ $extra { :z 1\n  , :a 2\n  }\n}\n{ :zeta 1\n, :alpha { :y 1\n  , :b 2\n  }\n, :mid $extra\n, :alpha { :c 3\n  , :a 4\n  }\n, :zeta! 5\n}";
+        let value = parse(input).unwrap();
+        let map = value.as_object().unwrap();
+        assert!(map.keys().eq(["zeta", "alpha", "mid"]));
+        assert!(map["alpha"].as_object().unwrap().keys().eq(["y", "b", "c", "a"]));
+        assert!(map["mid"].as_object().unwrap().keys().eq(["z", "a"]));
+        assert_eq!(map["zeta"], Value::Int(5));
+
+        let printed = convert::to_sym_string(&value, 0);
+        assert_eq!(parse(&printed).unwrap(), value);
+        assert!(printed.find(":zeta").unwrap() < printed.find(":alpha").unwrap());
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Value::from(true), Value::Bool(true));
+        assert_eq!(Value::from(8080u16), Value::Int(8080));
+        assert_eq!(Value::from(0.5f32), Value::Float(0.5));
+        assert_eq!(Value::from("web"), Value::String("web".into()));
+        assert_eq!(Value::from(None::<i32>), Value::Null);
+        assert_eq!(Value::from(Some("x")), Value::String("x".into()));
+        assert_eq!(Value::from(vec![1, 2]), Value::Array(vec![Value::Int(1), Value::Int(2)]));
+        let map = Value::from(IndexMap::from([("b", vec![Some(1)]), ("a", vec![None])]));
+        assert!(map.as_object().unwrap().keys().eq(["b", "a"]));
+        assert_eq!(map["a"][0], Value::Null);
+        let sorted = Value::from(BTreeMap::from([("b".to_string(), 1), ("a".to_string(), 2)]));
+        assert!(sorted.as_object().unwrap().keys().eq(["a", "b"]));
+        assert_eq!(Value::from(HashMap::from([("k", "v")]))["k"], Value::from("v"));
+
+        let value = parse("{ :port 8080\n, :big 70000\n, :ratio 0.25\n, :name web\n, :hosts [a\n  , b]\n}").unwrap();
+        assert_eq!(u16::try_from(&value["port"]).unwrap(), 8080);
+        assert_eq!(f64::try_from(&value["port"]).unwrap(), 8080.0);
+        assert_eq!(f32::try_from(&value["ratio"]).unwrap(), 0.25);
+        let err = u16::try_from(&value["big"]).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot convert 70000 to u16");
+        assert_eq!(err.code(), ErrorCode::Conversion);
+        assert_eq!(i64::try_from(&value["ratio"]).unwrap_err().to_string(), "Cannot convert 0.25 to i64");
+        assert_eq!(u8::try_from(&value["name"]).unwrap_err().to_string(), "Cannot convert a string to u8");
+        assert!(u32::try_from(&Value::Int(-1)).is_err());
+        assert!(f32::try_from(&Value::Float(1e300)).is_err());
+        assert!(f32::try_from(&Value::Float(f64::INFINITY)).unwrap().is_infinite());
+        assert!(usize::try_from(&value["missing"]).is_err());
+    }
+
+    #[test]
+    fn test_index_and_mut_accessors() {
+        let mut value = parse("{ :server { :port 8080\n  , :tls false\n  , :ratio 0.5\n  }\n, :hosts [a\n  , b]\n, :mode :fast\n}").unwrap();
+        assert_eq!(value["server"]["port"], Value::Int(8080));
+        assert_eq!(value["hosts"][1], Value::from("b"));
+        assert_eq!(value["hosts"][5], Value::Null);
+        assert_eq!(value["missing"]["deeper"][0], Value::Null);
+        assert_eq!(value["hosts"]["port"], Value::Null);
+
+        let server = value.as_object_mut().unwrap().get_mut("server").unwrap();
+        *server.as_object_mut().unwrap()["port"].as_int_mut().unwrap() += 1;
+        *server.get_path_mut("tls").unwrap().as_bool_mut().unwrap() = true;
+        *server.get_path_mut("ratio").unwrap().as_float_mut().unwrap() *= 2.0;
+        assert!(server.get_path_mut("port").unwrap().as_float_mut().is_none());
+        value.get_path_mut("hosts").unwrap().as_array_mut().unwrap().push("c".into());
+        value.get_path_mut("hosts[0]").unwrap().as_string_mut().unwrap().push('1');
+        value.get_path_mut("mode").unwrap().as_symbol_mut().unwrap().replace_range(.., "slow");
+        assert_eq!(value["server"]["port"], Value::Int(8081));
+        assert_eq!(value["server"]["tls"], Value::Bool(true));
+        assert_eq!(value["server"]["ratio"], Value::Float(1.0));
+        assert_eq!(value["hosts"], Value::from(vec!["a1", "b", "c"]));
+        assert_eq!(value["mode"], Value::Symbol("slow".into()));
+        assert!(value["mode"].clone().as_array_mut().is_none());
+    }
+
     #[test]
     fn test_nested_object() {
         let input = r#"{ :server
@@ -390,4 +1437,434 @@ mod tests {
             Some("https://example.com")
         );
     }
+
+    /// A temporary directory, removed with its files when dropped
+    struct TempDir(std::path::PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Write files into a fresh temporary directory
+    fn write_files(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir(std::env::temp_dir().join(format!("sym-parser-{}-{}", name, std::process::id())));
+        for (file, content) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_import_merges_data() {
+        let dir = write_files(
+            "merge",
+            &[
+                (
+                    "base.sym",
+                    "{ $host localhost }\n{ :database\n  { :host $host\n  , :port 5432\n  }\n, :plugins [ :auth ]\n}",
+                ),
+                (
+                    "production.sym",
+                    "@import ./base.sym\n\n{ :database\n  { :host prod.example.com\n  , :ssl true\n  }\n, :plugins [ :cache ]\n}",
+                ),
+            ],
+        );
+        let result = parse_file(dir.join("production.sym")).unwrap();
+        assert_eq!(result.get_path("database.host").unwrap().as_str(), Some("prod.example.com"));
+        assert_eq!(result.get_path("database.port").unwrap().as_int(), Some(5432));
+        assert_eq!(result.get_path("database.ssl").unwrap().as_bool(), Some(true));
+        let db = result.get_path("database").unwrap().as_object().unwrap();
+        assert!(db.keys().eq(["host", "port", "ssl"]));
+        let plugins = result.get_path("plugins").unwrap().as_array().unwrap();
+        assert_eq!(plugins, &vec![Value::Symbol("auth".into()), Value::Symbol("cache".into())]);
+    }
+
+    #[test]
+    fn test_import_replace_and_override() {
+        let dir = write_files(
+            "override",
+            &[
+                (
+                    "shared/base.sym",
+                    "{ $port 3000 }\n{ :server { :port $port }\n, :logging { :level :debug }\n}",
+                ),
+                (
+                    "main.sym",
+                    "@import shared/base.sym\n{ $port! 8080 }\n{ :logging! { :driver datadog } }",
+                ),
+            ],
+        );
+        let result = parse_file(dir.join("main.sym")).unwrap();
+        let obj = result.as_object().unwrap();
+        let server = obj.get("server").unwrap().as_object().unwrap();
+        assert_eq!(server.get("port").unwrap().as_int(), Some(8080));
+        let logging = obj.get("logging").unwrap().as_object().unwrap();
+        assert_eq!(logging.len(), 1);
+        assert_eq!(logging.get("driver").unwrap().as_str(), Some("datadog"));
+    }
+
+    #[test]
+    fn test_import_duplicate_variable_requires_override() {
+        let dir = write_files(
+            "duplicate",
+            &[
+                ("base.sym", "{ $port 3000 }\n{ :port $port }"),
+                ("main.sym", "@import ./base.sym\n{ $port 8080 }\n{ :port $port }"),
+            ],
+        );
+        let err = parse_file(dir.join("main.sym")).unwrap_err();
+        match &err {
+            SymError::DuplicateVariable { name, location } => {
+                assert_eq!(name, "port");
+                assert_eq!((location.line, location.col), (2, 3));
+                assert!(location.file.as_ref().unwrap().ends_with("main.sym"));
+            }
+            other => panic!("expected duplicate variable, got {:?}", other),
+        }
+        assert!(err.to_string().contains("(use $port! to override)"));
+    }
+
+    #[test]
+    fn test_import_duplicate_variable_across_imports() {
+        let dir = write_files(
+            "duplicate-imports",
+            &[
+                ("a.sym", "{ $port 3000 }\n{ :a 1 }"),
+                ("b.sym", "{ $host b\n, $port 8080\n}\n{ :b 2 }"),
+                ("c.sym", "{ $port! 9090 }\n{ :c 3 }"),
+                ("main.sym", "@import ./a.sym\n@import ./b.sym\n{ :port $port }"),
+                ("override.sym", "@import ./a.sym\n@import ./c.sym\n{ :port $port }"),
+            ],
+        );
+        let err = parse_file(dir.join("main.sym")).unwrap_err();
+        match &err {
+            SymError::DuplicateVariable { name, location } => {
+                assert_eq!(name, "port");
+                assert_eq!((location.line, location.col), (2, 3));
+                assert!(location.file.as_ref().unwrap().ends_with("b.sym"));
+            }
+            other => panic!("expected duplicate variable, got {:?}", other),
+        }
+
+        let result = parse_file(dir.join("override.sym")).unwrap();
+        assert_eq!(result["port"].as_int(), Some(9090));
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.sym", "@import ./b.sym\n{ :a 1 }"),
+                ("b.sym", "@import ./a.sym\n{ :b 2 }"),
+            ],
+        );
+        let err = parse_file(dir.join("a.sym")).unwrap_err();
+        match &err {
+            SymError::ImportCycle { chain, location } => {
+                assert!(location.file.as_ref().unwrap().ends_with("b.sym"));
+                assert_eq!(location.line, 1);
+                let names: Vec<_> = chain.iter().map(|p| p.file_name().unwrap()).collect();
+                assert_eq!(names, ["a.sym", "b.sym", "a.sym"]);
+            }
+            other => panic!("expected import cycle, got {:?}", other),
+        }
+        assert!(err.to_string().contains("a.sym -> "));
+    }
+
+    #[test]
+    fn test_import_graph() {
+        let dir = write_files(
+            "graph",
+            &[
+                ("common.sym", "{ :tags [ common ] }"),
+                ("left.sym", "@import ./common.sym\n{ :left true }"),
+                ("right.sym", "@import ./common.sym\n{ :right true }"),
+                ("main.sym", "@import ./left.sym\n@import ./right.sym\n{ :main true }"),
+            ],
+        );
+        let graph = import_graph(dir.join("main.sym")).unwrap();
+        let name = |p: &std::path::PathBuf| p.file_name().unwrap().to_str().unwrap().to_string();
+        let files: Vec<_> = graph.files.iter().map(name).collect();
+        assert_eq!(files, ["main.sym", "left.sym", "common.sym", "right.sym"]);
+        let order: Vec<_> = graph.load_order.iter().map(name).collect();
+        assert_eq!(order, ["common.sym", "left.sym", "right.sym", "main.sym"]);
+        assert_eq!(graph.edges.len(), 4);
+        assert_eq!(graph.dependencies().len(), 3);
+
+        // A file reached twice is only merged once
+        let result = parse_file(dir.join("main.sym")).unwrap();
+        let tags = result.as_object().unwrap().get("tags").unwrap();
+        assert_eq!(tags.as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_memory_resolver() {
+        let resolver = MemoryResolver::new()
+            .with_file("config/base.sym", "{ $region eu-west }\n{ :region $region\n, :replicas 1\n}")
+            .with_file("config/prod.sym", "@import ./base.sym\n{ :replicas 3 }")
+            .with_file("main.sym", "@import config/prod.sym\n{ :name app }");
+
+        let result = parse_file_with_resolver("main.sym", &resolver).unwrap();
+        let obj = result.as_object().unwrap();
+        assert_eq!(obj.get("replicas").unwrap().as_int(), Some(3));
+        assert_eq!(obj.get("name").unwrap().as_str(), Some("app"));
+
+        let err = parse_file_with_resolver("missing.sym", &resolver).unwrap_err();
+        assert!(matches!(err, SymError::ImportError { .. }));
+    }
+
+    #[test]
+    fn test_spans_across_imports() {
+        let dir = write_files(
+            "spans",
+            &[
+                ("base.sym", "{ :host localhost\n, :port 5432\n}"),
+                ("app.sym", "@import ./base.sym\n{ :port 6543 }"),
+            ],
+        );
+        let result = parse_file_spanned(dir.join("app.sym")).unwrap();
+        let host = result.get("host").unwrap();
+        let file = host.span.file.as_deref().unwrap();
+        assert!(file.ends_with("base.sym"));
+        assert_eq!((host.span.line, host.span.col), (1, 9));
+
+        let port = result.get("port").unwrap();
+        assert!(port.span.file.as_deref().unwrap().ends_with("app.sym"));
+        assert_eq!((port.span.line, port.span.col), (2, 9));
+    }
+
+    #[test]
+    fn test_errors_in_imported_files() {
+        let resolver = MemoryResolver::new()
+            .with_file("db.sym", "{ :host $db_host\n}")
+            .with_file("base.sym", "// shared\n@import ./db.sym\n{ :debug false }")
+            .with_file("main.sym", "// production\n@import ./base.sym\n{ :debug true }");
+
+        let err = parse_file_with_resolver("main.sym", &resolver).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Variable);
+        assert_eq!(err.kind().name(), "VariableError");
+        let location = err.location().unwrap();
+        assert_eq!(location.file.as_deref(), Some(std::path::Path::new("db.sym")));
+        assert_eq!((location.line, location.col), (1, 9));
+        let stack: Vec<_> = location
+            .import_stack
+            .iter()
+            .map(|site| (site.file.as_deref().unwrap().to_str().unwrap(), site.line))
+            .collect();
+        assert_eq!(stack, [("main.sym", 2), ("base.sym", 2)]);
+
+        let resolver = MemoryResolver::new()
+            .with_file("base.sym", "{ :a 1\n, :b\n")
+            .with_file("main.sym", "@import ./base.sym\n{ :c 3 }");
+        let err = parse_file_with_resolver("main.sym", &resolver).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Syntax);
+        let location = err.location().unwrap();
+        assert_eq!(location.file.as_deref(), Some(std::path::Path::new("base.sym")));
+        assert_eq!(location.import_stack.len(), 1);
+
+        let err = parse("{ :tags a\n, :tags+ [ b ]\n}").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Merge);
+        assert_eq!(err.location().map(|l| (l.line, l.col)), Some((2, 3)));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let input = "{ :a inf\n, :b nan\n, :c 0x1F\n, :d -inf\n}";
+        let value = parse_with_options(input, &ParseOptions::new().inf(false).nan(false).hex(false)).unwrap();
+        let obj = value.as_object().unwrap();
+        for (key, text) in [("a", "inf"), ("b", "nan"), ("c", "0x1F"), ("d", "-inf")] {
+            assert_eq!(obj[key].as_str(), Some(text));
+        }
+        let value = parse_with_options(input, &ParseOptions::new()).unwrap();
+        assert_eq!(value.as_object().unwrap()["c"].as_int(), Some(31));
+
+        let input = "{ $env prod }\n{ :host db.$nope.$env\n, :user $nobody\n}";
+        let get = |options: &ParseOptions, key: &str| {
+            let value = parse_with_options(input, options).unwrap();
+            value.as_object().unwrap()[key].as_str().map(String::from)
+        };
+        let literal = ParseOptions::new().undefined_variables(UndefinedVariables::Literal);
+        assert_eq!(get(&literal, "host").as_deref(), Some("db.$nope.prod"));
+        assert_eq!(get(&literal, "user").as_deref(), Some("$nobody"));
+        let empty = ParseOptions::new().undefined_variables(UndefinedVariables::Empty);
+        assert_eq!(get(&empty, "host").as_deref(), Some("db..prod"));
+        assert!(matches!(
+            parse_with_options(input, &ParseOptions::new()),
+            Err(SymError::UndefinedVariable { .. })
+        ));
+
+        let dir = write_files(
+            "options",
+            &[
+                ("secret.sym", "{ :token hunter2 }"),
+                ("app/base.sym", "{ :name app }"),
+                ("app/main.sym", "@import ./base.sym\n{ :debug true }"),
+                ("app/leak.sym", "@import ../secret.sym\n{ :debug true }"),
+            ],
+        );
+        let no_imports = ParseOptions::new().imports(false);
+        let err = parse_file_with_options(dir.join("app/main.sym"), &no_imports).unwrap_err();
+        assert!(err.to_string().contains("imports are disabled"), "{}", err);
+
+        // The same directory, relative to the working directory
+        let cwd = std::env::current_dir().unwrap();
+        let relative: std::path::PathBuf = cwd
+            .components()
+            .skip(1)
+            .map(|_| std::path::Component::ParentDir)
+            .chain(dir.components().skip(1))
+            .collect();
+        for root in [dir.join("app"), relative.join("app")] {
+            let sandboxed = ParseOptions::new().import_root(&root);
+            for main in [dir.join("app/main.sym"), relative.join("app/main.sym")] {
+                let value = parse_file_with_options(&main, &sandboxed).unwrap();
+                assert_eq!(value.as_object().unwrap()["name"].as_str(), Some("app"));
+            }
+            let err = parse_file_with_options(relative.join("app/leak.sym"), &sandboxed).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Import);
+        }
+
+        let strict = ParseOptions::new().duplicate_keys(DuplicateKeys::Reject);
+        assert!(parse_with_options("{ :a 1\n, :a 2\n}", &strict).is_err());
+    }
+
+    #[test]
+    fn test_parse_borrowed() {
+        use std::borrow::Cow;
+
+        let input = "{ $env prod }\n{ :name web server  // inline\n, :url https://example.com/a\r\n, :path \\ indented\n, :host $env.example.com\n, :notes\n    first\n    second\n, :kind :deployment\n, :tags [a\n, b]\n}";
+        let value = parse_borrowed(input).unwrap();
+        let borrowed = |key: &str| matches!(value.get(key), Some(BorrowedValue::String(Cow::Borrowed(_))));
+        assert!(borrowed("name"));
+        assert!(borrowed("url"));
+        assert!(!borrowed("path"));
+        assert!(!borrowed("host"));
+        assert!(!borrowed("notes"));
+        assert_eq!(value.get("name").and_then(BorrowedValue::as_str), Some("web server"));
+        assert_eq!(value.get("url").and_then(BorrowedValue::as_str), Some("https://example.com/a"));
+        assert!(matches!(value.get("kind"), Some(BorrowedValue::Symbol(Cow::Borrowed("deployment")))));
+        let Some(BorrowedValue::Array(tags)) = value.get("tags") else {
+            panic!("expected an array");
+        };
+        assert!(matches!(tags[0], BorrowedValue::String(Cow::Borrowed("a"))));
+        let BorrowedValue::Object(map) = &value else {
+            panic!("expected an object");
+        };
+        assert!(map.keys().all(|key| matches!(key, Cow::Borrowed(_))));
+
+        assert_eq!(value.into_owned(), parse(input).unwrap());
+
+        // Merges, appends and substituted values give the same data as `parse`
+        let input = "{ $db { :host a } }\n{ :db $db  // shared\r\n, :db { :port 1 }\n, :tags [a]\n, :tags+ [b]\n, :name x\n, :name! y\n}";
+        let value = parse_borrowed(input).unwrap();
+        assert!(matches!(value.get("name"), Some(BorrowedValue::String(Cow::Borrowed("y")))));
+        assert_eq!(value.into_owned(), parse(input).unwrap());
+    }
+
+    #[test]
+    fn test_limits() {
+        let limit = |result: Result<Value>| match result {
+            Err(SymError::LimitExceeded { limit, .. }) => limit,
+            other => panic!("expected a limit error, got {:?}", other),
+        };
+
+        // Deep nesting fails instead of overflowing the stack, by default
+        let deep = "[".repeat(100_000);
+        assert_eq!(limit(parse(&deep)), Limit::Depth);
+        assert_eq!(parse_recovering(&deep).errors.last().unwrap().kind(), ErrorKind::Limit);
+        assert!(parse_cst(&deep).is_err());
+        assert!(parse(&format!("{}{}", "[".repeat(100), "]".repeat(100))).is_ok());
+
+        // Nesting through variables counts too
+        let shallow = Limits { max_depth: 3, ..Limits::default() };
+        let options = ParseOptions::new().limits(shallow);
+        let nested = "{ $a [[1]] }\n{ :x [$a] }";
+        assert_eq!(limit(parse_with_options(nested, &options)), Limit::Depth);
+
+        let small = Limits {
+            max_input_size: 20,
+            max_string_length: 5,
+            max_array_length: 3,
+            max_output_size: 1000,
+            ..Limits::default()
+        };
+        let options = ParseOptions::new().limits(small);
+        assert_eq!(limit(parse_with_options(&format!("[{}]", " ".repeat(30)), &options)), Limit::InputSize);
+        assert_eq!(limit(parse_with_options("{ :a abcdef }", &options)), Limit::StringLength);
+        assert_eq!(limit(parse_with_options("{ $a abc }\n[$a$a]", &options)), Limit::StringLength);
+        assert_eq!(limit(parse_with_options("[1\n,2\n,3\n,4]", &options)), Limit::ArrayLength);
+        assert!(parse_with_options("[1\n,2\n,3]", &options).is_ok());
+
+        // Each level multiplies the size of the document by ten
+        let mut laughs = format!("{{ $a [{}]\n", ["lol"; 10].join("\n,"));
+        for (name, prev) in ["b", "c", "d", "e", "f", "g", "h"].iter().zip(["a", "b", "c", "d", "e", "f", "g"]) {
+            let refs = vec![format!("${}", prev); 10].join("\n,");
+            laughs.push_str(&format!(", ${} [{}]\n", name, refs));
+        }
+        laughs.push_str("}\n{ :x $h }");
+        let err = parse_with_options(&laughs, &ParseOptions::untrusted()).unwrap_err();
+        assert!(matches!(err, SymError::LimitExceeded { limit: Limit::OutputSize, .. }), "{}", err);
+        assert_eq!(err.code(), ErrorCode::LimitExceeded);
+        assert!(err.to_string().contains("output size limit"), "{}", err);
+
+        // The default limits stop it too, before it runs out of memory
+        let mut laughs = format!("{{ $a {}\n", "lol".repeat(500));
+        for (name, prev) in ["b", "c", "d", "e", "f", "g", "h"].iter().zip(["a", "b", "c", "d", "e", "f", "g"]) {
+            let refs = vec![format!("${}", prev); 10].join("\n,");
+            laughs.push_str(&format!(", ${} [{}]\n", name, refs));
+        }
+        laughs.push_str("}\n{ :x $h }");
+        assert_eq!(limit(parse(&laughs)), Limit::OutputSize);
+    }
+
+    #[test]
+    fn test_sandboxed_resolver() {
+        let dir = write_files(
+            "sandbox",
+            &[
+                ("secret.sym", "{ :token hunter2 }"),
+                ("app/main.sym", "@import ../secret.sym\n{ :name app }"),
+            ],
+        );
+        let resolver = FsResolver::sandboxed(dir.join("app"));
+        let err = parse_file_with_resolver("main.sym", &resolver).unwrap_err();
+        match err {
+            SymError::ImportError { source, .. } => {
+                assert_eq!(source.kind(), std::io::ErrorKind::PermissionDenied)
+            }
+            other => panic!("expected import error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import_missing_file() {
+        let dir = write_files("missing", &[("main.sym", "@import ./nope.sym\n{ :a 1 }")]);
+        let err = parse_file(dir.join("main.sym")).unwrap_err();
+        match &err {
+            SymError::ImportError { written, path, .. } => {
+                assert_eq!(&**written, "./nope.sym");
+                assert_eq!(&**path, dir.join("nope.sym"));
+            }
+            other => panic!("expected import error, got {:?}", other),
+        }
+        let message = format!("Cannot import ./nope.sym ({})", dir.join("nope.sym").display());
+        assert!(err.to_string().starts_with(&message), "{}", err);
+
+        // The root document is not loaded again, only its imports are
+        let mut parser = parser::Parser::for_file("{ :a 1 }", std::path::Path::new("/nonexistent/x.sym"));
+        assert_eq!(parser.parse().unwrap(), parse("{ :a 1 }").unwrap());
+    }
 }
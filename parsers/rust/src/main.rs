@@ -3,10 +3,11 @@
 use std::env;
 use std::fs;
 use std::io::{self, Read};
-use sym_parser::{parse, convert, Value};
+use std::path::Path;
+use sym_parser::{convert, diagnostic, parser::Parser, DuplicateKeys, IndexMap, ParseOptions, Query, Value};
 
 fn print_usage(program: &str) {
-    eprintln!("Usage: {} [OPTIONS] <file>", program);
+    eprintln!("Usage: {} [OPTIONS] <file>...", program);
     eprintln!();
     eprintln!("Options:");
     eprintln!("  -e '<sym>'        Parse SYM expression");
@@ -15,6 +16,11 @@ fn print_usage(program: &str) {
     eprintln!("  --from-json       Convert JSON to SYM");
     eprintln!("  --from-yaml       Convert YAML to SYM");
     eprintln!("  --from-toml       Convert TOML to SYM");
+    eprintln!("  --plain-errors    Report errors on one line, without source snippets");
+    eprintln!("  --no-warnings     Do not report likely mistakes");
+    eprintln!("  --strict-keys     Reject keys repeated in one object without ! or +");
+    eprintln!("  --deny-warnings   Fail if there are warnings");
+    eprintln!("  -q, --query '<q>' Print the values the query selects, in every input");
     eprintln!();
     eprintln!("Examples:");
     eprintln!("  {} config.sym                    Parse SYM file", program);
@@ -23,6 +29,7 @@ fn print_usage(program: &str) {
     eprintln!("  {} --from-yaml config.yaml       Convert YAML to SYM", program);
     eprintln!("  {} --from-toml config.toml       Convert TOML to SYM", program);
     eprintln!("  cat file.json | {} --from-json - Convert stdin JSON to SYM", program);
+    eprintln!("  {} -q '..image' k8s/*.sym        Find every image", program);
 }
 
 #[derive(PartialEq)]
@@ -39,6 +46,50 @@ enum OutputFormat {
     Json,
 }
 
+/// What to do with warnings about likely mistakes
+#[derive(PartialEq)]
+enum Warnings {
+    Report,
+    Ignore,
+    Deny,
+}
+
+/// The source a report quotes: the input, or the imported file it is about
+fn report_source(file: Option<&Path>, input: &str, input_path: Option<&str>) -> String {
+    match file {
+        Some(file) if input_path.map(Path::new) != Some(file) => {
+            fs::read_to_string(file).unwrap_or_default()
+        }
+        _ => input.to_string(),
+    }
+}
+
+/// Where an input comes from
+enum Source {
+    Expression(String),
+    Stdin,
+    File(String),
+}
+
+impl Source {
+    /// The name matches are reported under
+    fn name(&self) -> &str {
+        match self {
+            Source::Expression(_) => "-e",
+            Source::Stdin => "-",
+            Source::File(path) => path,
+        }
+    }
+}
+
+/// How inputs are read and their problems reported
+struct Options {
+    input_format: InputFormat,
+    plain_errors: bool,
+    warnings: Warnings,
+    duplicate_keys: DuplicateKeys,
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = &args[0];
@@ -48,10 +99,15 @@ fn main() {
         std::process::exit(1);
     }
 
-    let mut input_format = InputFormat::Sym;
+    let mut options = Options {
+        input_format: InputFormat::Sym,
+        plain_errors: false,
+        warnings: Warnings::Report,
+        duplicate_keys: DuplicateKeys::Merge,
+    };
     let mut output_format = OutputFormat::Sym;
-    let mut input_source: Option<String> = None;
-    let mut expression: Option<String> = None;
+    let mut sources = Vec::new();
+    let mut query: Option<Query> = None;
 
     let mut i = 1;
     while i < args.len() {
@@ -64,13 +120,25 @@ fn main() {
                 output_format = OutputFormat::Json;
             }
             "--from-json" => {
-                input_format = InputFormat::Json;
+                options.input_format = InputFormat::Json;
             }
             "--from-yaml" => {
-                input_format = InputFormat::Yaml;
+                options.input_format = InputFormat::Yaml;
             }
             "--from-toml" => {
-                input_format = InputFormat::Toml;
+                options.input_format = InputFormat::Toml;
+            }
+            "--plain-errors" => {
+                options.plain_errors = true;
+            }
+            "--no-warnings" => {
+                options.warnings = Warnings::Ignore;
+            }
+            "--deny-warnings" => {
+                options.warnings = Warnings::Deny;
+            }
+            "--strict-keys" => {
+                options.duplicate_keys = DuplicateKeys::Reject;
             }
             "-e" => {
                 i += 1;
@@ -78,91 +146,172 @@ fn main() {
                     eprintln!("Error: Expected expression after -e");
                     std::process::exit(1);
                 }
-                expression = Some(args[i].clone());
+                sources.push(Source::Expression(args[i].clone()));
+            }
+            "--query" | "-q" => {
+                i += 1;
+                if i >= args.len() {
+                    eprintln!("Error: Expected query after {}", args[i - 1]);
+                    std::process::exit(1);
+                }
+                match Query::parse(&args[i]) {
+                    Ok(q) => query = Some(q),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-" => {
+                sources.push(Source::Stdin);
             }
             arg => {
-                input_source = Some(arg.to_string());
+                sources.push(Source::File(arg.to_string()));
             }
         }
         i += 1;
     }
 
+    if sources.is_empty() {
+        eprintln!("Error: No input specified");
+        print_usage(program);
+        std::process::exit(1);
+    }
+
+    let Some(query) = query else {
+        if sources.len() > 1 {
+            eprintln!("Error: Expected a single input, or --query to search several");
+            std::process::exit(1);
+        }
+        let Some(value) = load(&sources[0], &options) else {
+            std::process::exit(1);
+        };
+
+        // Output based on format
+        match output_format {
+            OutputFormat::Sym => {
+                println!("{}", convert::to_sym_string(&value, 0));
+            }
+            OutputFormat::Json => {
+                print_json(&value, 0);
+                println!();
+            }
+        }
+        return;
+    };
+
+    // Search every input, going on past those that fail to load
+    let mut failed = false;
+    let mut results = Vec::new();
+    for source in &sources {
+        let Some(value) = load(source, &options) else {
+            failed = true;
+            continue;
+        };
+        let file = (sources.len() > 1).then(|| source.name());
+        for m in query.select(&value) {
+            let path = if m.path.is_empty() { "$".to_string() } else { m.path() };
+            match output_format {
+                OutputFormat::Sym => match file {
+                    Some(file) => println!("{}:{} {}", file, path, m.value),
+                    None => println!("{} {}", path, m.value),
+                },
+                OutputFormat::Json => {
+                    let mut result = IndexMap::new();
+                    if let Some(file) = file {
+                        result.insert("file".to_string(), Value::String(file.to_string()));
+                    }
+                    result.insert("path".to_string(), Value::String(path));
+                    result.insert("value".to_string(), m.value.clone());
+                    results.push(Value::Object(result));
+                }
+            }
+        }
+    }
+    if output_format == OutputFormat::Json {
+        print_json(&Value::Array(results), 0);
+        println!();
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Read and parse one input, reporting any problems; `None` if it failed
+fn load(source: &Source, options: &Options) -> Option<Value> {
+    // Imports are resolved relative to the input file, if there is one
+    let input_path = match source {
+        Source::File(path) => Some(path.as_str()),
+        _ => None,
+    };
+
     // Get input content
-    let input = if let Some(expr) = expression {
-        expr
-    } else if let Some(source) = input_source {
-        if source == "-" {
+    let input = match source {
+        Source::Expression(expr) => expr.clone(),
+        Source::Stdin => {
             let mut buffer = String::new();
             match io::stdin().read_to_string(&mut buffer) {
                 Ok(_) => buffer,
                 Err(e) => {
                     eprintln!("Error reading stdin: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        } else {
-            match fs::read_to_string(&source) {
-                Ok(content) => content,
-                Err(e) => {
-                    eprintln!("Error reading file '{}': {}", source, e);
-                    std::process::exit(1);
+                    return None;
                 }
             }
         }
-    } else {
-        eprintln!("Error: No input specified");
-        print_usage(program);
-        std::process::exit(1);
+        Source::File(path) => match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", path, e);
+                return None;
+            }
+        },
     };
 
     // Parse input based on format
-    let value = match input_format {
+    let parsed = match options.input_format {
         InputFormat::Sym => {
-            match parse(&input) {
-                Ok(v) => v,
-                Err(e) => {
+            let parse_options = ParseOptions::new().duplicate_keys(options.duplicate_keys);
+            let mut parser = match input_path {
+                Some(path) => Parser::for_file(&input, Path::new(path)),
+                None => Parser::new(&input),
+            };
+            parser.set_options(parse_options);
+            // Report every error at once rather than one per run
+            let recovered = parser.parse_recovering();
+            for e in &recovered.errors {
+                if options.plain_errors {
                     eprintln!("SYM parse error: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        }
-        InputFormat::Json => {
-            match convert::parse_json(&input) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    std::process::exit(1);
-                }
-            }
-        }
-        InputFormat::Yaml => {
-            match convert::parse_yaml(&input) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    std::process::exit(1);
+                } else {
+                    let file = e.location().and_then(|l| l.file.as_deref());
+                    let source = report_source(file, &input, input_path);
+                    eprint!("{}", diagnostic::render(e, &source, input_path));
                 }
             }
-        }
-        InputFormat::Toml => {
-            match convert::parse_toml(&input) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    std::process::exit(1);
+            if options.warnings != Warnings::Ignore {
+                for w in &recovered.warnings {
+                    if options.plain_errors {
+                        eprintln!("SYM warning: {}", w);
+                    } else {
+                        let source = report_source(w.location.file.as_deref(), &input, input_path);
+                        eprint!("{}", w.render(&source, input_path));
+                    }
                 }
             }
+            let denied = options.warnings == Warnings::Deny && !recovered.warnings.is_empty();
+            return match recovered.value {
+                Some(v) if recovered.errors.is_empty() && !denied => Some(v),
+                _ => None,
+            };
         }
+        InputFormat::Json => convert::parse_json(&input),
+        InputFormat::Yaml => convert::parse_yaml(&input),
+        InputFormat::Toml => convert::parse_toml(&input),
     };
-
-    // Output based on format
-    match output_format {
-        OutputFormat::Sym => {
-            println!("{}", convert::to_sym_string(&value, 0));
-        }
-        OutputFormat::Json => {
-            print_json(&value, 0);
-            println!();
+    match parsed {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
         }
     }
 }
@@ -174,9 +323,7 @@ fn print_json(value: &Value, indent: usize) {
         Value::Bool(b) => print!("{}", b),
         Value::Int(i) => print!("{}", i),
         Value::Float(f) => {
-            if f.is_nan() {
-                print!("null")
-            } else if f.is_infinite() {
+            if f.is_nan() || f.is_infinite() {
                 print!("null")
             } else {
                 print!("{}", f)
@@ -0,0 +1,207 @@
+//! Settings that control how SYM is parsed
+//!
+//! Embedders with different trust and strictness needs start from the
+//! defaults and switch features off or make checks stricter:
+//!
+//! ```
+//! use sym_parser::{DuplicateKeys, ParseOptions, UndefinedVariables};
+//!
+//! let options = ParseOptions::new()
+//!     .duplicate_keys(DuplicateKeys::Reject)
+//!     .imports(false)
+//!     .undefined_variables(UndefinedVariables::Literal)
+//!     .nan(false);
+//! let value = sym_parser::parse_with_options("{ :greeting hello $user\n, :ratio nan\n}", &options).unwrap();
+//! assert_eq!(value.as_object().unwrap()["greeting"].as_str(), Some("hello $user"));
+//! assert_eq!(value.as_object().unwrap()["ratio"].as_str(), Some("nan"));
+//! ```
+
+use crate::import::FsResolver;
+use std::fmt;
+use std::path::PathBuf;
+
+/// What to do with a key repeated within one object without `!` or `+`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Deep merge the values, with a warning (the default)
+    #[default]
+    Merge,
+    /// Fail with [`SymError::DuplicateKey`](crate::SymError::DuplicateKey)
+    Reject,
+}
+
+/// What a reference to an undefined variable becomes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UndefinedVariables {
+    /// Fail with [`SymError::UndefinedVariable`](crate::SymError::UndefinedVariable) (the default)
+    #[default]
+    Error,
+    /// Keep the reference as written, e.g. `$name`
+    Literal,
+    /// Substitute the empty string
+    Empty,
+}
+
+/// Bounds on the resources a parse may use
+///
+/// The defaults bound nesting, so that deeply nested input fails instead of
+/// overflowing the stack, and the size of the parsed document, so that
+/// variables cannot multiply it until the process runs out of memory. Both
+/// are far above what a config needs; [`Limits::untrusted`] bounds everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Deepest nesting of arrays and objects, including values substituted
+    /// from variables
+    pub max_depth: usize,
+    /// Longest input, in bytes, of the document and of each imported file
+    pub max_input_size: usize,
+    /// Longest string value, in bytes, after interpolation
+    pub max_string_length: usize,
+    /// Most elements in one array, after appending with `+`
+    pub max_array_length: usize,
+    /// Largest parsed document, counted as one per value plus the length of
+    /// every key and string, to stop variables from multiplying its size
+    pub max_output_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_input_size: usize::MAX,
+            max_string_length: usize::MAX,
+            max_array_length: usize::MAX,
+            max_output_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Limits for input from an untrusted source, such as an upload
+    pub fn untrusted() -> Self {
+        Self {
+            max_depth: 64,
+            max_input_size: 4 * 1024 * 1024,
+            max_string_length: 1024 * 1024,
+            max_array_length: 100_000,
+            max_output_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Which of the [`Limits`] was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Depth,
+    InputSize,
+    StringLength,
+    ArrayLength,
+    OutputSize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "nesting depth",
+            Limit::InputSize => "input size",
+            Limit::StringLength => "string length",
+            Limit::ArrayLength => "array length",
+            Limit::OutputSize => "output size",
+        })
+    }
+}
+
+/// Parser settings, built from the defaults
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub(crate) duplicate_keys: DuplicateKeys,
+    pub(crate) imports: bool,
+    /// Sandboxed resolver for an import root
+    pub(crate) import_root: Option<FsResolver>,
+    pub(crate) undefined_variables: UndefinedVariables,
+    pub(crate) inf: bool,
+    pub(crate) nan: bool,
+    pub(crate) hex: bool,
+    pub(crate) limits: Limits,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            duplicate_keys: DuplicateKeys::default(),
+            imports: true,
+            import_root: None,
+            undefined_variables: UndefinedVariables::default(),
+            inf: true,
+            nan: true,
+            hex: true,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The defaults: everything enabled, duplicate keys merged, undefined
+    /// variables an error
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Settings for input from an untrusted source: no imports and
+    /// [`Limits::untrusted`]
+    pub fn untrusted() -> Self {
+        Self::new().imports(false).limits(Limits::untrusted())
+    }
+
+    /// How keys repeated within one object are handled
+    pub fn duplicate_keys(mut self, policy: DuplicateKeys) -> Self {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Whether `@import` is allowed; if not, any import is an error
+    pub fn imports(mut self, allowed: bool) -> Self {
+        self.imports = allowed;
+        self
+    }
+
+    /// Only allow imports of files inside `root`
+    ///
+    /// Replaces the filesystem resolver with [`FsResolver::sandboxed`]; a
+    /// resolver given to [`Parser::set_resolver`](crate::parser::Parser::set_resolver)
+    /// afterwards takes precedence.
+    pub fn import_root<P: Into<PathBuf>>(mut self, root: P) -> Self {
+        self.import_root = Some(FsResolver::sandboxed(root));
+        self
+    }
+
+    /// What references to undefined variables become
+    pub fn undefined_variables(mut self, behaviour: UndefinedVariables) -> Self {
+        self.undefined_variables = behaviour;
+        self
+    }
+
+    /// Whether `inf` and `-inf` are floats rather than strings
+    pub fn inf(mut self, enabled: bool) -> Self {
+        self.inf = enabled;
+        self
+    }
+
+    /// Whether `nan` is a float rather than a string
+    pub fn nan(mut self, enabled: bool) -> Self {
+        self.nan = enabled;
+        self
+    }
+
+    /// Whether `0x1F` is an integer rather than a string
+    pub fn hex(mut self, enabled: bool) -> Self {
+        self.hex = enabled;
+        self
+    }
+
+    /// Bounds on nesting, sizes and variable expansion
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
@@ -1,9 +1,21 @@
 //! Parser implementation for SYM format
 
-use crate::{Result, SymError, Value};
-use std::collections::HashMap;
-use std::iter::Peekable;
-use std::str::Chars;
+use crate::diagnostic::{suggest, ErrorCode, Warning, WarningCode};
+use crate::import::{normalize, suggest_paths, FsResolver, ImportContext, ImportGraph, Resolver};
+use crate::lexer::{
+    block_comment_len, env_ref, first_char, horizontal_whitespace_len, line_comment_len, line_len, name_len, parse_literal,
+    plain_text_len, starts_var_ref, value_follows, var_ref_len,
+};
+use crate::options::{DuplicateKeys, Limit, ParseOptions, UndefinedVariables};
+use crate::span::{Location, Span, SpannedField, SpannedKind, SpannedName, SpannedValue};
+use crate::{BorrowedValue, Result, SymError, Value};
+use indexmap::IndexMap;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Key modifier for merge behavior
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,56 +28,385 @@ pub enum KeyModifier {
     Append,
 }
 
+/// A value as written in the source, before variables are substituted
+#[derive(Debug, Clone)]
+struct Node<'a> {
+    kind: NodeKind<'a>,
+    span: Span,
+}
+
+/// The kinds of [`Node`]
+#[derive(Debug, Clone)]
+enum NodeKind<'a> {
+    /// A scalar that needs no substitution, borrowing from the source if it
+    /// was written verbatim
+    Value(SpannedKind<'a>),
+    /// A value that is exactly one variable reference ($name)
+    Variable(VarRef),
+    /// A string with variables interpolated into it
+    Template(Vec<Segment>),
+    /// Array of nodes
+    Array(Vec<Node<'a>>),
+    /// Object fields in source order, merged when resolved
+    Object(Vec<Field<'a>>),
+}
+
+/// A reference to a variable and where it was written
+#[derive(Debug, Clone)]
+struct VarRef {
+    name: String,
+    span: Span,
+}
+
+/// Part of a string value
+#[derive(Debug, Clone)]
+enum Segment {
+    /// Literal text
+    Text(String),
+    /// An interpolated variable
+    Variable(VarRef),
+    /// An interpolated environment variable
+    Env(EnvRef),
+}
+
+/// A reference to an environment variable: `${NAME}`, `${NAME:-default}`
+/// or `${NAME:?message}`
+#[derive(Debug, Clone)]
+struct EnvRef {
+    name: String,
+    fallback: EnvFallback,
+    span: Span,
+}
+
+/// What an environment reference becomes when the variable is not set
+#[derive(Debug, Clone)]
+enum EnvFallback {
+    /// `${NAME}` - the empty string
+    Empty,
+    /// `${NAME:-default}` - the default text
+    Default(String),
+    /// `${NAME:?message}` - an error
+    Required(String),
+}
+
+/// A single `:key value` or `$var value` entry of an object
+#[derive(Debug, Clone)]
+struct Field<'a> {
+    key: Cow<'a, str>,
+    key_span: Span,
+    modifier: KeyModifier,
+    value: Node<'a>,
+}
+
+/// Every definition of each variable, in order, until it is resolved
+type Definitions<'a> = HashMap<String, Vec<Option<Node<'a>>>>;
+
+/// A parsed document with its imports flattened, before substitution
+#[derive(Debug, Default)]
+struct Document<'a> {
+    /// Variable definitions in the order they take effect
+    defs: Vec<Field<'a>>,
+    /// Data blocks to merge, imported data first
+    layers: Vec<Node<'a>>,
+}
+
+impl Document<'_> {
+    /// Whether the document adds no variables and no data
+    fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+            && self
+                .layers
+                .iter()
+                .all(|node| matches!(&node.kind, NodeKind::Object(fields) if fields.is_empty()))
+    }
+}
+
+/// A value evaluated from [`Node`]s: a [`SpannedValue`], or a
+/// [`BorrowedValue`] when no locations are wanted
+trait Evaluated<'a>: Sized {
+    /// The value of an object field, with its key's location if kept
+    type Field;
+
+    /// A scalar, or a string interpolated from the variables in `refs`
+    fn scalar(kind: SpannedKind<'a>, span: Span, refs: Vec<SpannedName>) -> Self;
+    /// The value of the variable `var`, substituted at `span`
+    fn substituted(value: &SpannedValue<'a>, var: VarRef, span: Span) -> Self;
+    fn array(items: Vec<Self>, span: Span) -> Self;
+    fn object(map: IndexMap<Cow<'a, str>, Self::Field>, span: Span) -> Self;
+    fn field(value: Self, key_span: Span) -> Self::Field;
+    fn field_value(field: &mut Self::Field) -> &mut Self;
+    fn as_array_mut(&mut self) -> Option<&mut Vec<Self>>;
+    fn as_object_mut(&mut self) -> Option<&mut IndexMap<Cow<'a, str>, Self::Field>>;
+    fn into_array(self) -> Option<Vec<Self>>;
+    fn deep_merge(&mut self, other: Self);
+    /// Arrays and objects nested in the value, counting the value itself
+    fn nesting_depth(&self) -> usize;
+}
+
+/// The result of parsing in recovering mode
+#[derive(Debug)]
+pub struct Recovered {
+    /// The value built from everything that parsed, unless the document
+    /// could not be evaluated at all
+    pub value: Option<Value>,
+    /// Every error found, in the order it was found
+    pub errors: Vec<SymError>,
+    /// Warnings about the parts that parsed
+    pub warnings: Vec<Warning>,
+}
+
+/// An array being read element by element, see [`Parser::open_array`]
+pub(crate) struct OpenArray {
+    start: Span,
+    /// Elements started so far
+    len: usize,
+}
+
+/// A parsed value with the warnings found on the way
+#[derive(Debug)]
+pub struct Parsed {
+    pub value: Value,
+    pub warnings: Vec<Warning>,
+}
+
 /// Parser for SYM format
 pub struct Parser<'a> {
     input: &'a str,
-    chars: Peekable<Chars<'a>>,
     line: usize,
     col: usize,
+    /// Byte offset of the next character
     pos: usize,
-    variables: HashMap<String, Value>,
-    imports: Vec<String>,
+    variables: HashMap<String, SpannedValue<'a>>,
+    env: Option<HashMap<String, String>>,
+    /// `@import` paths and where they were written
+    imports: Vec<(String, Span)>,
+    path: Option<Arc<Path>>,
+    /// Resolver given to [`Parser::set_resolver`], see [`Parser::resolver`]
+    resolver: Option<&'a dyn Resolver>,
+    context: ImportContext,
+    /// Whether syntax errors are collected instead of ending the parse
+    recover: bool,
+    errors: Vec<SymError>,
+    options: ParseOptions,
+    /// Filled while substituting, which only borrows the parser
+    warnings: RefCell<Vec<Warning>>,
+    /// Variables referenced so far
+    used: RefCell<HashSet<String>>,
+    /// Arrays and objects open at the current position
+    depth: usize,
+    /// Size of the values substituted so far, see [`Limits::max_output_size`](crate::Limits)
+    output: Cell<usize>,
 }
 
+/// Resolver used unless the parser is given another one
+static DEFAULT_RESOLVER: FsResolver = FsResolver::new();
+
 impl<'a> Parser<'a> {
     /// Create a new parser
     pub fn new(input: &'a str) -> Self {
         Self {
             input,
-            chars: input.chars().peekable(),
             line: 1,
             col: 1,
             pos: 0,
             variables: HashMap::new(),
+            env: None,
             imports: Vec::new(),
+            path: None,
+            resolver: None,
+            context: ImportContext::default(),
+            recover: false,
+            errors: Vec::new(),
+            options: ParseOptions::default(),
+            warnings: RefCell::default(),
+            used: RefCell::default(),
+            depth: 0,
+            output: Cell::new(0),
+        }
+    }
+
+    /// Create a parser for the contents of a file
+    ///
+    /// `@import` paths are resolved relative to the directory containing `path`.
+    pub fn for_file(input: &'a str, path: &Path) -> Self {
+        let mut parser = Self::new(input);
+        parser.path = Some(Arc::from(path));
+        parser
+    }
+
+    /// Read `${NAME}` references from `env` instead of the process environment
+    pub fn set_env(&mut self, env: HashMap<String, String>) {
+        self.env = Some(env);
+    }
+
+    /// Use `resolver` to locate and load imported files
+    pub fn set_resolver(&mut self, resolver: &'a dyn Resolver) {
+        self.resolver = Some(resolver);
+    }
+
+    /// Apply `options`, replacing any set before
+    ///
+    /// An [`import_root`](ParseOptions::import_root) in `options` replaces the
+    /// resolver given to [`Parser::set_resolver`] before.
+    pub fn set_options(&mut self, options: ParseOptions) {
+        if options.import_root.is_some() {
+            self.resolver = None;
+        }
+        self.options = options;
+    }
+
+    /// The resolver imports go through: the one set last of
+    /// [`Parser::set_resolver`] and the options' import root, or the
+    /// unrestricted filesystem
+    fn resolver(&self) -> &dyn Resolver {
+        match (self.resolver, &self.options.import_root) {
+            (Some(resolver), _) => resolver,
+            (None, Some(sandbox)) => sandbox,
+            (None, None) => &DEFAULT_RESOLVER,
         }
     }
 
     /// Create a parser with pre-defined variables
     pub fn with_vars(input: &'a str, vars: HashMap<String, Value>) -> Self {
         Self {
-            input,
-            chars: input.chars().peekable(),
-            line: 1,
-            col: 1,
-            pos: 0,
-            variables: vars,
-            imports: Vec::new(),
+            variables: vars
+                .into_iter()
+                .map(|(name, value)| (name, SpannedValue::from_value(value, &Span::default())))
+                .collect(),
+            ..Self::new(input)
         }
     }
 
     /// Parse the input and return the final value
     pub fn parse(&mut self) -> Result<Value> {
-        // Parse imports
-        self.skip_whitespace_and_comments();
-        while self.check_import() {
-            let path = self.parse_import()?;
-            self.imports.push(path);
-            self.skip_whitespace_and_comments();
+        self.parse_spanned().map(SpannedValue::into_value)
+    }
+
+    /// Parse the input, collecting every syntax error instead of stopping at the first
+    ///
+    /// After an error the parser skips to the next `\n,` separator or closing
+    /// bracket and carries on, so the value holds everything that parsed.
+    pub fn parse_recovering(&mut self) -> Recovered {
+        self.recover = true;
+        let result = self.parse();
+        let mut errors: Vec<SymError> = std::mem::take(&mut self.errors)
+            .into_iter()
+            .map(|error| self.with_import_stack(error))
+            .collect();
+        let value = match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                errors.push(error);
+                None
+            }
+        };
+        let warnings = self.take_warnings();
+        Recovered {
+            value,
+            errors,
+            warnings,
+        }
+    }
+
+    /// Parse the input, also returning warnings about likely mistakes
+    ///
+    /// Warnings cover repeated keys that are deep merged, unused variables,
+    /// imports that add nothing and keys without a value.
+    pub fn parse_with_warnings(&mut self) -> Result<Parsed> {
+        let value = self.parse()?;
+        let warnings = self.take_warnings();
+        Ok(Parsed { value, warnings })
+    }
+
+    /// The warnings found so far, located across imports
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        let mut warnings = self.warnings.take();
+        for warning in &mut warnings {
+            self.add_import_stack(&mut warning.location);
+        }
+        warnings
+    }
+
+    /// Parse the input into a value whose verbatim strings and keys borrow from it
+    ///
+    /// No locations are kept, so this is cheaper than [`Parser::parse_spanned`].
+    pub fn parse_borrowed(&mut self) -> Result<BorrowedValue<'a>> {
+        self.evaluate().map_err(|error| self.with_import_stack(error))
+    }
+
+    /// Parse the input and return the final value with source locations
+    pub fn parse_spanned(&mut self) -> Result<SpannedValue<'a>> {
+        self.evaluate().map_err(|error| self.with_import_stack(error))
+    }
+
+    /// Parse the document, then substitute variables and merge the data blocks
+    fn evaluate<V: Evaluated<'a>>(&mut self) -> Result<V> {
+        self.start_root();
+        let document = self.parse_document()?;
+        let defined = self.define_variables(document.defs)?;
+        let result = self.merge_layers(document.layers)?;
+
+        for (name, span) in defined {
+            if !self.used.borrow().contains(&name) {
+                let message = format!("Variable ${} is never used", name);
+                self.warn(WarningCode::UnusedVariable, message, &span);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Add definitions to scope and return each name with where it was last defined
+    ///
+    /// The last definition of each name wins, and definitions are resolved in
+    /// dependency order. A redefinition may refer to the definition it replaces.
+    fn define_variables(&mut self, fields: Vec<Field<'a>>) -> Result<Vec<(String, Span)>> {
+        let mut order = Vec::new();
+        let mut defs: Definitions<'a> = HashMap::new();
+        let mut def_spans = HashMap::new();
+        for field in fields {
+            let name = field.key.into_owned();
+            if !defs.contains_key(&name) {
+                order.push(name.clone());
+            }
+            def_spans.insert(name.clone(), field.key_span);
+            defs.entry(name).or_default().push(Some(field.value));
         }
+        for name in &order {
+            let last = defs[name].len() - 1;
+            self.resolve_definition(name, last, &mut defs, &mut Vec::new())?;
+            let value = &self.variables[name];
+            self.check_depth(value, &value.span)?;
+        }
+        Ok(order
+            .into_iter()
+            .map(|name| {
+                let span = def_spans.remove(&name).unwrap_or_default();
+                (name, span)
+            })
+            .collect())
+    }
+
+    /// Merge data blocks, imported data first
+    fn merge_layers<V: Evaluated<'a>>(&self, layers: impl IntoIterator<Item = Node<'a>>) -> Result<V> {
+        let mut layers = layers.into_iter();
+        let Some(first) = layers.next() else {
+            return Err(self.error(ErrorCode::EmptyDocument, "Empty document"));
+        };
+        let span = first.span.clone();
+        let mut result = self.substitute_variables(first)?;
+        for layer in layers {
+            self.merge_node(&mut result, layer)?;
+        }
+        self.check_depth(&result, &span)?;
+        Ok(result)
+    }
+
+    /// Parse imports, defs blocks and data without substituting variables
+    fn parse_document(&mut self) -> Result<Document<'a>> {
+        self.parse_imports()?;
 
         // Parse defs blocks and data
-        let mut blocks: Vec<(bool, Value)> = Vec::new(); // (is_defs, value)
+        let mut blocks: Vec<(bool, Node)> = Vec::new(); // (is_defs, value)
 
         loop {
             self.skip_whitespace_and_comments();
@@ -73,57 +414,399 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let value = self.parse_value()?;
-
-            // Check if this block is a defs block (all keys start with $)
-            let is_defs = if let Value::Object(ref obj) = value {
-                !obj.is_empty() && obj.keys().all(|k| k.starts_with('$'))
-            } else {
-                false
+            let node = match self.parse_value() {
+                Ok(node) => node,
+                Err(error) => {
+                    self.recover(error)?;
+                    // Skip the stray closing bracket that stopped recovery
+                    if matches!(self.peek(), Some('}' | ']')) {
+                        self.advance();
+                    }
+                    continue;
+                }
             };
 
-            blocks.push((is_defs, value));
+            blocks.push((is_defs_block(&node), node));
         }
 
-        if blocks.is_empty() {
-            return Err(self.error("Empty document"));
+        if blocks.is_empty() && self.imports.is_empty() {
+            return Err(self.error(ErrorCode::EmptyDocument, "Empty document"));
         }
 
-        // Process blocks: all but last can be defs, last is always data
-        let (data_block, defs_blocks) = if blocks.len() == 1 {
-            (blocks.pop().unwrap().1, vec![])
-        } else {
-            let data = blocks.pop().unwrap().1;
-            (data, blocks)
+        let mut document = Document::default();
+        let mut defined: HashSet<String> = self.variables.keys().cloned().collect();
+        self.load_imports(&mut document, &mut defined)?;
+
+        // The last block is data, unless the file only overrides imported variables
+        let data_block = match blocks.last() {
+            Some((true, _)) if !self.imports.is_empty() => None,
+            _ => blocks.pop().map(|(_, node)| node),
         };
 
-        // Process defs blocks - add variables to scope
-        for (is_defs, block) in defs_blocks {
+        for (is_defs, block) in blocks {
             if is_defs {
-                if let Value::Object(obj) = block {
-                    for (key, value) in obj {
-                        // Remove $ prefix from key
-                        let var_name = key.trim_start_matches('$');
-                        let (var_name, is_override) = if var_name.ends_with('!') {
-                            (var_name.trim_end_matches('!').to_string(), true)
-                        } else {
-                            (var_name.to_string(), false)
-                        };
-
-                        if self.variables.contains_key(&var_name) && !is_override {
-                            return Err(SymError::DuplicateVariable(var_name));
-                        }
+                self.add_defs(block, &mut document, &mut defined)?;
+            }
+        }
 
-                        self.variables.insert(var_name, value);
-                    }
+        document.layers.extend(data_block);
+        Ok(document)
+    }
+
+    /// Parse the `@import` directives at the start of the input
+    fn parse_imports(&mut self) -> Result<()> {
+        let max = self.options.limits.max_input_size;
+        if self.input.len() > max {
+            return Err(self.limit_error(Limit::InputSize, max, &self.mark()));
+        }
+
+        self.skip_whitespace_and_comments();
+        while self.check_import() {
+            let start = self.mark();
+            let path = self.parse_import()?;
+            let span = self.span_from(start);
+            self.imports.push((path, span));
+            self.skip_whitespace_and_comments();
+        }
+        Ok(())
+    }
+
+    /// Load imports in order - their definitions and data come first
+    ///
+    /// `defined` collects the names of the variables defined so far.
+    fn load_imports(&mut self, document: &mut Document<'a>, defined: &mut HashSet<String>) -> Result<()> {
+        for (path, span) in self.imports.clone() {
+            // Files reached through more than one import are merged only once
+            let imported = match self.load_import(&path, &span) {
+                Ok(Some(imported)) if imported.is_empty() => {
+                    let message = format!("@import {} adds no variables or data", path);
+                    self.warn(WarningCode::EmptyImport, message, &span);
+                    continue;
+                }
+                Ok(Some(imported)) => imported,
+                Ok(None) => continue,
+                Err(error) => {
+                    self.report(error)?;
+                    continue;
                 }
+            };
+            // Each file has checked its own definitions, so only names
+            // from earlier imports can clash
+            let earlier = defined.clone();
+            for field in imported.defs {
+                if earlier.contains(field.key.as_ref()) && field.modifier != KeyModifier::Replace {
+                    self.report(SymError::DuplicateVariable {
+                        name: field.key.into_owned(),
+                        location: Location::from(&field.key_span),
+                    })?;
+                    continue;
+                }
+                defined.insert(field.key.to_string());
+                document.defs.push(field);
             }
+            document.layers.extend(imported.layers);
         }
+        Ok(())
+    }
 
-        // Substitute variables in data block
-        let result = self.substitute_variables(data_block)?;
+    /// Add the variables of a defs block to the document
+    fn add_defs(&mut self, block: Node<'a>, document: &mut Document<'a>, defined: &mut HashSet<String>) -> Result<()> {
+        let NodeKind::Object(fields) = block.kind else {
+            return Ok(());
+        };
+        for field in fields {
+            // Remove $ prefix from key
+            let var_name = field.key.trim_start_matches('$').to_string();
+            let is_override = field.modifier == KeyModifier::Replace;
+
+            if !defined.insert(var_name.clone()) && !is_override {
+                self.report(SymError::DuplicateVariable {
+                    name: var_name,
+                    location: Location::from(&field.key_span),
+                })?;
+                continue;
+            }
 
-        Ok(result)
+            document.defs.push(Field {
+                key: Cow::Owned(var_name),
+                ..field
+            });
+        }
+        Ok(())
+    }
+
+    /// Parse the imports and defs blocks before the data and define their
+    /// variables, for reading the data as a stream
+    ///
+    /// Returns the data, unless it is an array at the current position that
+    /// can be read element by element.
+    pub(crate) fn parse_prelude(&mut self) -> Result<Option<SpannedValue<'a>>> {
+        self.start_root();
+        self.prelude().map_err(|error| self.with_import_stack(error))
+    }
+
+    fn prelude(&mut self) -> Result<Option<SpannedValue<'a>>> {
+        self.parse_imports()?;
+        let mut document = Document::default();
+        let mut defined: HashSet<String> = self.variables.keys().cloned().collect();
+        self.load_imports(&mut document, &mut defined)?;
+
+        // Blocks are read as `parse_document` would, except that an array is
+        // taken to be the last block. The latest defs block is held back in
+        // case it turns out to be the data.
+        let mut data = None;
+        let mut held = None;
+        loop {
+            self.skip_whitespace_and_comments();
+            if self.is_at_end() {
+                break;
+            }
+            if let Some(block) = held.take() {
+                self.add_defs(block, &mut document, &mut defined)?;
+            }
+            if self.check('[') && document.layers.is_empty() {
+                self.define_variables(document.defs)?;
+                return Ok(None);
+            }
+            let node = self.parse_value()?;
+            if is_defs_block(&node) {
+                held = Some(node);
+            } else {
+                data = Some(node);
+            }
+        }
+        if let Some(block) = held {
+            if self.imports.is_empty() {
+                data = Some(block);
+            } else {
+                self.add_defs(block, &mut document, &mut defined)?;
+                data = None;
+            }
+        }
+
+        self.define_variables(document.defs)?;
+        self.merge_layers(document.layers.into_iter().chain(data)).map(Some)
+    }
+
+    /// Enter the array at the current position
+    pub(crate) fn open_array(&mut self) -> Result<OpenArray> {
+        let max = self.options.limits.max_depth;
+        if self.depth >= max {
+            return Err(self.limit_error(Limit::Depth, max, &self.mark()));
+        }
+        let start = self.mark();
+        self.expect('[')?;
+        self.depth += 1;
+        Ok(OpenArray { start, len: 0 })
+    }
+
+    /// Move to the next element of `array`, or past its `]` if there are no more
+    pub(crate) fn next_element(&mut self, array: &mut OpenArray) -> Result<bool> {
+        self.skip_whitespace_and_comments();
+        if array.len > 0 && !self.check(']') {
+            if !self.check_separator() {
+                if self.is_at_end() {
+                    return Err(self.error(ErrorCode::UnclosedArray, "Unclosed array"));
+                }
+                return Err(self.error(ErrorCode::MissingSeparator, "Expected ',' separator"));
+            }
+            self.consume_separator();
+            self.skip_whitespace_and_comments();
+        }
+        if self.check(']') {
+            self.advance();
+            self.depth -= 1;
+            return Ok(false);
+        }
+        array.len += 1;
+        self.check_array(array.len, &array.start)?;
+        Ok(true)
+    }
+
+    /// Whether the next element is an array
+    pub(crate) fn at_array(&mut self) -> bool {
+        self.check('[')
+    }
+
+    /// Parse the element at the current position and substitute its variables
+    pub(crate) fn parse_element(&mut self) -> Result<SpannedValue<'a>> {
+        let node = self.parse_array_element()?;
+        let value: SpannedValue = self.substitute_variables(node)?;
+        self.check_depth(&value, &value.span)?;
+        Ok(value)
+    }
+
+    /// Fail unless only whitespace and comments follow the streamed data
+    pub(crate) fn finish_data(&mut self) -> Result<()> {
+        self.skip_whitespace_and_comments();
+        if self.is_at_end() {
+            return Ok(());
+        }
+        Err(self.error(ErrorCode::Stream, "Expected the streamed array to be the last block"))
+    }
+
+    /// Parse the imports reachable from this document and return their graph
+    ///
+    /// The whole tree is parsed, so this fails exactly when `parse` would fail
+    /// to load an import.
+    pub fn import_graph(&mut self) -> Result<ImportGraph> {
+        self.start_root();
+        self.parse_document()
+            .map_err(|error| self.with_import_stack(error))?;
+        let mut graph = std::mem::take(&mut self.context.graph);
+        if let Some(root) = graph.files.first().cloned() {
+            graph.load_order.push(root);
+        }
+        Ok(graph)
+    }
+
+    /// Resolve definition `version` of `name` after the definitions it refers to
+    ///
+    /// Inside a redefinition, `name` refers to the definition before it; any
+    /// other reference is to the last definition of a name. `stack` holds the
+    /// definitions being resolved, to report reference cycles.
+    fn resolve_definition(
+        &mut self,
+        name: &str,
+        version: usize,
+        defs: &mut Definitions<'a>,
+        stack: &mut Vec<(String, usize)>,
+    ) -> Result<()> {
+        // Already resolved through another definition
+        let Some(node) = defs.get_mut(name).and_then(|versions| versions[version].take()) else {
+            return Ok(());
+        };
+        stack.push((name.to_string(), version));
+
+        let mut refs = Vec::new();
+        node.collect_refs(&mut refs);
+        for var in refs {
+            let target = longest_prefix(&var.name, |prefix| {
+                defs.contains_key(prefix) || self.variables.contains_key(prefix)
+            });
+            let Some(target) = target else {
+                // Reported as undefined when substituted
+                continue;
+            };
+            let target_version = match (target == name, defs.get(target)) {
+                (true, _) if version > 0 => version - 1,
+                // The value given to the parser that this definition replaces
+                (true, _) if self.variables.contains_key(target) => continue,
+                (_, Some(versions)) => versions.len() - 1,
+                (_, None) => continue,
+            };
+            if let Some(start) = stack.iter().position(|(n, v)| n == target && *v == target_version) {
+                let mut cycle: Vec<String> = stack[start..].iter().map(|(n, _)| n.clone()).collect();
+                cycle.push(target.to_string());
+                return Err(SymError::VariableCycle {
+                    names: cycle,
+                    location: Location::from(&var.span),
+                });
+            }
+            self.resolve_definition(target, target_version, defs, stack)?;
+        }
+
+        let value: SpannedValue = self.substitute_variables(node)?;
+        self.variables.insert(name.to_string(), value);
+        stack.pop();
+        Ok(())
+    }
+
+    /// Add the `@import` directives leading to the file an error was found in
+    fn with_import_stack(&self, mut error: SymError) -> SymError {
+        if let Some(location) = error.location_mut() {
+            self.add_import_stack(location);
+        }
+        error
+    }
+
+    /// Add the `@import` directives leading to the file of `location`
+    fn add_import_stack(&self, location: &mut Location) {
+        if location.import_stack.is_empty() {
+            location.import_stack = self.context.import_stack(location.file.as_deref());
+        }
+    }
+
+    /// Record the root file, so that imports leading back to it are cycles
+    ///
+    /// The caller has already read the file, so it is not loaded again.
+    fn start_root(&mut self) {
+        if !self.context.stack.is_empty() {
+            return;
+        }
+        if let Some(path) = &self.path {
+            self.context = ImportContext::for_root(self.resolver().root(path));
+        }
+    }
+
+    /// Load and parse an imported file, relative to the importing file
+    ///
+    /// Returns `None` if the file was already loaded through another import.
+    fn load_import(&mut self, path: &str, span: &Span) -> Result<Option<Document<'a>>> {
+        let import_error = |resolved: &Path, source, suggestions| SymError::ImportError {
+            written: path.into(),
+            path: Arc::from(resolved),
+            source,
+            location: Some(Location::from(span)),
+            suggestions,
+        };
+        // Until the resolver finds the file, it is taken relative to the importer
+        let importer = self.context.stack.last().map(PathBuf::as_path);
+        let joined = normalize(&importer.and_then(Path::parent).unwrap_or(Path::new("")).join(path));
+        if !self.options.imports {
+            let source = std::io::Error::new(ErrorKind::PermissionDenied, "imports are disabled");
+            return Err(import_error(&joined, source, Vec::new()));
+        }
+        let id = match self.resolver().resolve(importer, path) {
+            Ok(id) => id,
+            Err(source) => {
+                let suggestions = suggest_paths(self.resolver(), importer, path);
+                return Err(import_error(&joined, source, suggestions));
+            }
+        };
+
+        if let Some(chain) = self.context.cycle_to(&id) {
+            return Err(SymError::ImportCycle {
+                chain,
+                location: Location::from(span),
+            });
+        }
+        if let Some(importer) = self.context.stack.last() {
+            let edge = (importer.clone(), id.clone());
+            if self.context.graph.edges.contains(&edge) {
+                let message = format!("{} is already imported by this file", path);
+                self.warn(WarningCode::RepeatedImport, message, span);
+            }
+            self.context.graph.edges.push(edge);
+        }
+        if self.context.graph.files.contains(&id) {
+            return Ok(None);
+        }
+        self.context.graph.files.push(id.clone());
+        self.context.record_site(&id, span);
+
+        let content = self
+            .resolver()
+            .load(&id)
+            .map_err(|source| import_error(&id, source, Vec::new()))?;
+
+        // The imported file shares our import state while it is parsed
+        let mut parser = Parser::new(&content);
+        parser.path = Some(Arc::from(id.as_path()));
+        parser.resolver = self.resolver;
+        parser.options = self.options.clone();
+        parser.context = std::mem::take(&mut self.context);
+        parser.context.stack.push(id.clone());
+        parser.recover = self.recover;
+        let document = parser.parse_document();
+        self.context = std::mem::take(&mut parser.context);
+        self.errors.append(&mut parser.errors);
+        self.warnings.get_mut().append(parser.warnings.get_mut());
+        self.context.stack.pop();
+        self.context.graph.load_order.push(id);
+
+        // The file's text is dropped here, so its strings are copied
+        document.map(|document| Some(document.into_owned()))
     }
 
     /// Check if we're at an @import directive
@@ -142,52 +825,63 @@ impl<'a> Parser<'a> {
         self.skip_horizontal_whitespace();
 
         // Parse path until end of line
-        let mut path = String::new();
-        while let Some(&ch) = self.chars.peek() {
-            if ch == '\n' || ch == '\r' {
-                break;
-            }
-            path.push(self.advance().unwrap());
-        }
+        let rest = self.rest();
+        let path = rest[..line_len(rest)].trim().to_string();
+        self.advance_by(line_len(rest));
 
-        Ok(path.trim().to_string())
+        Ok(path)
     }
 
     /// Parse a value
-    fn parse_value(&mut self) -> Result<Value> {
+    fn parse_value(&mut self) -> Result<Node<'a>> {
         self.skip_whitespace_and_comments();
 
-        let ch = match self.chars.peek() {
-            Some(&c) => c,
-            None => return Err(self.error("Unexpected end of input")),
+        let ch = match self.peek() {
+            Some(c) => c,
+            None => return Err(self.error(ErrorCode::UnexpectedEnd, "Unexpected end of input")),
         };
 
         match ch {
-            '{' => self.parse_object(),
-            '[' => self.parse_array(),
+            '{' => self.nested(Self::parse_object),
+            '[' => self.nested(Self::parse_array),
             ':' => self.parse_symbol_or_key(),
-            '$' => self.parse_variable_ref(),
             '\\' => self.parse_escaped_value(),
+            '}' | ']' => Err(self.error(ErrorCode::UnexpectedClose, &format!("Unexpected '{}'", ch))),
             _ => self.parse_literal_or_string(),
         }
     }
 
+    /// Parse an array or object, one level deeper than the current position
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Node<'a>>) -> Result<Node<'a>> {
+        let max = self.options.limits.max_depth;
+        if self.depth >= max {
+            return Err(self.limit_error(Limit::Depth, max, &self.mark()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
     /// Parse an object { :key value, ... }
-    fn parse_object(&mut self) -> Result<Value> {
+    fn parse_object(&mut self) -> Result<Node<'a>> {
+        let start = self.mark();
         self.expect('{')?;
         self.skip_whitespace_and_comments();
 
-        let mut map = HashMap::new();
+        let mut fields = Vec::new();
 
         // Empty object
         if self.check('}') {
             self.advance();
-            return Ok(Value::Object(map));
+            return Ok(self.node(NodeKind::Object(fields), start));
         }
 
         // First field (no leading comma)
-        let (key, modifier, value) = self.parse_field()?;
-        self.apply_field(&mut map, key, modifier, value)?;
+        match self.parse_field() {
+            Ok(field) => fields.push(field),
+            Err(error) => self.recover(error)?,
+        }
 
         // Remaining fields (with separator)
         loop {
@@ -201,10 +895,21 @@ impl<'a> Parser<'a> {
             // Expect separator: newline then comma
             if !self.check_separator() {
                 if self.is_at_end() {
-                    return Err(self.error("Unclosed object"));
+                    self.report(self.error(ErrorCode::UnclosedObject, "Unclosed object"))?;
+                    break;
+                }
+                // Close the object at a `]`, as `parse_array` does at a `}`
+                if self.check(']') {
+                    let message = "Unclosed '{', expected '}' before ']'";
+                    self.report(self.error_at(ErrorCode::UnclosedObject, message, &start))?;
+                    if self.separator_after_closer() {
+                        self.advance();
+                    }
+                    break;
                 }
                 // Maybe it's the next field on a new line without comma (error)
-                return Err(self.error("Expected ',' separator"));
+                self.recover_separator()?;
+                continue;
             }
 
             self.consume_separator();
@@ -215,62 +920,119 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let (key, modifier, value) = self.parse_field()?;
-            self.apply_field(&mut map, key, modifier, value)?;
+            match self.parse_field() {
+                Ok(field) => fields.push(field),
+                Err(error) => self.recover(error)?,
+            }
         }
 
-        Ok(Value::Object(map))
+        Ok(self.node(NodeKind::Object(fields), start))
+    }
+
+    /// Check a key repeated within one object literal without `!` or `+`
+    ///
+    /// `seen` holds the keys of the literal's earlier fields.
+    fn check_duplicate(&self, seen: &mut HashSet<Cow<'a, str>>, field: &Field<'a>) -> Result<()> {
+        if seen.insert(field.key.clone()) || field.modifier != KeyModifier::Merge {
+            return Ok(());
+        }
+        if self.options.duplicate_keys == DuplicateKeys::Reject {
+            return Err(SymError::DuplicateKey {
+                key: field.key.to_string(),
+                location: Location::from(&field.key_span),
+            });
+        }
+        let message = format!(
+            "Key :{} is repeated and deep merged into the earlier value (use :{}! to replace it)",
+            field.key, field.key
+        );
+        self.warn(WarningCode::DuplicateKey, message, &field.key_span);
+        Ok(())
     }
 
     /// Apply a field to the object, handling modifiers
-    fn apply_field(
-        &self,
-        map: &mut HashMap<String, Value>,
-        key: String,
-        modifier: KeyModifier,
-        value: Value,
-    ) -> Result<()> {
+    fn apply_field<V: Evaluated<'a>>(&self, map: &mut IndexMap<Cow<'a, str>, V::Field>, field: Field<'a>) -> Result<()> {
+        let Field {
+            key,
+            key_span,
+            modifier,
+            value,
+        } = field;
+
         match modifier {
             KeyModifier::Merge => {
                 if let Some(existing) = map.get_mut(&key) {
-                    existing.deep_merge(value);
+                    self.merge_node(V::field_value(existing), value)?;
                 } else {
-                    map.insert(key, value);
+                    let value = self.substitute_variables(value)?;
+                    map.insert(key, V::field(value, key_span));
                 }
             }
             KeyModifier::Replace => {
-                map.insert(key, value);
+                let value = self.substitute_variables(value)?;
+                map.insert(key, V::field(value, key_span));
             }
             KeyModifier::Append => {
+                let value: V = self.substitute_variables(value)?;
                 if let Some(existing) = map.get_mut(&key) {
-                    match (existing, value) {
-                        (Value::Array(arr), Value::Array(new_items)) => {
+                    match (V::field_value(existing).as_array_mut(), value.into_array()) {
+                        (Some(arr), Some(new_items)) => {
                             arr.extend(new_items);
+                            self.check_array(arr.len(), &key_span)?;
+                        }
+                        _ => {
+                            return Err(SymError::AppendToNonArray {
+                                location: Location::from(&key_span),
+                                key: key.into_owned(),
+                            })
                         }
-                        _ => return Err(SymError::AppendToNonArray(key)),
                     }
                 } else {
-                    map.insert(key, value);
+                    map.insert(key, V::field(value, key_span));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge a node into an existing value
+    ///
+    /// Objects are merged field by field so that nested `!` and `+` modifiers
+    /// still apply; anything else is substituted and deep merged.
+    fn merge_node<V: Evaluated<'a>>(&self, existing: &mut V, node: Node<'a>) -> Result<()> {
+        match (existing.as_object_mut(), node.kind) {
+            (Some(map), NodeKind::Object(fields)) => {
+                let mut seen = HashSet::new();
+                for field in fields {
+                    self.check_duplicate(&mut seen, &field)?;
+                    self.apply_field::<V>(map, field)?;
                 }
             }
+            (_, kind) => {
+                let value = self.substitute_variables(Node { kind, ..node })?;
+                existing.deep_merge(value);
+            }
         }
         Ok(())
     }
 
     /// Parse a single field: :key value or $key value
-    fn parse_field(&mut self) -> Result<(String, KeyModifier, Value)> {
+    fn parse_field(&mut self) -> Result<Field<'a>> {
         self.skip_whitespace_and_comments();
 
-        let ch = self.chars.peek().copied();
+        let ch = self.peek();
 
         match ch {
             Some(':') => {
                 // Object key
+                let start = self.mark();
                 self.advance(); // consume ':'
                 let key = self.parse_identifier()?;
+                let key_span = self.span_from(start);
 
                 // Check for modifier
                 let modifier = self.parse_key_modifier();
+                self.check_modifier(&format!(":{}", key), modifier, &key_span)?;
 
                 self.skip_horizontal_whitespace();
 
@@ -278,15 +1040,24 @@ impl<'a> Parser<'a> {
                 let value = if self.check_value_start() {
                     self.parse_value()?
                 } else {
-                    Value::String(String::new())
+                    let message = format!("Key :{} has no value and is read as an empty string", key);
+                    self.warn(WarningCode::EmptyValue, message, &key_span);
+                    self.empty_string()
                 };
 
-                Ok((key, modifier, value))
+                Ok(Field {
+                    key: Cow::Borrowed(key),
+                    key_span,
+                    modifier,
+                    value,
+                })
             }
             Some('$') => {
                 // Variable definition
+                let start = self.mark();
                 self.advance(); // consume '$'
                 let key = self.parse_identifier()?;
+                let key_span = self.span_from(start);
 
                 // Check for override modifier
                 let modifier = if self.check('!') {
@@ -295,25 +1066,31 @@ impl<'a> Parser<'a> {
                 } else {
                     KeyModifier::Merge
                 };
+                self.check_modifier(&format!("${}", key), modifier, &key_span)?;
 
                 self.skip_horizontal_whitespace();
 
                 let value = if self.check_value_start() {
                     self.parse_value()?
                 } else {
-                    Value::String(String::new())
+                    self.empty_string()
                 };
 
                 // Store with $ prefix so we know it's a var def
-                Ok((format!("${}", key), modifier, value))
+                Ok(Field {
+                    key: Cow::Owned(format!("${}", key)),
+                    key_span,
+                    modifier,
+                    value,
+                })
             }
-            _ => Err(self.error("Expected ':' or '$' at start of field")),
+            _ => Err(self.error(ErrorCode::ExpectedField, "Expected ':' or '$' at start of field")),
         }
     }
 
     /// Parse key modifier (! or +)
     fn parse_key_modifier(&mut self) -> KeyModifier {
-        match self.chars.peek() {
+        match self.peek() {
             Some('!') => {
                 self.advance();
                 KeyModifier::Replace
@@ -326,8 +1103,37 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Reject a modifier-like character after a key or variable name, e.g. `:key?`
+    ///
+    /// `name` is the key with its `:` or `$`, and `modifier` what was read after it.
+    fn check_modifier(&mut self, name: &str, modifier: KeyModifier, span: &Span) -> Result<()> {
+        if !self.peek().is_some_and(is_modifier_like) {
+            return Ok(());
+        }
+        let mut written = match modifier {
+            KeyModifier::Merge => String::new(),
+            KeyModifier::Replace => "!".to_string(),
+            KeyModifier::Append => "+".to_string(),
+        };
+        while let Some(c) = self.peek().filter(|&c| is_modifier_like(c)) {
+            written.push(c);
+            self.advance();
+        }
+
+        // Variables can only be overridden
+        let valid: &[&str] = if name.starts_with('$') { &["", "!"] } else { &["", "!", "+"] };
+        let candidates: Vec<String> = valid.iter().map(|m| format!("{}{}", name, m)).collect();
+        Err(SymError::ParseError {
+            message: format!("Invalid key modifier `{}` on {}: only ! and + are allowed", written, name),
+            code: ErrorCode::InvalidModifier,
+            location: Location::from(span),
+            suggestions: suggest(&format!("{}{}", name, written), candidates.iter().map(String::as_str)),
+        })
+    }
+
     /// Parse an array [ value, ... ]
-    fn parse_array(&mut self) -> Result<Value> {
+    fn parse_array(&mut self) -> Result<Node<'a>> {
+        let start = self.mark();
         self.expect('[')?;
         self.skip_whitespace_and_comments();
 
@@ -336,12 +1142,17 @@ impl<'a> Parser<'a> {
         // Empty array
         if self.check(']') {
             self.advance();
-            return Ok(Value::Array(arr));
+            return Ok(self.node(NodeKind::Array(arr), start));
         }
 
         // First element (no leading comma)
-        let value = self.parse_value()?;
-        arr.push(value);
+        match self.parse_array_element() {
+            Ok(value) => {
+                arr.push(value);
+                self.check_array(arr.len(), &start)?;
+            }
+            Err(error) => self.recover(error)?,
+        }
 
         // Remaining elements (with separator)
         loop {
@@ -354,9 +1165,21 @@ impl<'a> Parser<'a> {
 
             if !self.check_separator() {
                 if self.is_at_end() {
-                    return Err(self.error("Unclosed array"));
+                    self.report(self.error(ErrorCode::UnclosedArray, "Unclosed array"))?;
+                    break;
+                }
+                // Close the array at a `}`, taking it for a mistyped `]` when a
+                // separator follows and leaving it to the enclosing object otherwise
+                if self.check('}') {
+                    let message = "Unclosed '[', expected ']' before '}'";
+                    self.report(self.error_at(ErrorCode::UnclosedArray, message, &start))?;
+                    if self.separator_after_closer() {
+                        self.advance();
+                    }
+                    break;
                 }
-                return Err(self.error("Expected ',' separator"));
+                self.recover_separator()?;
+                continue;
             }
 
             self.consume_separator();
@@ -367,129 +1190,201 @@ impl<'a> Parser<'a> {
                 break;
             }
 
-            let value = self.parse_value()?;
-            arr.push(value);
+            match self.parse_array_element() {
+                Ok(value) => {
+                    arr.push(value);
+                    self.check_array(arr.len(), &start)?;
+                }
+                Err(error) => self.recover(error)?,
+            }
         }
 
-        Ok(Value::Array(arr))
+        Ok(self.node(NodeKind::Array(arr), start))
+    }
+
+    /// Parse an array element
+    ///
+    /// A `$name` followed by an inline `,` is a whole element and keeps the
+    /// variable's value; only a reference within other text is interpolated.
+    fn parse_array_element(&mut self) -> Result<Node<'a>> {
+        self.skip_whitespace_and_comments();
+        let rest = self.rest();
+        if starts_var_ref(rest) {
+            let after = &rest[var_ref_len(rest)..];
+            if after[horizontal_whitespace_len(after)..].starts_with(',') {
+                let start = self.mark();
+                let var = self.parse_variable_ref();
+                return Ok(self.node(NodeKind::Variable(var), start));
+            }
+        }
+        self.parse_value()
     }
 
     /// Parse a symbol :name (in value position)
-    fn parse_symbol_or_key(&mut self) -> Result<Value> {
+    fn parse_symbol_or_key(&mut self) -> Result<Node<'a>> {
+        let start = self.mark();
         self.advance(); // consume ':'
         let name = self.parse_identifier()?;
-        Ok(Value::Symbol(name))
+        Ok(self.node(NodeKind::Value(SpannedKind::Symbol(name.into())), start))
     }
 
-    /// Parse a variable reference $name
-    fn parse_variable_ref(&mut self) -> Result<Value> {
-        self.advance(); // consume '$'
-        let name = self.parse_identifier()?;
-        // Return as a placeholder - will be substituted later
-        Ok(Value::String(format!("${}", name)))
+    /// Parse a variable reference $name inside a string
+    ///
+    /// Hyphens belong to the name only when another name character follows,
+    /// so `$env-$region` refers to `$env` and `$region`.
+    fn parse_variable_ref(&mut self) -> VarRef {
+        let start = self.mark();
+        let len = var_ref_len(self.rest());
+        let name = self.rest()[1..len].to_string();
+        self.advance_by(len);
+
+        // Substituted once all definitions are known
+        VarRef {
+            name,
+            span: self.span_from(start),
+        }
     }
 
-    /// Parse an escaped value \something
-    fn parse_escaped_value(&mut self) -> Result<Value> {
-        self.advance(); // consume '\'
+    /// Parse an environment variable reference inside a string
+    fn parse_env_ref(&mut self) -> Result<EnvRef> {
+        let start = self.mark();
+        let token = match env_ref(self.rest()) {
+            Ok(token) => token,
+            Err((offset, code, message)) => {
+                self.advance_by(offset);
+                return Err(self.error(code, message));
+            }
+        };
+        let name = token.name.to_string();
+        let fallback = match token.fallback {
+            Some(('-', text)) => EnvFallback::Default(text.to_string()),
+            Some((_, text)) => EnvFallback::Required(text.to_string()),
+            None => EnvFallback::Empty,
+        };
+        self.advance_by(token.len);
 
-        // The rest is a literal string
-        let content = self.parse_string_content()?;
-        Ok(Value::String(content))
+        Ok(EnvRef {
+            name,
+            fallback,
+            span: self.span_from(start),
+        })
     }
 
-    /// Parse a literal (number, bool, null) or string
-    fn parse_literal_or_string(&mut self) -> Result<Value> {
-        let start_pos = self.pos;
+    /// Parse an escaped value \something
+    fn parse_escaped_value(&mut self) -> Result<Node<'a>> {
+        // The leading '\' escapes the first character, like any other escape
+        let start = self.mark();
+        let (segments, end) = self.parse_string_content()?;
+        self.check_string(text_len(&segments), &start)?;
+        Ok(string_node(segments, Span { end, ..start }))
+    }
 
-        // Peek at what we have
-        let first_line = self.peek_to_end_of_line();
+    /// Parse a literal (number, bool, null) or string
+    fn parse_literal_or_string(&mut self) -> Result<Node<'a>> {
+        let start = self.mark();
+        let rest = self.rest();
 
         // Try to parse as number, bool, or null
-        if let Some(value) = self.try_parse_number(&first_line) {
-            // Advance past the number
-            let num_str = self.extract_number_str(&first_line);
-            for _ in 0..num_str.len() {
-                self.advance();
-            }
-            return Ok(value);
+        if let Some((value, len)) = parse_literal(rest, &self.options) {
+            self.advance_by(len);
+            let kind = SpannedValue::from_value(value, &start).kind;
+            return Ok(self.node(NodeKind::Value(kind), start));
         }
 
-        // Check for boolean/null keywords (only at value start)
-        if first_line.starts_with("true") && !self.is_identifier_char_at(&first_line, 4) {
-            for _ in 0..4 {
-                self.advance();
-            }
-            return Ok(Value::Bool(true));
+        // A string on one line with nothing to unescape or substitute borrows
+        // from the source
+        if let Some((text, end)) = self.parse_verbatim() {
+            self.check_string(text.len(), &start)?;
+            let kind = NodeKind::Value(SpannedKind::String(Cow::Borrowed(text)));
+            return Ok(Node { kind, span: Span { end, ..start } });
         }
-        if first_line.starts_with("false") && !self.is_identifier_char_at(&first_line, 5) {
-            for _ in 0..5 {
-                self.advance();
-            }
-            return Ok(Value::Bool(false));
-        }
-        if first_line.starts_with("null") && !self.is_identifier_char_at(&first_line, 4) {
-            for _ in 0..4 {
-                self.advance();
-            }
-            return Ok(Value::Null);
-        }
-        if first_line.starts_with("inf") && !self.is_identifier_char_at(&first_line, 3) {
-            for _ in 0..3 {
-                self.advance();
-            }
-            return Ok(Value::Float(f64::INFINITY));
-        }
-        if first_line.starts_with("-inf") && !self.is_identifier_char_at(&first_line, 4) {
-            for _ in 0..4 {
-                self.advance();
+
+        // It's a string - parse multiline string content
+        let (segments, end) = self.parse_string_content()?;
+        self.check_string(text_len(&segments), &start)?;
+        Ok(string_node(segments, Span { end, ..start }))
+    }
+
+    /// Parse a string that is exactly its source text, ending the line it
+    /// starts on, and return it with the byte offset just past it
+    ///
+    /// Leaves the position where [`Self::parse_string_content`] would, or
+    /// returns `None` without moving if the string has escapes, references
+    /// or continues on the next line.
+    fn parse_verbatim(&mut self) -> Option<(&'a str, usize)> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let mut len = 0;
+        let mut text_len = None;
+        let mut line_end = None;
+        while let Some(&b) = bytes.get(len) {
+            match b {
+                b'}' | b']' => break,
+                b'\n' => {
+                    line_end = Some(len);
+                    break;
+                }
+                b'\r' if bytes.get(len + 1) == Some(&b'\n') => {
+                    line_end = Some(len + 1);
+                    break;
+                }
+                b'\\' | b'$' | b'\r' => return None,
+                b'/' if len > 0 && matches!(bytes[len - 1], b' ' | b'\t') && bytes.get(len + 1) == Some(&b'/') => {
+                    // An inline comment ends the text, but not the line
+                    text_len = Some(len);
+                    len += line_comment_len(&rest[len..]);
+                    if len < rest.len() {
+                        line_end = Some(len);
+                    }
+                    break;
+                }
+                _ => len += 1,
             }
-            return Ok(Value::Float(f64::NEG_INFINITY));
         }
-        if first_line.starts_with("nan") && !self.is_identifier_char_at(&first_line, 3) {
-            for _ in 0..3 {
-                self.advance();
+
+        // Lines are joined unless a separator or closing bracket follows
+        if let Some(newline) = line_end {
+            let next = &rest[newline + 1..];
+            let next = &next[horizontal_whitespace_len(next)..];
+            if !matches!(next.as_bytes().first(), None | Some(b',' | b'}' | b']')) {
+                return None;
             }
-            return Ok(Value::Float(f64::NAN));
         }
 
-        // It's a string - parse multiline string content
-        let content = self.parse_string_content()?;
-        Ok(Value::String(content))
-    }
-
-    /// Check if character at position is an identifier char
-    fn is_identifier_char_at(&self, s: &str, pos: usize) -> bool {
-        s.chars()
-            .nth(pos)
-            .map(|c| c.is_alphanumeric() || c == '_' || c == '-')
-            .unwrap_or(false)
+        let text = rest[..text_len.unwrap_or(len)].trim_end();
+        let end = self.pos + text.len();
+        self.advance_by(line_end.unwrap_or(len));
+        if line_end.is_some() {
+            self.advance(); // consume newline
+            self.skip_horizontal_whitespace();
+        }
+        Some((text, end))
     }
 
-    /// Parse string content (potentially multiline)
-    fn parse_string_content(&mut self) -> Result<String> {
-        let mut lines: Vec<String> = Vec::new();
-        let mut current_line = String::new();
+    /// Parse string content (potentially multiline) with its variable references
+    ///
+    /// Also returns the byte offset just past the last character of the value,
+    /// excluding trailing whitespace and comments.
+    fn parse_string_content(&mut self) -> Result<(Vec<Segment>, usize)> {
+        let mut lines: Vec<Vec<Segment>> = Vec::new();
+        let mut current_line: Vec<Segment> = Vec::new();
         let mut preserve_whitespace = false;
+        let mut end = self.pos;
 
         loop {
-            match self.chars.peek().copied() {
+            match self.peek() {
                 None => break,
                 Some('}') | Some(']') => break,
                 Some('\n') => {
                     // End of line - save it
-                    let trimmed = if preserve_whitespace {
-                        current_line.trim_end().to_string()
-                    } else {
-                        current_line.trim().to_string()
-                    };
+                    let trimmed = trim_line(current_line, preserve_whitespace);
 
                     if !trimmed.is_empty() || !lines.is_empty() {
                         lines.push(trimmed);
                     }
 
                     self.advance(); // consume newline
-                    current_line = String::new();
+                    current_line = Vec::new();
                     preserve_whitespace = false;
 
                     // Check for separator (newline + whitespace + comma)
@@ -518,294 +1413,297 @@ impl<'a> Parser<'a> {
                 Some('\\') => {
                     self.advance();
                     // Check what follows
-                    match self.chars.peek().copied() {
+                    match self.peek() {
                         Some(' ') | Some('\t') => {
                             // Preserve whitespace for this line
                             preserve_whitespace = true;
-                            current_line.push(self.advance().unwrap());
+                            push_char(&mut current_line, self.advance().unwrap());
                         }
                         Some('\\') => {
-                            current_line.push(self.advance().unwrap());
+                            push_char(&mut current_line, self.advance().unwrap());
                         }
                         Some(',') => {
                             // Escaped comma - literal comma
-                            current_line.push(self.advance().unwrap());
+                            push_char(&mut current_line, self.advance().unwrap());
+                        }
+                        Some('$') if self.input[self.pos + 1..].starts_with('{') => {
+                            // Literal `${...}`, closed like a reference at the first `}`
+                            let rest = self.rest();
+                            let len = match rest.find(['}', '\n']) {
+                                Some(i) if rest[i..].starts_with('}') => i + 1,
+                                _ => 2,
+                            };
+                            push_text(&mut current_line, &rest[..len]);
+                            self.advance_by(len);
                         }
-                        Some(c) => {
+                        Some(_) => {
                             // Other escape - include the character
-                            current_line.push(self.advance().unwrap());
+                            push_char(&mut current_line, self.advance().unwrap());
                         }
                         None => {
-                            current_line.push('\\');
+                            push_char(&mut current_line, '\\');
                         }
                     }
+                    end = self.pos;
                 }
                 Some(ch) => {
-                    // Check for inline comment (whitespace + //)
+                    // Whitespace followed by `//` starts an inline comment
                     if ch == ' ' || ch == '\t' {
-                        let remaining = &self.input[self.pos..];
-                        if let Some(comment_pos) = remaining.find("//") {
-                            // Check if there's only whitespace before //
-                            let before = &remaining[..comment_pos];
-                            if before.chars().all(|c| c == ' ' || c == '\t') {
-                                // This is an inline comment - skip to end of line
-                                current_line.push_str(before);
-                                for _ in 0..comment_pos {
-                                    self.advance();
-                                }
-                                self.skip_line_comment();
-                                continue;
-                            }
+                        let blank = &self.rest()[..horizontal_whitespace_len(self.rest())];
+                        push_text(&mut current_line, blank);
+                        self.advance_by(blank.len());
+                        if self.check_line_comment_start() {
+                            self.skip_line_comment();
                         }
+                        continue;
                     }
-                    current_line.push(self.advance().unwrap());
+
+                    // Variable reference: $ followed by the start of a name
+                    if starts_var_ref(self.rest()) {
+                        let var = self.parse_variable_ref();
+                        current_line.push(Segment::Variable(var));
+                        end = self.pos;
+                        continue;
+                    }
+
+                    // Environment variable reference: ${NAME}
+                    if ch == '$' && self.input[self.pos + 1..].starts_with('{') {
+                        let env = self.parse_env_ref()?;
+                        current_line.push(Segment::Env(env));
+                        end = self.pos;
+                        continue;
+                    }
+
+                    if ch.is_whitespace() {
+                        push_char(&mut current_line, self.advance().unwrap());
+                        continue;
+                    }
+
+                    // Plain text up to the next character with a meaning
+                    let rest = self.rest();
+                    let len = plain_text_len(rest);
+                    push_text(&mut current_line, &rest[..len]);
+                    self.advance_by(len);
+                    end = self.pos;
                 }
             }
         }
 
         // Handle last line
-        let trimmed = if preserve_whitespace {
-            current_line.trim_end().to_string()
-        } else {
-            current_line.trim().to_string()
-        };
+        let trimmed = trim_line(current_line, preserve_whitespace);
         if !trimmed.is_empty() {
             lines.push(trimmed);
         }
 
-        Ok(lines.join("\n"))
-    }
-
-    /// Try to parse a number from the start of a string
-    fn try_parse_number(&self, s: &str) -> Option<Value> {
-        let s = s.trim_start();
-
-        // Hex
-        if s.starts_with("0x") || s.starts_with("0X") {
-            let num_part: String = s[2..]
-                .chars()
-                .take_while(|c| c.is_ascii_hexdigit() || *c == '_')
-                .collect();
-            if num_part.is_empty() {
-                return None;
-            }
-            let clean: String = num_part.chars().filter(|c| *c != '_').collect();
-            return i64::from_str_radix(&clean, 16).ok().map(Value::Int);
-        }
-
-        // Binary
-        if s.starts_with("0b") || s.starts_with("0B") {
-            let num_part: String = s[2..]
-                .chars()
-                .take_while(|c| *c == '0' || *c == '1' || *c == '_')
-                .collect();
-            if num_part.is_empty() {
-                return None;
+        // Join lines with newlines
+        let mut segments = Vec::new();
+        for (i, line) in lines.into_iter().enumerate() {
+            if i > 0 {
+                push_char(&mut segments, '\n');
             }
-            let clean: String = num_part.chars().filter(|c| *c != '_').collect();
-            return i64::from_str_radix(&clean, 2).ok().map(Value::Int);
-        }
-
-        // Octal
-        if s.starts_with("0o") || s.starts_with("0O") {
-            let num_part: String = s[2..]
-                .chars()
-                .take_while(|c| c.is_digit(8) || *c == '_')
-                .collect();
-            if num_part.is_empty() {
-                return None;
+            for segment in line {
+                match segment {
+                    Segment::Text(text) => push_text(&mut segments, &text),
+                    var => segments.push(var),
+                }
             }
-            let clean: String = num_part.chars().filter(|c| *c != '_').collect();
-            return i64::from_str_radix(&clean, 8).ok().map(Value::Int);
         }
+        Ok((segments, end))
+    }
 
-        // Decimal (int or float)
-        let mut chars = s.chars().peekable();
-        let mut num_str = String::new();
-        let mut is_float = false;
-
-        // Optional negative sign
-        if chars.peek() == Some(&'-') {
-            num_str.push(chars.next().unwrap());
-        }
+    /// Parse an identifier
+    fn parse_identifier(&mut self) -> Result<&'a str> {
+        // A letter or underscore, then digits and hyphens too
+        let rest = self.rest();
+        let Some(len) = name_len(rest) else {
+            return Err(self.error(ErrorCode::ExpectedIdentifier, "Expected identifier"));
+        };
+        self.advance_by(len);
+        Ok(&rest[..len])
+    }
 
-        // Integer part
-        let mut has_digits = false;
-        while let Some(&c) = chars.peek() {
-            if c.is_ascii_digit() {
-                has_digits = true;
-                num_str.push(chars.next().unwrap());
-            } else if c == '_' && has_digits {
-                chars.next();
-            } else {
-                break;
+    /// Substitute variables in a node, producing the final value
+    fn substitute_variables<V: Evaluated<'a>>(&self, node: Node<'a>) -> Result<V> {
+        let Node { kind, span } = node;
+        match kind {
+            NodeKind::Value(kind) => {
+                self.add_output(output_size(&kind), &span)?;
+                Ok(V::scalar(kind, span, Vec::new()))
             }
-        }
-
-        if !has_digits {
-            return None;
-        }
-
-        // Decimal part
-        if chars.peek() == Some(&'.') {
-            // Check it's followed by a digit
-            let mut chars_clone = chars.clone();
-            chars_clone.next();
-            if chars_clone.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
-                is_float = true;
-                num_str.push(chars.next().unwrap()); // consume '.'
-                while let Some(&c) = chars.peek() {
-                    if c.is_ascii_digit() {
-                        num_str.push(chars.next().unwrap());
-                    } else if c == '_' {
-                        chars.next();
-                    } else {
-                        break;
-                    }
+            NodeKind::Variable(var) => match self.variables.get(&var.name) {
+                Some(value) => {
+                    self.add_output(output_size(&value.kind), &span)?;
+                    self.used.borrow_mut().insert(var.name.clone());
+                    Ok(V::substituted(value, var, span))
                 }
+                // `$env-suffix` where only `$env` is defined
+                None => self.substitute_template(vec![Segment::Variable(var)], span),
+            },
+            NodeKind::Template(segments) => self.substitute_template(segments, span),
+            NodeKind::Array(arr) => {
+                self.add_output(1, &span)?;
+                let new_arr: Result<Vec<V>> = arr.into_iter().map(|n| self.substitute_variables(n)).collect();
+                Ok(V::array(new_arr?, span))
             }
-        }
-
-        // Exponent part
-        if chars.peek() == Some(&'e') || chars.peek() == Some(&'E') {
-            is_float = true;
-            num_str.push(chars.next().unwrap());
-            if chars.peek() == Some(&'+') || chars.peek() == Some(&'-') {
-                num_str.push(chars.next().unwrap());
-            }
-            while let Some(&c) = chars.peek() {
-                if c.is_ascii_digit() {
-                    num_str.push(chars.next().unwrap());
-                } else {
-                    break;
+            NodeKind::Object(fields) => {
+                let keys: usize = fields.iter().map(|field| field.key.len()).sum();
+                self.add_output(1 + keys, &span)?;
+                let mut map = IndexMap::new();
+                let mut seen = HashSet::new();
+                for field in fields {
+                    self.check_duplicate(&mut seen, &field)?;
+                    self.apply_field::<V>(&mut map, field)?;
                 }
+                Ok(V::object(map, span))
             }
         }
+    }
 
-        // Make sure the next char isn't an identifier char
-        if let Some(&c) = chars.peek() {
-            if c.is_alphabetic() || c == '_' {
-                return None;
-            }
-        }
-
-        // Parse
-        let clean: String = num_str.chars().filter(|c| *c != '_').collect();
-        if is_float {
-            clean.parse::<f64>().ok().map(Value::Float)
-        } else {
-            clean.parse::<i64>().ok().map(Value::Int)
-        }
+    /// Build a string value from its segments
+    fn substitute_template<V: Evaluated<'a>>(&self, segments: Vec<Segment>, span: Span) -> Result<V> {
+        let mut refs = Vec::new();
+        let text = self.interpolate(segments, &span, &mut refs)?;
+        self.add_output(1 + text.len(), &span)?;
+        Ok(V::scalar(SpannedKind::String(Cow::Owned(text)), span, refs))
     }
 
-    /// Extract the number string from input
-    fn extract_number_str(&self, s: &str) -> String {
-        let s = s.trim_start();
+    /// Interpolate variables into a string, recording the references used
+    ///
+    /// A reference uses the longest hyphen-separated prefix of its name that
+    /// is defined, so `$env-vpc` becomes the value of `$env` followed by `-vpc`.
+    fn interpolate(&self, segments: Vec<Segment>, span: &Span, refs: &mut Vec<SpannedName>) -> Result<String> {
+        let max = self.options.limits.max_string_length;
         let mut result = String::new();
+        for segment in segments {
+            if result.len() > max {
+                return Err(self.limit_error(Limit::StringLength, max, span));
+            }
+            let var = match segment {
+                Segment::Text(text) => {
+                    result.push_str(&text);
+                    continue;
+                }
+                Segment::Env(env) => {
+                    result.push_str(&self.lookup_env(env)?);
+                    continue;
+                }
+                Segment::Variable(var) => var,
+            };
 
-        // Handle prefix
-        if s.starts_with("0x")
-            || s.starts_with("0X")
-            || s.starts_with("0b")
-            || s.starts_with("0B")
-            || s.starts_with("0o")
-            || s.starts_with("0O")
-        {
-            result.push_str(&s[..2]);
-            let rest = &s[2..];
-            for c in rest.chars() {
-                if c.is_alphanumeric() || c == '_' {
-                    result.push(c);
-                } else {
-                    break;
+            let Some(name) = longest_prefix(&var.name, |n| self.variables.contains_key(n)) else {
+                match self.options.undefined_variables {
+                    UndefinedVariables::Error => {}
+                    UndefinedVariables::Literal => {
+                        result.push('$');
+                        result.push_str(&var.name);
+                        continue;
+                    }
+                    UndefinedVariables::Empty => continue,
                 }
+                let defined = self.variables.keys().map(String::as_str);
+                return Err(SymError::UndefinedVariable {
+                    location: Location::from(&var.span),
+                    suggestions: suggest(&var.name, defined),
+                    name: var.name,
+                });
+            };
+            let value = &self.variables[name];
+            let end = name.len();
+            self.used.borrow_mut().insert(name.to_string());
+
+            match &value.kind {
+                SpannedKind::String(s) | SpannedKind::Symbol(s) => result.push_str(s),
+                SpannedKind::Array(_) | SpannedKind::Object(_) => {
+                    return Err(SymError::NonScalarInterpolation {
+                        name: var.name[..end].to_string(),
+                        location: Location::from(&var.span),
+                    })
+                }
+                _ => result.push_str(&value.to_value().to_string()),
             }
-            return result;
+            result.push_str(&var.name[end..]);
+
+            // Only `$env` of `$env-vpc` is a reference
+            refs.push(SpannedName {
+                name: name.to_string(),
+                span: Span {
+                    end: var.span.start + 1 + end,
+                    ..var.span
+                },
+            });
         }
-
-        // Decimal number
-        let mut chars = s.chars().peekable();
-
-        // Sign
-        if chars.peek() == Some(&'-') {
-            result.push(chars.next().unwrap());
+        if result.len() > max {
+            return Err(self.limit_error(Limit::StringLength, max, span));
         }
+        Ok(result)
+    }
 
-        // Digits, dot, exponent
-        let mut in_exponent = false;
-        while let Some(&c) = chars.peek() {
-            if c.is_ascii_digit() || c == '_' {
-                result.push(chars.next().unwrap());
-            } else if c == '.' && !in_exponent {
-                result.push(chars.next().unwrap());
-            } else if (c == 'e' || c == 'E') && !in_exponent {
-                in_exponent = true;
-                result.push(chars.next().unwrap());
-                if chars.peek() == Some(&'+') || chars.peek() == Some(&'-') {
-                    result.push(chars.next().unwrap());
-                }
-            } else {
-                break;
-            }
+    /// Read an environment variable, applying the reference's fallback
+    fn lookup_env(&self, env: EnvRef) -> Result<String> {
+        let value = match &self.env {
+            Some(vars) => vars.get(&env.name).cloned(),
+            None => std::env::var(&env.name).ok(),
+        };
+        match (value, env.fallback) {
+            (Some(value), _) => Ok(value),
+            (None, EnvFallback::Empty) => Ok(String::new()),
+            (None, EnvFallback::Default(default)) => Ok(default),
+            (None, EnvFallback::Required(message)) => Err(SymError::MissingEnvVar {
+                name: env.name,
+                message,
+                location: Location::from(&env.span),
+            }),
         }
-
-        result
     }
 
-    /// Parse an identifier
-    fn parse_identifier(&mut self) -> Result<String> {
-        let mut name = String::new();
+    // Helper methods
 
-        // First char must be letter or underscore
-        match self.chars.peek() {
-            Some(&c) if c.is_alphabetic() || c == '_' => {
-                name.push(self.advance().unwrap());
-            }
-            _ => return Err(self.error("Expected identifier")),
+    /// A zero-length span at the current position
+    fn mark(&self) -> Span {
+        Span {
+            start: self.pos,
+            end: self.pos,
+            line: self.line,
+            col: self.col,
+            file: self.path.clone(),
         }
+    }
 
-        // Rest can include digits and hyphens
-        while let Some(&c) = self.chars.peek() {
-            if c.is_alphanumeric() || c == '_' || c == '-' {
-                name.push(self.advance().unwrap());
-            } else {
-                break;
-            }
+    /// Extend a span started with `mark` up to the current position
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.pos,
+            ..start
         }
+    }
 
-        Ok(name)
+    /// A node spanning from `start` to the current position
+    fn node(&self, kind: NodeKind<'a>, start: Span) -> Node<'a> {
+        Node {
+            kind,
+            span: self.span_from(start),
+        }
     }
 
-    /// Substitute variables in a value
-    fn substitute_variables(&self, value: Value) -> Result<Value> {
-        match value {
-            Value::String(s) if s.starts_with('$') => {
-                let var_name = &s[1..];
-                self.variables
-                    .get(var_name)
-                    .cloned()
-                    .ok_or_else(|| SymError::UndefinedVariable(var_name.to_string()))
-            }
-            Value::Array(arr) => {
-                let new_arr: Result<Vec<Value>> =
-                    arr.into_iter().map(|v| self.substitute_variables(v)).collect();
-                Ok(Value::Array(new_arr?))
-            }
-            Value::Object(obj) => {
-                let new_obj: Result<HashMap<String, Value>> = obj
-                    .into_iter()
-                    .map(|(k, v)| Ok((k, self.substitute_variables(v)?)))
-                    .collect();
-                Ok(Value::Object(new_obj?))
-            }
-            other => Ok(other),
+    /// The empty string of a key written without a value
+    fn empty_string(&self) -> Node<'a> {
+        Node {
+            kind: NodeKind::Value(SpannedKind::String(Cow::Borrowed(""))),
+            span: self.mark(),
         }
     }
 
-    // Helper methods
+    /// The input from the current position on
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        first_char(self.rest())
+    }
 
     fn advance(&mut self) -> Option<char> {
-        let ch = self.chars.next()?;
+        let ch = self.peek()?;
         self.pos += ch.len_utf8();
         if ch == '\n' {
             self.line += 1;
@@ -816,8 +1714,16 @@ impl<'a> Parser<'a> {
         Some(ch)
     }
 
+    /// Advance past the next `len` bytes, which hold no newline
+    fn advance_by(&mut self, len: usize) {
+        let skipped = &self.input[self.pos..self.pos + len];
+        debug_assert!(!skipped.contains('\n'));
+        self.col += skipped.bytes().filter(|&b| b & 0xC0 != 0x80).count();
+        self.pos += len;
+    }
+
     fn check(&mut self, expected: char) -> bool {
-        self.chars.peek() == Some(&expected)
+        self.peek() == Some(expected)
     }
 
     fn expect(&mut self, expected: char) -> Result<()> {
@@ -825,20 +1731,12 @@ impl<'a> Parser<'a> {
             self.advance();
             Ok(())
         } else {
-            Err(self.error(&format!("Expected '{}'", expected)))
+            Err(self.error(ErrorCode::ExpectedCharacter, &format!("Expected '{}'", expected)))
         }
     }
 
     fn is_at_end(&mut self) -> bool {
-        self.chars.peek().is_none()
-    }
-
-    fn peek_to_end_of_line(&self) -> String {
-        self.input[self.pos..]
-            .lines()
-            .next()
-            .unwrap_or("")
-            .to_string()
+        self.peek().is_none()
     }
 
     fn skip_whitespace_and_comments(&mut self) {
@@ -856,7 +1754,7 @@ impl<'a> Parser<'a> {
     }
 
     fn skip_whitespace(&mut self) {
-        while let Some(&c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             if c.is_whitespace() {
                 self.advance();
             } else {
@@ -866,77 +1764,47 @@ impl<'a> Parser<'a> {
     }
 
     fn skip_horizontal_whitespace(&mut self) {
-        while let Some(&c) = self.chars.peek() {
-            if c == ' ' || c == '\t' {
-                self.advance();
-            } else {
-                break;
-            }
-        }
+        self.advance_by(horizontal_whitespace_len(self.rest()));
     }
 
     fn check_line_comment_start(&mut self) -> bool {
-        let remaining = &self.input[self.pos..];
-        remaining.starts_with("//")
+        self.rest().starts_with("//")
     }
 
     fn skip_line_comment(&mut self) {
-        // Consume //
-        self.advance();
-        self.advance();
-
-        // Skip to end of line
-        while let Some(&c) = self.chars.peek() {
-            if c == '\n' {
-                break;
-            }
-            self.advance();
-        }
+        self.advance_by(line_comment_len(self.rest()));
     }
 
     fn check_block_comment_start(&mut self) -> bool {
-        let remaining = &self.input[self.pos..];
-        remaining.starts_with("/*")
+        self.rest().starts_with("/*")
     }
 
     fn skip_block_comment(&mut self) {
-        // Consume /*
-        self.advance();
-        self.advance();
-
-        // Find */
-        loop {
-            match self.advance() {
-                Some('*') => {
-                    if self.check('/') {
-                        self.advance();
-                        break;
-                    }
-                }
-                None => break,
-                _ => {}
+        let comment = &self.rest()[..block_comment_len(self.rest())];
+        match comment.rfind('\n') {
+            Some(last) => {
+                self.line += comment.matches('\n').count();
+                self.col = 1;
+                self.pos += last + 1;
+                self.advance_by(comment.len() - last - 1);
             }
+            None => self.advance_by(comment.len()),
         }
     }
 
+    /// Whether a `,` separator is next, once whitespace and comments are skipped
     fn check_separator(&mut self) -> bool {
-        // Look back to see if we've had a newline, then look for comma
-        let remaining = &self.input[self.pos..];
+        self.check(',')
+    }
 
-        // We should be positioned at or before a comma after newline(s)
-        for (i, c) in remaining.char_indices() {
-            if c == ',' {
-                return true;
-            } else if !c.is_whitespace() {
-                return false;
-            }
-        }
-        false
+    /// Whether a separator follows the closing bracket at the current position
+    fn separator_after_closer(&self) -> bool {
+        self.input[self.pos + 1..].trim_start().starts_with(',')
     }
 
     fn consume_separator(&mut self) {
         // Skip whitespace until we hit comma
-        while let Some(&c) = self.chars.peek() {
+        while let Some(c) = self.peek() {
             if c == ',' {
                 self.advance();
                 break;
@@ -948,18 +1816,432 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Whether a value follows the key just parsed, e.g. a nested object or
+    /// multiline string placed under it
     fn check_value_start(&mut self) -> bool {
-        match self.chars.peek() {
-            Some(&c) => !matches!(c, '\n' | '\r' | ',' | '}' | ']'),
-            None => false,
+        value_follows(self.rest())
+    }
+
+    /// Record a warning at `span`
+    fn warn(&self, code: WarningCode, message: String, span: &Span) {
+        self.warnings.borrow_mut().push(Warning {
+            code,
+            message,
+            location: Location::from(span),
+        });
+    }
+
+    /// The error for going over the `max` of `limit` at `span`
+    fn limit_error(&self, limit: Limit, max: usize, span: &Span) -> SymError {
+        SymError::LimitExceeded {
+            limit,
+            max,
+            location: Some(Location::from(span)),
+        }
+    }
+
+    /// Fail if an array parsed or appended to at `span` has too many elements
+    fn check_array(&self, len: usize, span: &Span) -> Result<()> {
+        let max = self.options.limits.max_array_length;
+        if len > max {
+            return Err(self.limit_error(Limit::ArrayLength, max, span));
+        }
+        Ok(())
+    }
+
+    /// Fail if a string literal starting at `span` has `len` bytes of text,
+    /// before substitution, and that is too long
+    fn check_string(&self, len: usize, span: &Span) -> Result<()> {
+        let max = self.options.limits.max_string_length;
+        if len > max {
+            return Err(self.limit_error(Limit::StringLength, max, span));
         }
+        Ok(())
     }
 
-    fn error(&self, message: &str) -> SymError {
+    /// Fail if a value, including values substituted from variables, nests too
+    /// deeply inside the arrays and objects open at the current position
+    fn check_depth<V: Evaluated<'a>>(&self, value: &V, span: &Span) -> Result<()> {
+        let max = self.options.limits.max_depth;
+        if self.depth + value.nesting_depth() > max {
+            return Err(self.limit_error(Limit::Depth, max, span));
+        }
+        Ok(())
+    }
+
+    /// Count `size` towards the output size limit
+    fn add_output(&self, size: usize, span: &Span) -> Result<()> {
+        let max = self.options.limits.max_output_size;
+        let total = self.output.get().saturating_add(size);
+        self.output.set(total);
+        if total > max {
+            return Err(self.limit_error(Limit::OutputSize, max, span));
+        }
+        Ok(())
+    }
+
+    fn error(&self, code: ErrorCode, message: &str) -> SymError {
+        self.error_at(code, message, &self.mark())
+    }
+
+    fn error_at(&self, code: ErrorCode, message: &str, span: &Span) -> SymError {
         SymError::ParseError {
-            line: self.line,
-            col: self.col,
             message: message.to_string(),
+            code,
+            location: Location::from(span),
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// Record an error when recovering, or fail with it
+    ///
+    /// Only the first of several errors at the same position is kept.
+    fn report(&mut self, error: SymError) -> Result<()> {
+        // Running out of resources is never recovered from
+        if !self.recover || matches!(error, SymError::LimitExceeded { .. }) {
+            return Err(error);
+        }
+        let position = |e: &SymError| match e {
+            SymError::ParseError { location, .. } => Some((location.line, location.col)),
+            _ => None,
+        };
+        let repeated = self
+            .errors
+            .last()
+            .is_some_and(|last| position(last).is_some() && position(last) == position(&error));
+        if !repeated {
+            self.errors.push(error);
+        }
+        Ok(())
+    }
+
+    /// Record an error and skip to where parsing can resume, when recovering
+    fn recover(&mut self, error: SymError) -> Result<()> {
+        self.report(error)?;
+        self.synchronize();
+        Ok(())
+    }
+
+    /// Report a missing separator, skipping a mismatched closing bracket
+    fn recover_separator(&mut self) -> Result<()> {
+        let error = self.error(ErrorCode::MissingSeparator, "Expected ',' separator");
+        if matches!(self.peek(), Some('}' | ']')) {
+            self.report(error)?;
+            self.advance();
+            Ok(())
+        } else {
+            self.recover(error)
+        }
+    }
+
+    /// Skip to the next `\n,` separator or the closing bracket of the
+    /// enclosing object or array, without consuming it
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        let mut line_start = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '{' | '[' => depth += 1,
+                '}' | ']' if depth == 0 => return,
+                '}' | ']' => depth -= 1,
+                ',' if depth == 0 && line_start => return,
+                '\\' => {
+                    // The escaped character has no meaning
+                    self.advance();
+                }
+                _ => {}
+            }
+            line_start = c == '\n' || (line_start && (c == ' ' || c == '\t'));
+            self.advance();
+        }
+    }
+}
+
+impl Document<'_> {
+    /// Copy the strings borrowed from the source
+    fn into_owned(self) -> Document<'static> {
+        Document {
+            defs: self.defs.into_iter().map(Field::into_owned).collect(),
+            layers: self.layers.into_iter().map(Node::into_owned).collect(),
+        }
+    }
+}
+
+impl Field<'_> {
+    /// Copy the strings borrowed from the source
+    fn into_owned(self) -> Field<'static> {
+        Field {
+            key: Cow::Owned(self.key.into_owned()),
+            key_span: self.key_span,
+            modifier: self.modifier,
+            value: self.value.into_owned(),
+        }
+    }
+}
+
+impl Node<'_> {
+    /// Copy the strings borrowed from the source
+    fn into_owned(self) -> Node<'static> {
+        let kind = match self.kind {
+            NodeKind::Value(kind) => {
+                let value = SpannedValue::new(kind, Span::default()).into_owned();
+                NodeKind::Value(value.kind)
+            }
+            NodeKind::Variable(var) => NodeKind::Variable(var),
+            NodeKind::Template(segments) => NodeKind::Template(segments),
+            NodeKind::Array(items) => NodeKind::Array(items.into_iter().map(Node::into_owned).collect()),
+            NodeKind::Object(fields) => NodeKind::Object(fields.into_iter().map(Field::into_owned).collect()),
+        };
+        Node { kind, span: self.span }
+    }
+
+    /// Collect the variable references in this node
+    fn collect_refs<'n>(&'n self, refs: &mut Vec<&'n VarRef>) {
+        match &self.kind {
+            NodeKind::Value(_) => {}
+            NodeKind::Variable(var) => refs.push(var),
+            NodeKind::Template(segments) => {
+                for segment in segments {
+                    if let Segment::Variable(var) = segment {
+                        refs.push(var);
+                    }
+                }
+            }
+            NodeKind::Array(items) => {
+                for item in items {
+                    item.collect_refs(refs);
+                }
+            }
+            NodeKind::Object(fields) => {
+                for field in fields {
+                    field.value.collect_refs(refs);
+                }
+            }
         }
     }
 }
+
+impl<'a> Evaluated<'a> for SpannedValue<'a> {
+    type Field = SpannedField<'a>;
+
+    fn scalar(kind: SpannedKind<'a>, span: Span, refs: Vec<SpannedName>) -> Self {
+        SpannedValue { kind, span, refs }
+    }
+
+    fn substituted(value: &SpannedValue<'a>, var: VarRef, span: Span) -> Self {
+        // Located at the reference rather than the definition
+        let mut value = value.clone();
+        value.span = span;
+        value.refs.insert(0, SpannedName { name: var.name, span: var.span });
+        value
+    }
+
+    fn array(items: Vec<Self>, span: Span) -> Self {
+        SpannedValue::new(SpannedKind::Array(items), span)
+    }
+
+    fn object(map: IndexMap<Cow<'a, str>, SpannedField<'a>>, span: Span) -> Self {
+        SpannedValue::new(SpannedKind::Object(map), span)
+    }
+
+    fn field(value: Self, key_span: Span) -> SpannedField<'a> {
+        SpannedField { key_span, value }
+    }
+
+    fn field_value(field: &mut Self::Field) -> &mut Self {
+        &mut field.value
+    }
+
+    fn as_array_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match &mut self.kind {
+            SpannedKind::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_object_mut(&mut self) -> Option<&mut IndexMap<Cow<'a, str>, SpannedField<'a>>> {
+        match &mut self.kind {
+            SpannedKind::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    fn into_array(self) -> Option<Vec<Self>> {
+        match self.kind {
+            SpannedKind::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn deep_merge(&mut self, other: Self) {
+        SpannedValue::deep_merge(self, other);
+    }
+
+    fn nesting_depth(&self) -> usize {
+        match &self.kind {
+            SpannedKind::Array(arr) => 1 + arr.iter().map(Self::nesting_depth).max().unwrap_or(0),
+            SpannedKind::Object(map) => {
+                1 + map.values().map(|field| field.value.nesting_depth()).max().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl<'a> Evaluated<'a> for BorrowedValue<'a> {
+    type Field = BorrowedValue<'a>;
+
+    fn scalar(kind: SpannedKind<'a>, span: Span, _refs: Vec<SpannedName>) -> Self {
+        SpannedValue::new(kind, span).into_borrowed()
+    }
+
+    fn substituted(value: &SpannedValue<'a>, _var: VarRef, _span: Span) -> Self {
+        value.to_borrowed()
+    }
+
+    fn array(items: Vec<Self>, _span: Span) -> Self {
+        BorrowedValue::Array(items)
+    }
+
+    fn object(map: IndexMap<Cow<'a, str>, Self>, _span: Span) -> Self {
+        BorrowedValue::Object(map)
+    }
+
+    fn field(value: Self, _key_span: Span) -> Self {
+        value
+    }
+
+    fn field_value(field: &mut Self) -> &mut Self {
+        field
+    }
+
+    fn as_array_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match self {
+            BorrowedValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_object_mut(&mut self) -> Option<&mut IndexMap<Cow<'a, str>, Self>> {
+        match self {
+            BorrowedValue::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    fn into_array(self) -> Option<Vec<Self>> {
+        match self {
+            BorrowedValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn deep_merge(&mut self, other: Self) {
+        BorrowedValue::deep_merge(self, other);
+    }
+
+    fn nesting_depth(&self) -> usize {
+        match self {
+            BorrowedValue::Array(arr) => 1 + arr.iter().map(Self::nesting_depth).max().unwrap_or(0),
+            BorrowedValue::Object(map) => 1 + map.values().map(Self::nesting_depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+/// Whether `c` directly after a key reads as an attempted modifier
+fn is_modifier_like(c: char) -> bool {
+    matches!(c, '!' | '+' | '?' | '*' | '=' | '~' | '^' | '&' | '%' | '#' | '@' | '<' | '>' | '|' | ';')
+}
+
+/// The longest hyphen-separated prefix of a reference name that is defined
+fn longest_prefix(name: &str, is_defined: impl Fn(&str) -> bool) -> Option<&str> {
+    let mut end = name.len();
+    loop {
+        if is_defined(&name[..end]) {
+            return Some(&name[..end]);
+        }
+        match name[..end].rfind('-') {
+            Some(i) if i > 0 => end = i,
+            _ => return None,
+        }
+    }
+}
+
+/// Whether a block defines variables: an object whose keys all start with `$`
+fn is_defs_block(node: &Node) -> bool {
+    match &node.kind {
+        NodeKind::Object(fields) => !fields.is_empty() && fields.iter().all(|f| f.key.starts_with('$')),
+        _ => false,
+    }
+}
+
+/// The size of a value towards the output size limit
+fn output_size(kind: &SpannedKind) -> usize {
+    match kind {
+        SpannedKind::String(s) | SpannedKind::Symbol(s) => 1 + s.len(),
+        SpannedKind::Array(arr) => 1 + arr.iter().map(|item| output_size(&item.kind)).sum::<usize>(),
+        SpannedKind::Object(map) => {
+            1 + map
+                .iter()
+                .map(|(key, field)| key.len() + output_size(&field.value.kind))
+                .sum::<usize>()
+        }
+        _ => 1,
+    }
+}
+
+/// Build the node for a parsed string
+///
+/// A string that is exactly one variable reference keeps the variable's type.
+fn string_node<'a>(mut segments: Vec<Segment>, span: Span) -> Node<'a> {
+    let kind = match segments.len() {
+        0 => NodeKind::Value(SpannedKind::String(Cow::Borrowed(""))),
+        1 => match segments.pop().unwrap() {
+            Segment::Text(text) => NodeKind::Value(SpannedKind::String(Cow::Owned(text))),
+            Segment::Variable(var) => NodeKind::Variable(var),
+            env => NodeKind::Template(vec![env]),
+        },
+        _ => NodeKind::Template(segments),
+    };
+    Node { kind, span }
+}
+
+/// Bytes of literal text in a string's segments
+fn text_len(segments: &[Segment]) -> usize {
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+fn push_char(segments: &mut Vec<Segment>, ch: char) {
+    match segments.last_mut() {
+        Some(Segment::Text(text)) => text.push(ch),
+        _ => segments.push(Segment::Text(ch.to_string())),
+    }
+}
+
+fn push_text(segments: &mut Vec<Segment>, s: &str) {
+    match segments.last_mut() {
+        Some(Segment::Text(text)) => text.push_str(s),
+        _ => segments.push(Segment::Text(s.to_string())),
+    }
+}
+
+/// Strip the whitespace around a line of a string value
+fn trim_line(mut line: Vec<Segment>, preserve_leading: bool) -> Vec<Segment> {
+    if !preserve_leading {
+        if let Some(Segment::Text(text)) = line.first_mut() {
+            *text = text.trim_start().to_string();
+        }
+    }
+    if let Some(Segment::Text(text)) = line.last_mut() {
+        text.truncate(text.trim_end().len());
+    }
+    line.retain(|segment| !matches!(segment, Segment::Text(text) if text.is_empty()));
+    line
+}
@@ -0,0 +1,295 @@
+//! Key paths into nested values
+//!
+//! A path names object keys separated by dots, with `[n]` for array
+//! elements: `server.port`, `spec.containers[0].image`. A backslash makes
+//! the next character part of the key, so `metadata.labels.app\.kubernetes\.io/name`
+//! names the key `app.kubernetes.io/name`. A path may start with an index
+//! into a top-level array, as in `[2].name`.
+//!
+//! ```
+//! use sym_parser::Value;
+//!
+//! let mut value = sym_parser::parse("{ :spec { :containers [ { :image nginx } ] }\n}").unwrap();
+//! assert_eq!(value.get_path("spec.containers[0].image"), Some(&Value::String("nginx".into())));
+//!
+//! value.set_path("spec.containers[0].ports[0].port", Value::Int(80)).unwrap();
+//! value.set_path("metadata.labels.app\\.kubernetes\\.io/name", Value::String("web".into())).unwrap();
+//! assert_eq!(value.remove_path("spec.containers[0].image").unwrap(), Some(Value::String("nginx".into())));
+//! ```
+
+use crate::{Result, SymError, Value};
+
+/// One step of a key path
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    /// An object key
+    Key(String),
+    /// An array index
+    Index(usize),
+}
+
+/// Split a path like `server.ports[0]` into segments
+pub fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let invalid = || path_error(path, "invalid path");
+    let mut segments = Vec::new();
+    let mut chars = path.chars().peekable();
+    loop {
+        // A key, which only the first part of a path may leave out
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '.' | '[' | ']' => break,
+                '\\' => {
+                    chars.next();
+                    key.push(chars.next().ok_or_else(invalid)?);
+                }
+                c => {
+                    key.push(c);
+                    chars.next();
+                }
+            }
+        }
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        } else if !segments.is_empty() || chars.peek() != Some(&'[') {
+            return Err(invalid());
+        }
+
+        while chars.next_if_eq(&'[').is_some() {
+            let mut digits = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                digits.push(c);
+            }
+            if chars.next() != Some(']') {
+                return Err(invalid());
+            }
+            segments.push(Segment::Index(digits.parse().map_err(|_| invalid())?));
+        }
+
+        match chars.next() {
+            None => return Ok(segments),
+            Some('.') => continue,
+            Some(_) => return Err(invalid()),
+        }
+    }
+}
+
+/// Write segments as a path, escaping the characters of keys that have a
+/// meaning in paths
+pub fn format_path(segments: &[Segment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Key(key) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                for c in key.chars() {
+                    if matches!(c, '.' | '[' | ']' | '\\') {
+                        path.push('\\');
+                    }
+                    path.push(c);
+                }
+            }
+            Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+fn path_error(path: &str, message: &str) -> SymError {
+    SymError::EditError {
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+impl Value {
+    /// The value at `path`, such as `server.port` or `spec.containers[0].image`
+    ///
+    /// See [`path`](crate::path) for the syntax. Returns `None` if the path is
+    /// invalid or leads nowhere.
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        self.get_segments(&parse_path(path).ok()?)
+    }
+
+    fn get_segments(&self, segments: &[Segment]) -> Option<&Value> {
+        segments
+            .iter()
+            .try_fold(self, |value, segment| match (value, segment) {
+                (Value::Object(map), Segment::Key(key)) => map.get(key),
+                (Value::Array(items), Segment::Index(index)) => items.get(*index),
+                _ => None,
+            })
+    }
+
+    /// The value at `path`, for changing it in place
+    pub fn get_path_mut(&mut self, path: &str) -> Option<&mut Value> {
+        self.get_segments_mut(&parse_path(path).ok()?)
+    }
+
+    fn get_segments_mut(&mut self, segments: &[Segment]) -> Option<&mut Value> {
+        segments
+            .iter()
+            .try_fold(self, |value, segment| match (value, segment) {
+                (Value::Object(map), Segment::Key(key)) => map.get_mut(key),
+                (Value::Array(items), Segment::Index(index)) => items.get_mut(*index),
+                _ => None,
+            })
+    }
+
+    /// Set the value at `path`, creating the objects and arrays leading to it
+    ///
+    /// A missing or `null` value on the way becomes an object or an array,
+    /// depending on the segment that follows. An index may point one past the
+    /// end of an array to append to it. Fails, leaving the value unchanged,
+    /// if the path runs into a scalar or into the wrong kind of container.
+    pub fn set_path(&mut self, path: &str, value: impl Into<Value>) -> Result<()> {
+        let segments = parse_path(path)?;
+        self.check_set(&segments, path)?;
+        let mut target = self;
+        for segment in &segments {
+            if target.is_null() {
+                *target = match segment {
+                    Segment::Key(_) => Value::Object(Default::default()),
+                    Segment::Index(_) => Value::Array(Vec::new()),
+                };
+            }
+            target = match (target, segment) {
+                (Value::Object(map), Segment::Key(key)) => map.entry(key.clone()).or_insert(Value::Null),
+                (Value::Array(items), Segment::Index(index)) => {
+                    if *index == items.len() {
+                        items.push(Value::Null);
+                    }
+                    &mut items[*index]
+                }
+                _ => unreachable!("the path was checked"),
+            };
+        }
+        *target = value.into();
+        Ok(())
+    }
+
+    /// Check that [`set_path`](Self::set_path) can follow `segments`, so that
+    /// it fails before changing anything
+    fn check_set(&self, segments: &[Segment], path: &str) -> Result<()> {
+        // `None` once the path has left the existing values
+        let mut target = Some(self);
+        for (i, segment) in segments.iter().enumerate() {
+            let at = || match i {
+                0 => "the value".to_string(),
+                _ => format_path(&segments[..i]),
+            };
+            target = match (target, segment) {
+                (None | Some(Value::Null), Segment::Key(_)) => None,
+                (None | Some(Value::Null), Segment::Index(0)) => None,
+                (Some(Value::Object(map)), Segment::Key(key)) => map.get(key),
+                (Some(Value::Array(items)), Segment::Index(index)) if *index <= items.len() => {
+                    items.get(*index)
+                }
+                (None | Some(Value::Null | Value::Array(_)), Segment::Index(_)) => {
+                    return Err(path_error(path, "array index out of range"))
+                }
+                (Some(Value::Object(_)), Segment::Index(_)) => {
+                    return Err(path_error(path, &format!("{} is an object, not an array", at())))
+                }
+                (Some(Value::Array(_)), Segment::Key(_)) => {
+                    return Err(path_error(path, &format!("{} is an array, not an object", at())))
+                }
+                (Some(_), _) => {
+                    return Err(path_error(path, &format!("{} is not an object or array", at())))
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Remove the value at `path` and return it
+    ///
+    /// Later elements of an array move up and the other keys of an object
+    /// keep their order. Returns `None` if there is nothing at `path`.
+    pub fn remove_path(&mut self, path: &str) -> Result<Option<Value>> {
+        let segments = parse_path(path)?;
+        let (last, parents) = segments.split_last().expect("a parsed path is not empty");
+        Ok(match (self.get_segments_mut(parents), last) {
+            (Some(Value::Object(map)), Segment::Key(key)) => map.shift_remove(key),
+            (Some(Value::Array(items)), Segment::Index(index)) if *index < items.len() => {
+                Some(items.remove(*index))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_parse_path() {
+        let key = |k: &str| Segment::Key(k.to_string());
+        assert_eq!(parse_path("server.port").unwrap(), [key("server"), key("port")]);
+        assert_eq!(
+            parse_path("spec.containers[0].ports[12]").unwrap(),
+            [key("spec"), key("containers"), Segment::Index(0), key("ports"), Segment::Index(12)]
+        );
+        assert_eq!(parse_path("[1][0].name").unwrap(), [Segment::Index(1), Segment::Index(0), key("name")]);
+        assert_eq!(parse_path(r"labels.app\.io/name").unwrap(), [key("labels"), key("app.io/name")]);
+        assert_eq!(parse_path(r"a\\b\[0\]").unwrap(), [key(r"a\b[0]")]);
+        assert_eq!(parse_path("$version").unwrap(), [key("$version")]);
+
+        for invalid in ["", ".a", "a.", "a..b", "a.[0]", "a[", "a[x]", "a[-1]", "a]", "a[0]b", "a\\"] {
+            assert!(matches!(parse_path(invalid), Err(SymError::EditError { .. })), "{}", invalid);
+        }
+
+        let segments = [key("a.b"), Segment::Index(3), key("c[d]")];
+        assert_eq!(format_path(&segments), r"a\.b[3].c\[d\]");
+        assert_eq!(parse_path(&format_path(&segments)).unwrap(), segments);
+    }
+
+    #[test]
+    fn test_value_paths() {
+        let mut value = parse("{ :server { :host localhost\n  , :port 8080\n  }\n, :tags [a\n  , b]\n}").unwrap();
+        assert_eq!(value.get_path("server.port"), Some(&Value::Int(8080)));
+        assert_eq!(value.get_path("tags[1]"), Some(&Value::String("b".into())));
+        assert_eq!(value.get_path("tags[2]"), None);
+        assert_eq!(value.get_path("server.port.x"), None);
+        assert_eq!(value.get_path("server..port"), None);
+
+        *value.get_path_mut("server.port").unwrap() = Value::Int(9090);
+        value.set_path("server.tls.enabled", Value::Bool(true)).unwrap();
+        value.set_path("tags[2]", Value::String("c".into())).unwrap();
+        value.set_path("routes[0].path", Value::String("/".into())).unwrap();
+        value.set_path(r"labels.app\.io/name", Value::String("web".into())).unwrap();
+        assert_eq!(value.get_path("server.port"), Some(&Value::Int(9090)));
+        assert_eq!(value.get_path("server.tls.enabled"), Some(&Value::Bool(true)));
+        assert_eq!(value.get_path("tags").unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(value.get_path("routes[0].path"), Some(&Value::String("/".into())));
+        assert!(value.get_path("labels").unwrap().as_object().unwrap().contains_key("app.io/name"));
+
+        let err = |path: &str, value: &mut Value| match value.set_path(path, Value::Null) {
+            Err(SymError::EditError { message, .. }) => message,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(err("server.port.x", &mut value), "server.port is not an object or array");
+        assert_eq!(err("tags.x", &mut value), "tags is an array, not an object");
+        assert_eq!(err("server[0]", &mut value), "server is an object, not an array");
+        assert_eq!(err("tags[5]", &mut value), "array index out of range");
+        assert_eq!(err("[0]", &mut value), "the value is an object, not an array");
+
+        // A path that fails part way leaves the value as it was
+        let before = value.clone();
+        assert_eq!(err("x.y[2].z", &mut value), "array index out of range");
+        assert_eq!(err("tags[3].x[1]", &mut value), "array index out of range");
+        assert_eq!(err("routes[0].path.x", &mut value), "routes[0].path is not an object or array");
+        assert_eq!(value, before);
+
+        assert_eq!(value.remove_path("tags[0]").unwrap(), Some(Value::String("a".into())));
+        assert_eq!(value.get_path("tags[0]"), Some(&Value::String("b".into())));
+        assert_eq!(value.remove_path("server.host").unwrap(), Some(Value::String("localhost".into())));
+        assert!(value.get_path("server").unwrap().as_object().unwrap().keys().eq(["port", "tls"]));
+        assert_eq!(value.remove_path("missing.key").unwrap(), None);
+        assert!(value.remove_path("a..b").is_err());
+    }
+}
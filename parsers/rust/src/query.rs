@@ -0,0 +1,663 @@
+//! Queries that select values across a document
+//!
+//! A query extends [key paths](crate::path) with patterns that match any
+//! number of values:
+//!
+//! - `services.*.image` - `*` matches every key of an object or element of an array
+//! - `..port` - `..` searches the value and everything nested in it
+//! - `items[-1]`, `items[1:3]`, `items[::2]` - indexes count back from the
+//!   end when negative, and slices work as in Python
+//! - `items[?(@.kind == :Deployment)]` - a filter keeps the elements, or
+//!   the values of an object, for which a test holds
+//!
+//! In a filter, `@` is the value being tested and `@.name` or `@[0]` a value
+//! inside it. Tests compare with `==`, `!=`, `<`, `<=`, `>` and `>=`, and
+//! combine with `&&`, `||`, `!` and parentheses. Literals are written as in
+//! SYM, `:symbol`, `42`, `true`, `null` or a bare word, or quoted with `'`
+//! or `"`. A path on its own tests that the value exists and is not `false`
+//! or `null`.
+//!
+//! ```
+//! let config = sym_parser::parse(
+//!     "{ :services\n  { :web { :image nginx\n    , :port 80\n    }\n  , :db { :image postgres }\n  }\n}",
+//! )
+//! .unwrap();
+//! let images: Vec<String> = config
+//!     .query("services.*.image")
+//!     .unwrap()
+//!     .iter()
+//!     .map(|m| m.path())
+//!     .collect();
+//! assert_eq!(images, ["services.web.image", "services.db.image"]);
+//! ```
+
+use crate::lexer::{number_len, try_parse_number};
+use crate::path::{format_path, Segment};
+use crate::{Result, SymError, Value};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed query, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+/// A value selected by a query and where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'v> {
+    pub path: Vec<Segment>,
+    pub value: &'v Value,
+}
+
+impl Match<'_> {
+    /// The path of the value, as accepted by [`Value::get_path`], or an
+    /// empty string for the queried value itself
+    pub fn path(&self) -> String {
+        format_path(&self.path)
+    }
+}
+
+/// One step of a query, applied to every value selected so far
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// Select from the value itself
+    Child(Selector),
+    /// Select from the value and everything nested in it (`..`)
+    Descendants(Selector),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Key(String),
+    Wildcard,
+    Index(i64),
+    Slice {
+        start: Option<i64>,
+        end: Option<i64>,
+        step: usize,
+    },
+    Filter(Filter),
+}
+
+/// A test on the value a filter is applied to
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// `@.path` on its own
+    Truthy(Vec<Segment>),
+    Compare(Operand, Comparison, Operand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    /// `@` or a path inside it
+    Current(Vec<Segment>),
+    Literal(Value),
+}
+
+impl Query {
+    /// Parse a query such as `services.*.image` or `..port`
+    pub fn parse(query: &str) -> Result<Self> {
+        QueryParser { query, pos: 0 }.parse()
+    }
+
+    /// The values in `value` that the query selects, in document order
+    ///
+    /// A value reached in more than one way by `..` is only listed once.
+    pub fn select<'v>(&self, value: &'v Value) -> Vec<Match<'v>> {
+        let mut matches = vec![Match {
+            path: Vec::new(),
+            value,
+        }];
+        for step in &self.steps {
+            let mut next = Vec::new();
+            match step {
+                Step::Child(selector) => {
+                    for m in &matches {
+                        selector.select(m, &mut next);
+                    }
+                }
+                Step::Descendants(selector) => {
+                    let mut all = Vec::new();
+                    for m in matches {
+                        descendants(m, &mut all);
+                    }
+                    for m in &all {
+                        selector.select(m, &mut next);
+                    }
+                    let mut seen = HashSet::new();
+                    next.retain(|m| seen.insert(m.path.clone()));
+                }
+            }
+            matches = next;
+        }
+        matches
+    }
+}
+
+impl FromStr for Query {
+    type Err = SymError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl Value {
+    /// The values selected by a [query](crate::query), with their paths
+    pub fn query(&self, query: &str) -> Result<Vec<Match<'_>>> {
+        Ok(Query::parse(query)?.select(self))
+    }
+}
+
+impl fmt::Display for Match<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "$ {}", self.value)
+        } else {
+            write!(f, "{} {}", self.path(), self.value)
+        }
+    }
+}
+
+/// Add `m` and every value nested in it, parents first
+fn descendants<'v>(m: Match<'v>, out: &mut Vec<Match<'v>>) {
+    let value = m.value;
+    let path = m.path.clone();
+    out.push(m);
+    for child in children(&path, value) {
+        descendants(child, out);
+    }
+}
+
+/// The fields of an object or elements of an array
+fn children<'v>(path: &[Segment], value: &'v Value) -> Vec<Match<'v>> {
+    let child = |segment: Segment, value| {
+        let mut path = path.to_vec();
+        path.push(segment);
+        Match { path, value }
+    };
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| child(Segment::Key(key.clone()), value))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, value)| child(Segment::Index(i), value))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Selector {
+    /// Add the values this selector picks from `m` to `out`
+    fn select<'v>(&self, m: &Match<'v>, out: &mut Vec<Match<'v>>) {
+        match (self, m.value) {
+            (Selector::Key(key), Value::Object(map)) => {
+                if let Some(value) = map.get(key) {
+                    let mut path = m.path.clone();
+                    path.push(Segment::Key(key.clone()));
+                    out.push(Match { path, value });
+                }
+            }
+            (Selector::Wildcard, _) => out.extend(children(&m.path, m.value)),
+            (Selector::Index(index), Value::Array(items)) => {
+                if let Some(i) = resolve_index(*index, items.len()) {
+                    let mut path = m.path.clone();
+                    path.push(Segment::Index(i));
+                    out.push(Match { path, value: &items[i] });
+                }
+            }
+            (Selector::Slice { start, end, step }, Value::Array(items)) => {
+                let len = items.len() as i64;
+                let bound = |i: Option<i64>, default: i64| match i {
+                    Some(i) if i < 0 => (len + i).max(0),
+                    Some(i) => i.min(len),
+                    None => default,
+                };
+                let (start, end) = (bound(*start, 0), bound(*end, len));
+                let elements = children(&m.path, m.value);
+                out.extend(
+                    elements
+                        .into_iter()
+                        .skip(start as usize)
+                        .take((end - start).max(0) as usize)
+                        .step_by(*step),
+                );
+            }
+            (Selector::Filter(filter), _) => {
+                out.extend(children(&m.path, m.value).into_iter().filter(|child| filter.test(child.value)))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The position of `index` in an array of `len` elements, counting back
+/// from the end if it is negative
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let i = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(i).ok().filter(|&i| i < len)
+}
+
+impl Filter {
+    fn test(&self, value: &Value) -> bool {
+        match self {
+            Filter::Or(a, b) => a.test(value) || b.test(value),
+            Filter::And(a, b) => a.test(value) && b.test(value),
+            Filter::Not(a) => !a.test(value),
+            Filter::Truthy(path) => {
+                matches!(lookup(value, path), Some(v) if !matches!(v, Value::Null | Value::Bool(false)))
+            }
+            Filter::Compare(left, comparison, right) => {
+                let (left, right) = (left.resolve(value), right.resolve(value));
+                match comparison {
+                    Comparison::Eq => left.zip(right).is_some_and(|(a, b)| equal(a, b)),
+                    Comparison::Ne => !left.zip(right).is_some_and(|(a, b)| equal(a, b)),
+                    _ => {
+                        let Some(ordering) = left.zip(right).and_then(|(a, b)| compare(a, b)) else {
+                            return false;
+                        };
+                        match comparison {
+                            Comparison::Lt => ordering.is_lt(),
+                            Comparison::Le => ordering.is_le(),
+                            Comparison::Gt => ordering.is_gt(),
+                            _ => ordering.is_ge(),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, value: &'a Value) -> Option<&'a Value> {
+        match self {
+            Operand::Current(path) => lookup(value, path),
+            Operand::Literal(literal) => Some(literal),
+        }
+    }
+}
+
+fn lookup<'v>(value: &'v Value, path: &[Segment]) -> Option<&'v Value> {
+    path.iter().try_fold(value, |value, segment| match (value, segment) {
+        (Value::Object(map), Segment::Key(key)) => map.get(key),
+        (Value::Array(items), Segment::Index(index)) => items.get(*index),
+        _ => None,
+    })
+}
+
+/// Equality that treats integers and floats with the same value as equal
+fn equal(a: &Value, b: &Value) -> bool {
+    match compare(a, b) {
+        Some(ordering) => ordering.is_eq(),
+        None => a == b,
+    }
+}
+
+/// The order of two numbers, strings or symbols
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            a.as_float()?.partial_cmp(&b.as_float()?)
+        }
+        (Value::String(a), Value::String(b)) | (Value::Symbol(a), Value::Symbol(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Characters that end a bare key or word inside a filter
+fn ends_filter_word(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '.' | '[' | ']' | '(' | ')' | '=' | '!' | '<' | '>' | '&' | '|')
+}
+
+struct QueryParser<'q> {
+    query: &'q str,
+    /// Byte offset of the next character
+    pos: usize,
+}
+
+impl QueryParser<'_> {
+    fn parse(mut self) -> Result<Query> {
+        let mut steps = Vec::new();
+        if !self.eat('$') && !matches!(self.peek(), Some('.' | '[')) {
+            steps.push(Step::Child(self.key(|c| matches!(c, '.' | '[' | ']'))?));
+        }
+        while let Some(c) = self.peek() {
+            let step = match c {
+                '.' if self.query[self.pos..].starts_with("..") => {
+                    self.pos += 2;
+                    match self.peek() {
+                        Some('[') => Step::Descendants(self.bracket()?),
+                        _ => Step::Descendants(self.key(|c| matches!(c, '.' | '[' | ']'))?),
+                    }
+                }
+                '.' => {
+                    self.pos += 1;
+                    Step::Child(self.key(|c| matches!(c, '.' | '[' | ']'))?)
+                }
+                '[' => Step::Child(self.bracket()?),
+                _ => return Err(self.error("expected '.' or '['")),
+            };
+            steps.push(step);
+        }
+        Ok(Query { steps })
+    }
+
+    /// A key, or `*` for every key, up to a character for which `ends` holds
+    fn key(&mut self, ends: fn(char) -> bool) -> Result<Selector> {
+        if self.query[self.pos..].starts_with('*') {
+            self.pos += 1;
+            return Ok(Selector::Wildcard);
+        }
+        let mut key = String::new();
+        while let Some(c) = self.peek().filter(|&c| !ends(c)) {
+            self.pos += c.len_utf8();
+            if c == '\\' {
+                let escaped = self.peek().ok_or_else(|| self.error("expected a character after '\\'"))?;
+                self.pos += escaped.len_utf8();
+                key.push(escaped);
+            } else {
+                key.push(c);
+            }
+        }
+        if key.is_empty() {
+            return Err(self.error("expected a key"));
+        }
+        Ok(Selector::Key(key))
+    }
+
+    /// `[*]`, `[n]`, `[start:end:step]`, `['key']` or `[?(filter)]`
+    fn bracket(&mut self) -> Result<Selector> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        let selector = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                Selector::Wildcard
+            }
+            Some('?') => {
+                self.pos += 1;
+                Selector::Filter(self.or()?)
+            }
+            Some(quote @ ('\'' | '"')) => Selector::Key(self.quoted(quote)?),
+            _ => {
+                let start = self.integer()?;
+                if self.eat(':') {
+                    let end = self.integer()?;
+                    let step = if self.eat(':') { self.integer()? } else { None };
+                    let step = match step {
+                        None => 1,
+                        Some(step) if step > 0 => step as usize,
+                        Some(_) => return Err(self.error("the step of a slice must be positive")),
+                    };
+                    Selector::Slice { start, end, step }
+                } else {
+                    Selector::Index(start.ok_or_else(|| self.error("expected an index, slice, '*' or '?'"))?)
+                }
+            }
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(selector)
+    }
+
+    /// An optional, possibly negative integer
+    fn integer(&mut self) -> Result<Option<i64>> {
+        self.skip_whitespace();
+        let rest = &self.query[self.pos..];
+        let len = usize::from(rest.starts_with('-'));
+        let len = len + rest[len..].bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return Ok(None);
+        }
+        let number = rest[..len].parse().map_err(|_| self.error("expected a number"))?;
+        self.pos += len;
+        self.skip_whitespace();
+        Ok(Some(number))
+    }
+
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        while self.eat_operator("||") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.unary()?;
+        while self.eat_operator("&&") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter> {
+        self.skip_whitespace();
+        if self.eat('!') {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let filter = self.or()?;
+            self.skip_whitespace();
+            self.expect(')')?;
+            return Ok(filter);
+        }
+        let left = self.operand()?;
+        self.skip_whitespace();
+        let comparison = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ]
+        .into_iter()
+        .find(|(op, _)| self.eat_str(op));
+        match (comparison, left) {
+            (Some((_, comparison)), left) => {
+                self.skip_whitespace();
+                Ok(Filter::Compare(left, comparison, self.operand()?))
+            }
+            (None, Operand::Current(path)) => Ok(Filter::Truthy(path)),
+            (None, Operand::Literal(_)) => Err(self.error("expected a comparison")),
+        }
+    }
+
+    /// `@`, a path inside it, or a literal
+    fn operand(&mut self) -> Result<Operand> {
+        if self.eat('@') {
+            let mut path = Vec::new();
+            loop {
+                if self.eat('.') {
+                    match self.key(ends_filter_word)? {
+                        Selector::Key(key) => path.push(Segment::Key(key)),
+                        _ => return Err(self.error("expected a key")),
+                    }
+                } else if self.eat('[') {
+                    let index = self.integer()?.and_then(|i| usize::try_from(i).ok());
+                    path.push(Segment::Index(index.ok_or_else(|| self.error("expected an index"))?));
+                    self.expect(']')?;
+                } else {
+                    return Ok(Operand::Current(path));
+                }
+            }
+        }
+        if let Some(quote @ ('\'' | '"')) = self.peek() {
+            return Ok(Operand::Literal(Value::String(self.quoted(quote)?)));
+        }
+
+        let rest = &self.query[self.pos..];
+        let word = &rest[..rest.find(ends_filter_word).unwrap_or(rest.len())];
+        if word.is_empty() {
+            return Err(self.error("expected '@' or a value"));
+        }
+        self.pos += word.len();
+        let value = match word {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "null" => Value::Null,
+            _ => match word.strip_prefix(':') {
+                Some(symbol) => Value::Symbol(symbol.to_string()),
+                None if number_len(word) == word.len() => {
+                    try_parse_number(word).unwrap_or_else(|| Value::String(word.to_string()))
+                }
+                None => Value::String(word.to_string()),
+            },
+        };
+        Ok(Operand::Literal(value))
+    }
+
+    /// A string in `quote`s, in which `\` escapes the next character
+    fn quoted(&mut self, quote: char) -> Result<String> {
+        self.expect(quote)?;
+        let mut text = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unclosed string"))?;
+            self.pos += c.len_utf8();
+            match c {
+                '\\' => {
+                    let escaped = self.peek().ok_or_else(|| self.error("unclosed string"))?;
+                    self.pos += escaped.len_utf8();
+                    text.push(escaped);
+                }
+                c if c == quote => return Ok(text),
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.query[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.eat_str(expected.encode_utf8(&mut [0; 4]))
+    }
+
+    fn eat_str(&mut self, expected: &str) -> bool {
+        let found = self.query[self.pos..].starts_with(expected);
+        if found {
+            self.pos += expected.len();
+        }
+        found
+    }
+
+    /// `expected` after optional whitespace
+    fn eat_operator(&mut self, expected: &str) -> bool {
+        self.skip_whitespace();
+        self.eat_str(expected)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.query[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> SymError {
+        SymError::QueryError {
+            query: self.query.to_string(),
+            message: format!("{} at column {}", message, self.query[..self.pos].chars().count() + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn paths(value: &Value, query: &str) -> Vec<String> {
+        value.query(query).unwrap().iter().map(Match::path).collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let value = parse(
+            "{ :services\n  { :web { :image nginx\n    , :ports [ { :port 80 }\n      , { :port 443 }\n      ]\n    }\n  , :db { :image postgres\n    , :port 5432\n    }\n  }\n, :items [1\n  , 2\n  , 3\n  , 4\n  , 5\n  ]\n}",
+        )
+        .unwrap();
+        assert_eq!(paths(&value, "services.*.image"), ["services.web.image", "services.db.image"]);
+        assert_eq!(paths(&value, "$.services.db.port"), ["services.db.port"]);
+        assert_eq!(
+            paths(&value, "..port"),
+            ["services.web.ports[0].port", "services.web.ports[1].port", "services.db.port"]
+        );
+        assert_eq!(paths(&value, "services..ports[*].port").len(), 2);
+        assert_eq!(paths(&value, "..[0]"), ["services.web.ports[0]", "items[0]"]);
+        assert_eq!(paths(&value, "items[-1]"), ["items[4]"]);
+        assert_eq!(paths(&value, "items[1:3]"), ["items[1]", "items[2]"]);
+        assert_eq!(paths(&value, "items[::2]"), ["items[0]", "items[2]", "items[4]"]);
+        assert_eq!(paths(&value, "items[-2:]"), ["items[3]", "items[4]"]);
+        assert_eq!(paths(&value, "items[9]"), Vec::<String>::new());
+        assert_eq!(paths(&value, "services.web.image[0]"), Vec::<String>::new());
+        assert_eq!(paths(&value, "missing.*"), Vec::<String>::new());
+
+        let root = value.query("$").unwrap();
+        assert_eq!(root.len(), 1);
+        assert!(root[0].path.is_empty());
+        assert_eq!(value.query("..image").unwrap()[1].value, &Value::String("postgres".into()));
+    }
+
+    #[test]
+    fn test_filters() {
+        let value = parse(
+            "[ { :kind :Deployment\n  , :name web\n  , :replicas 3\n  }\n, { :kind :Service\n  , :name web\n  , :port 80.0\n  }\n, { :kind :Deployment\n  , :name db\n  , :replicas 1\n  , :paused true\n  }\n]",
+        )
+        .unwrap();
+        assert_eq!(paths(&value, "[?(@.kind == :Deployment)].name"), ["[0].name", "[2].name"]);
+        assert_eq!(paths(&value, "[?(@.kind != :Deployment)]"), ["[1]"]);
+        assert_eq!(paths(&value, "[?(@.replicas >= 2)]"), ["[0]"]);
+        assert_eq!(paths(&value, "[?(@.port == 80)]"), ["[1]"]);
+        assert_eq!(paths(&value, "[?(@.paused)]"), ["[2]"]);
+        assert_eq!(paths(&value, "[?(!@.paused && @.kind == :Deployment)]"), ["[0]"]);
+        assert_eq!(paths(&value, "[?(@.name == db || (@.name == 'web' && @.port))]"), ["[1]", "[2]"]);
+        assert_eq!(paths(&value, "[?(@.name < \"m\")].name"), ["[2].name"]);
+        assert_eq!(paths(&value, "[?(@.kind == Deployment)]"), Vec::<String>::new());
+        assert_eq!(paths(&value, "[*].name[?(@ == web)]"), Vec::<String>::new());
+        assert_eq!(paths(&value, "[0][?(@ == 3)]"), ["[0].replicas"]);
+    }
+
+    #[test]
+    fn test_invalid_queries() {
+        for invalid in [
+            "", ".", "a.", "a..", "a[", "a[x]", "a[1", "a[::0]", "a[?(@.x ==)]", "a[?(1)]", "a[?(@.x]", "a]", "a\\",
+        ] {
+            match Query::parse(invalid) {
+                Err(err @ SymError::QueryError { .. }) => assert_eq!(err.code(), crate::ErrorCode::Query),
+                other => panic!("{:?}: {:?}", invalid, other),
+            }
+        }
+        let err = Query::parse("a[1").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid query a[1: expected ']' at column 4");
+    }
+}
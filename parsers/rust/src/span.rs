@@ -0,0 +1,286 @@
+//! Source locations for parsed values
+//!
+//! [`parse_spanned`](crate::parse_spanned) returns a [`SpannedValue`] tree in
+//! which every value, object key and variable reference remembers where it was
+//! written, so that checks run after parsing can point at the offending line.
+
+use crate::{BorrowedValue, Value};
+use indexmap::IndexMap;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A range of SYM source
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset just past the last character
+    pub end: usize,
+    /// Line of the first character (1-based)
+    pub line: usize,
+    /// Column of the first character (1-based)
+    pub col: usize,
+    /// File the source was read from, if it came from a file
+    pub file: Option<Arc<Path>>,
+}
+
+impl Span {
+    /// The source text covered by the span
+    pub fn text<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start..self.end]
+    }
+}
+
+/// Where an error was found
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    /// File the error is in, if the source came from a file
+    pub file: Option<Arc<Path>>,
+    /// Line (1-based)
+    pub line: usize,
+    /// Column (1-based)
+    pub col: usize,
+    /// The `@import` directives through which `file` was loaded, outermost first
+    pub import_stack: Vec<Location>,
+}
+
+impl From<&Span> for Location {
+    fn from(span: &Span) -> Self {
+        Self {
+            file: span.file.clone(),
+            line: span.line,
+            col: span.col,
+            import_stack: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.col)?;
+        if let Some(file) = &self.file {
+            write!(f, " of {}", file.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// A name as written in the source: an object key or a variable reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedName {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A parsed value with the location it was written at
+///
+/// A value substituted from a variable is located at the reference; nested
+/// values of an object or array variable keep the location of its definition.
+/// Values supplied through [`Parser::with_vars`](crate::parser::Parser::with_vars)
+/// have no source and carry an empty span. Strings and keys written verbatim
+/// borrow from the source, see [`BorrowedValue`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedValue<'a> {
+    pub kind: SpannedKind<'a>,
+    pub span: Span,
+    /// Variables substituted into this value, outermost first
+    pub refs: Vec<SpannedName>,
+}
+
+/// The value of a [`SpannedValue`], mirroring [`Value`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpannedKind<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(Cow<'a, str>),
+    Symbol(Cow<'a, str>),
+    Array(Vec<SpannedValue<'a>>),
+    Object(IndexMap<Cow<'a, str>, SpannedField<'a>>),
+}
+
+/// A field of a spanned object
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedField<'a> {
+    /// Where the key was written, including its leading `:`
+    pub key_span: Span,
+    pub value: SpannedValue<'a>,
+}
+
+impl<'a> SpannedValue<'a> {
+    /// Create a value with no variable references
+    pub(crate) fn new(kind: SpannedKind<'a>, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            refs: Vec::new(),
+        }
+    }
+
+    /// Wrap a plain value, locating it and everything inside it at `span`
+    pub(crate) fn from_value(value: Value, span: &Span) -> Self {
+        let kind = match value {
+            Value::Null => SpannedKind::Null,
+            Value::Bool(b) => SpannedKind::Bool(b),
+            Value::Int(i) => SpannedKind::Int(i),
+            Value::Float(f) => SpannedKind::Float(f),
+            Value::String(s) => SpannedKind::String(Cow::Owned(s)),
+            Value::Symbol(s) => SpannedKind::Symbol(Cow::Owned(s)),
+            Value::Array(items) => SpannedKind::Array(
+                items
+                    .into_iter()
+                    .map(|item| Self::from_value(item, span))
+                    .collect(),
+            ),
+            Value::Object(map) => SpannedKind::Object(
+                map.into_iter()
+                    .map(|(key, value)| {
+                        let field = SpannedField {
+                            key_span: span.clone(),
+                            value: Self::from_value(value, span),
+                        };
+                        (Cow::Owned(key), field)
+                    })
+                    .collect(),
+            ),
+        };
+        Self::new(kind, span.clone())
+    }
+
+    /// Drop the locations, keeping the value
+    pub fn into_value(self) -> Value {
+        match self.kind {
+            SpannedKind::Null => Value::Null,
+            SpannedKind::Bool(b) => Value::Bool(b),
+            SpannedKind::Int(i) => Value::Int(i),
+            SpannedKind::Float(f) => Value::Float(f),
+            SpannedKind::String(s) => Value::String(s.into_owned()),
+            SpannedKind::Symbol(s) => Value::Symbol(s.into_owned()),
+            SpannedKind::Array(items) => {
+                Value::Array(items.into_iter().map(Self::into_value).collect())
+            }
+            SpannedKind::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, field)| (key.into_owned(), field.value.into_value()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Drop the locations, keeping the strings borrowed from the source
+    pub fn into_borrowed(self) -> BorrowedValue<'a> {
+        match self.kind {
+            SpannedKind::Null => BorrowedValue::Null,
+            SpannedKind::Bool(b) => BorrowedValue::Bool(b),
+            SpannedKind::Int(i) => BorrowedValue::Int(i),
+            SpannedKind::Float(f) => BorrowedValue::Float(f),
+            SpannedKind::String(s) => BorrowedValue::String(s),
+            SpannedKind::Symbol(s) => BorrowedValue::Symbol(s),
+            SpannedKind::Array(items) => {
+                BorrowedValue::Array(items.into_iter().map(Self::into_borrowed).collect())
+            }
+            SpannedKind::Object(map) => BorrowedValue::Object(
+                map.into_iter()
+                    .map(|(key, field)| (key, field.value.into_borrowed()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Copy the value without its locations, still borrowing from the source
+    pub(crate) fn to_borrowed(&self) -> BorrowedValue<'a> {
+        match &self.kind {
+            SpannedKind::Null => BorrowedValue::Null,
+            SpannedKind::Bool(b) => BorrowedValue::Bool(*b),
+            SpannedKind::Int(i) => BorrowedValue::Int(*i),
+            SpannedKind::Float(f) => BorrowedValue::Float(*f),
+            SpannedKind::String(s) => BorrowedValue::String(s.clone()),
+            SpannedKind::Symbol(s) => BorrowedValue::Symbol(s.clone()),
+            SpannedKind::Array(items) => BorrowedValue::Array(items.iter().map(Self::to_borrowed).collect()),
+            SpannedKind::Object(map) => BorrowedValue::Object(
+                map.iter()
+                    .map(|(key, field)| (key.clone(), field.value.to_borrowed()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Copy the strings borrowed from the source, so the value outlives it
+    pub fn into_owned(self) -> SpannedValue<'static> {
+        let kind = match self.kind {
+            SpannedKind::Null => SpannedKind::Null,
+            SpannedKind::Bool(b) => SpannedKind::Bool(b),
+            SpannedKind::Int(i) => SpannedKind::Int(i),
+            SpannedKind::Float(f) => SpannedKind::Float(f),
+            SpannedKind::String(s) => SpannedKind::String(Cow::Owned(s.into_owned())),
+            SpannedKind::Symbol(s) => SpannedKind::Symbol(Cow::Owned(s.into_owned())),
+            SpannedKind::Array(items) => {
+                SpannedKind::Array(items.into_iter().map(Self::into_owned).collect())
+            }
+            SpannedKind::Object(map) => SpannedKind::Object(
+                map.into_iter()
+                    .map(|(key, field)| {
+                        let field = SpannedField {
+                            key_span: field.key_span,
+                            value: field.value.into_owned(),
+                        };
+                        (Cow::Owned(key.into_owned()), field)
+                    })
+                    .collect(),
+            ),
+        };
+        SpannedValue {
+            kind,
+            span: self.span,
+            refs: self.refs,
+        }
+    }
+
+    /// Copy the value without its locations
+    pub fn to_value(&self) -> Value {
+        self.clone().into_value()
+    }
+
+    /// Get a field of an object
+    pub fn get(&self, key: &str) -> Option<&SpannedValue<'a>> {
+        self.field(key).map(|field| &field.value)
+    }
+
+    /// Get a field of an object together with the location of its key
+    pub fn field(&self, key: &str) -> Option<&SpannedField<'a>> {
+        match &self.kind {
+            SpannedKind::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Deep merge another value into this one, like [`Value::deep_merge`]
+    ///
+    /// Merged objects keep their own location and the locations of existing keys.
+    pub(crate) fn deep_merge(&mut self, other: SpannedValue<'a>) {
+        match (&mut self.kind, other.kind) {
+            (SpannedKind::Object(base), SpannedKind::Object(overlay)) => {
+                for (key, field) in overlay {
+                    if let Some(base_field) = base.get_mut(&*key) {
+                        base_field.value.deep_merge(field.value);
+                    } else {
+                        base.insert(key, field);
+                    }
+                }
+            }
+            (SpannedKind::Array(base), SpannedKind::Array(overlay)) => {
+                base.extend(overlay);
+            }
+            (_, kind) => {
+                *self = SpannedValue {
+                    kind,
+                    span: other.span,
+                    refs: other.refs,
+                };
+            }
+        }
+    }
+}
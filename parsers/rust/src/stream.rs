@@ -0,0 +1,367 @@
+//! Reading the data of a document piece by piece
+//!
+//! [`Events`] is a pull parser: it reports the data as a sequence of
+//! [`Event`]s without building the whole value, and [`ArrayElements`] yields
+//! the elements of a top-level array one at a time. Imports and defs blocks
+//! before the data are read first, so variables work as they do in
+//! [`parse`](crate::parse).
+//!
+//! Arrays are read from the source element by element, so memory stays
+//! proportional to the largest element rather than the whole document. An
+//! object is read whole, since its repeated keys merge, and then reported
+//! field by field. The source text itself is borrowed, not copied.
+//!
+//! ```
+//! let input = "{ $region eu-west }\n[ { :name a\n  , :region $region\n  }\n, { :name b }\n]";
+//! let mut names = Vec::new();
+//! for element in sym_parser::array_elements(input) {
+//!     let element = element.unwrap();
+//!     names.push(element.as_object().unwrap()["name"].to_string());
+//! }
+//! assert_eq!(names, ["a", "b"]);
+//! ```
+//!
+//! The streamed array must be the last block of the document, as only the
+//! last block is data; anything after it is reported as an error once the
+//! array has been read.
+
+use crate::parser::{OpenArray, Parser};
+use crate::{BorrowedValue, ErrorCode, Location, Result, SymError, Value};
+use indexmap::map;
+use std::borrow::Cow;
+use std::vec;
+
+/// A step through the data of a document
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<'a> {
+    StartObject,
+    /// A key of the object being read, followed by the events of its value
+    Key(Cow<'a, str>),
+    EndObject,
+    StartArray,
+    EndArray,
+    /// A null, boolean, number, string or symbol
+    Scalar(BorrowedValue<'a>),
+}
+
+/// A container whose remaining contents are still to be reported
+enum Frame<'a> {
+    /// An array still being read from the source
+    Source(OpenArray),
+    Array(vec::IntoIter<BorrowedValue<'a>>),
+    Object(map::IntoIter<Cow<'a, str>, BorrowedValue<'a>>),
+}
+
+/// Pull parser over the data of a document, see the [module docs](self)
+///
+/// Iteration ends after the first error.
+pub struct Events<'a> {
+    parser: Parser<'a>,
+    stack: Vec<Frame<'a>>,
+    /// The value of the key just reported
+    pending: Option<BorrowedValue<'a>>,
+    started: bool,
+    done: bool,
+}
+
+impl<'a> Events<'a> {
+    /// Read the data of the document `parser` was created for
+    pub fn new(parser: Parser<'a>) -> Self {
+        Self {
+            parser,
+            stack: Vec::new(),
+            pending: None,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event<'a>>> {
+        if let Some(value) = self.pending.take() {
+            return Ok(Some(self.open(value)));
+        }
+        let Some(frame) = self.stack.last_mut() else {
+            return self.start();
+        };
+        let event = match frame {
+            Frame::Source(array) => {
+                if !self.parser.next_element(array)? {
+                    self.stack.pop();
+                    Event::EndArray
+                } else if self.parser.at_array() {
+                    let array = self.parser.open_array()?;
+                    self.stack.push(Frame::Source(array));
+                    Event::StartArray
+                } else {
+                    let element = self.parser.parse_element()?;
+                    self.open(element.into_borrowed())
+                }
+            }
+            Frame::Array(items) => match items.next() {
+                Some(item) => self.open(item),
+                None => {
+                    self.stack.pop();
+                    Event::EndArray
+                }
+            },
+            Frame::Object(fields) => match fields.next() {
+                Some((key, value)) => {
+                    self.pending = Some(value);
+                    Event::Key(key)
+                }
+                None => {
+                    self.stack.pop();
+                    Event::EndObject
+                }
+            },
+        };
+        Ok(Some(event))
+    }
+
+    /// Read up to the data and report its first event, or end after the data
+    fn start(&mut self) -> Result<Option<Event<'a>>> {
+        if self.started {
+            self.parser.finish_data()?;
+            return Ok(None);
+        }
+        self.started = true;
+        match self.parser.parse_prelude()? {
+            Some(value) => Ok(Some(self.open(value.into_borrowed()))),
+            None => {
+                let array = self.parser.open_array()?;
+                self.stack.push(Frame::Source(array));
+                Ok(Some(Event::StartArray))
+            }
+        }
+    }
+
+    /// Report the start of `value`, keeping its contents for later events
+    fn open(&mut self, value: BorrowedValue<'a>) -> Event<'a> {
+        match value {
+            BorrowedValue::Array(items) => {
+                self.stack.push(Frame::Array(items.into_iter()));
+                Event::StartArray
+            }
+            BorrowedValue::Object(fields) => {
+                self.stack.push(Frame::Object(fields.into_iter()));
+                Event::StartObject
+            }
+            scalar => Event::Scalar(scalar),
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<Event<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.next_event().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
+
+/// The source of the elements of [`ArrayElements`]
+enum Elements {
+    NotStarted,
+    Source(OpenArray),
+    Parsed(vec::IntoIter<Value>),
+    Done,
+}
+
+/// Iterator over the elements of a document whose data is an array, see the
+/// [module docs](self)
+///
+/// Iteration ends after the first error.
+pub struct ArrayElements<'a> {
+    parser: Parser<'a>,
+    elements: Elements,
+}
+
+impl<'a> ArrayElements<'a> {
+    /// Read the elements of the document `parser` was created for
+    pub fn new(parser: Parser<'a>) -> Self {
+        Self {
+            parser,
+            elements: Elements::NotStarted,
+        }
+    }
+
+    fn next_element(&mut self) -> Result<Option<Value>> {
+        loop {
+            match &mut self.elements {
+                Elements::NotStarted => {
+                    self.elements = match self.parser.parse_prelude()? {
+                        None => Elements::Source(self.parser.open_array()?),
+                        Some(value) => {
+                            let location = Location::from(&value.span);
+                            match value.into_value() {
+                                Value::Array(items) => Elements::Parsed(items.into_iter()),
+                                _ => return Err(not_an_array(location)),
+                            }
+                        }
+                    };
+                }
+                Elements::Source(array) => {
+                    if self.parser.next_element(array)? {
+                        return self.parser.parse_element().map(|element| Some(element.into_value()));
+                    }
+                    self.elements = Elements::Done;
+                    self.parser.finish_data()?;
+                }
+                Elements::Parsed(items) => return Ok(items.next()),
+                Elements::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Iterator for ArrayElements<'_> {
+    type Item = Result<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_element().transpose();
+        if matches!(result, Some(Err(_))) {
+            self.elements = Elements::Done;
+        }
+        result
+    }
+}
+
+/// The error for data at `location` that is not an array
+fn not_an_array(location: Location) -> SymError {
+    SymError::ParseError {
+        message: "Expected the data to be an array".to_string(),
+        code: ErrorCode::Stream,
+        location,
+        suggestions: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Limit, ParseOptions};
+    use indexmap::IndexMap;
+
+    /// Build the value that starts with `first` from the events
+    fn build(first: Event, events: &mut Events) -> Result<Value> {
+        Ok(match first {
+            Event::StartArray => {
+                let mut items = Vec::new();
+                loop {
+                    match events.next().unwrap()? {
+                        Event::EndArray => break Value::Array(items),
+                        event => items.push(build(event, events)?),
+                    }
+                }
+            }
+            Event::StartObject => {
+                let mut map = IndexMap::new();
+                loop {
+                    match events.next().unwrap()? {
+                        Event::EndObject => break Value::Object(map),
+                        Event::Key(key) => {
+                            let first = events.next().unwrap()?;
+                            map.insert(key.into_owned(), build(first, events)?);
+                        }
+                        event => panic!("unexpected {:?}", event),
+                    }
+                }
+            }
+            Event::Scalar(value) => value.into_owned(),
+            event => panic!("unexpected {:?}", event),
+        })
+    }
+
+    fn from_events(mut events: Events) -> Result<Value> {
+        let first = events.next().unwrap()?;
+        let value = build(first, &mut events)?;
+        match events.next() {
+            None => Ok(value),
+            Some(result) => result.map(|event| panic!("unexpected {:?}", event)),
+        }
+    }
+
+    fn elements(input: &str) -> Result<Vec<Value>> {
+        crate::array_elements(input).collect()
+    }
+
+    #[test]
+    fn test_events() {
+        let input = "{ $port 80 }\n[ { :name web\n  , :port $port\n  , :tags [a\n    , [b]\n    ]\n  }\n, :sym\n, [1, 2]\n, [\n  ]\n]";
+        let events: Vec<Event> = crate::events(input).collect::<Result<_>>().unwrap();
+        assert_eq!(events[0], Event::StartArray);
+        assert_eq!(events[1], Event::StartObject);
+        assert_eq!(events[events.len() - 3], Event::StartArray);
+        assert_eq!(events[events.len() - 1], Event::EndArray);
+        assert!(events.contains(&Event::Scalar(BorrowedValue::Symbol(Cow::Borrowed("sym")))));
+
+        for input in [
+            input,
+            "{ :a 1\n, :b { :c [x, y] }\n, :b { :d+ [z] }\n}",
+            "{ $list [1\n, 2]\n}\n[ $list\n, [$list]\n]",
+            "{ :ignored 1 }\n{ $x 1 }\n{ :used $x }",
+            "{ $x 1 }",
+            "42",
+            "[]",
+        ] {
+            assert_eq!(from_events(crate::events(input)).unwrap(), parse(input).unwrap(), "{}", input);
+        }
+
+        assert_eq!(from_events(crate::events("[1\n, 2 3\n]")).unwrap_err().code(), ErrorCode::MissingSeparator);
+        assert_eq!(from_events(crate::events("[1\n, [2")).unwrap_err().code(), ErrorCode::UnclosedArray);
+        assert_eq!(from_events(crate::events("")).unwrap_err().code(), ErrorCode::EmptyDocument);
+
+        let mut events = crate::events("[1\n, $missing\n]");
+        assert!(events.by_ref().take(2).all(|event| event.is_ok()));
+        assert!(matches!(events.next(), Some(Err(SymError::UndefinedVariable { .. }))));
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn test_array_elements() {
+        let input = "// fixtures\n{ $base { :kind :Pod }\n, $ns default\n}\n[ { :name a\n  , :meta $base\n  }\n, { :name b\n  , :ns $ns\n  }\n, [1, 2]\n]\n// end\n";
+        let items = elements(input).unwrap();
+        assert_eq!(Value::Array(items), parse(input).unwrap());
+
+        // Elements are read lazily, so an error ends the iteration where it occurs
+        let mut iter = crate::array_elements("[1\n, 2\n, }\n, 4\n]");
+        assert_eq!(iter.next().unwrap().unwrap(), Value::Int(1));
+        assert_eq!(iter.next().unwrap().unwrap(), Value::Int(2));
+        assert_eq!(iter.next().unwrap().unwrap_err().code(), ErrorCode::UnexpectedClose);
+        assert!(iter.next().is_none());
+
+        let err = elements("[1\n, 2\n]\n{ :more data }").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Stream);
+        let err = elements("{ :not an-array }").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Stream);
+        assert_eq!(err.location().unwrap().line, 1);
+
+        // Imported data is merged first, then read as a whole
+        let resolver = crate::MemoryResolver::new()
+            .with_file("base.sym", "{ $x 1 }\n[0]");
+        let text = "@import base.sym\n[$x]";
+        let mut parser = Parser::for_file(text, std::path::Path::new("main.sym"));
+        parser.set_resolver(&resolver);
+        let items: Vec<Value> = ArrayElements::new(parser).collect::<Result<_>>().unwrap();
+        assert_eq!(items, [Value::Int(0), Value::Int(1)]);
+
+        // Limits apply to the array as it is read
+        let options = ParseOptions::new().limits(crate::Limits { max_array_length: 2, max_depth: 2, ..Default::default() });
+        let limited = |input: &str| {
+            let mut parser = Parser::new(input);
+            parser.set_options(options.clone());
+            match ArrayElements::new(parser).collect::<Result<Vec<_>>>() {
+                Err(SymError::LimitExceeded { limit, .. }) => limit,
+                other => panic!("{:?}", other),
+            }
+        };
+        assert_eq!(limited("[1\n, 2\n, 3\n]"), Limit::ArrayLength);
+        assert_eq!(limited("[[[1]]]"), Limit::Depth);
+        assert_eq!(limited("{ $deep [[1]] }\n[$deep]"), Limit::Depth);
+    }
+}
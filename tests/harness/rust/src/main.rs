@@ -276,6 +276,22 @@ fn run_error_test(test_case: &TestCase, input: &str) -> TestResult {
             message: "Expected parser error, but parsing succeeded".to_string(),
         },
         Err(e) => {
+            // Check the error category, if error.json names one
+            if let Some(expected_type) = error_spec.get("type").and_then(|t| t.as_str()) {
+                if sym_parser::ErrorKind::from_name(expected_type) != Some(e.kind()) {
+                    return TestResult {
+                        name: test_case.name.clone(),
+                        passed: false,
+                        message: format!(
+                            "Error type mismatch:\n  Expected: {}\n  Got: {} ({})",
+                            expected_type,
+                            e.kind(),
+                            e
+                        ),
+                    };
+                }
+            }
+
             // Check if error matches expected pattern
             let error_message = e.to_string();
 